@@ -17,7 +17,6 @@ var cubeStrip = [
     0, 0, 0
 ];
 
-var takeScreenShot = false;
 var canvas = null;
 
 var gl = null;
@@ -0,0 +1,348 @@
+use cgmath::{InnerSpace, Quaternion, Vector3};
+
+use crate::camera::CameraPose;
+use crate::clipping::{ClipPlane, MAX_CLIP_PLANES};
+use crate::transfer_function::TransferFunction;
+use crate::zip::ZipWriter;
+use crate::Error;
+
+/// Keyframes closer together than this, in seconds, are taken as the same
+const SAME_TIME: f32 = 1e-3;
+
+/// Everything a keyframe sets, and so everything animated between them
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationState {
+    pub pose: CameraPose,
+    pub transfer_function: TransferFunction,
+    pub clip_planes: [ClipPlane; MAX_CLIP_PLANES],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    /// Seconds from the start of the animation
+    pub time: f32,
+    pub state: AnimationState,
+}
+
+/// Keyframes in time order, interpolated with Catmull-Rom splines
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    keyframes: Vec<Keyframe>,
+}
+
+impl Timeline {
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Adds `keyframe`, replacing any keyframe at the same time
+    pub fn add(&mut self, keyframe: Keyframe) {
+        match self
+            .keyframes
+            .iter()
+            .position(|existing| (existing.time - keyframe.time).abs() < SAME_TIME)
+        {
+            Some(index) => self.keyframes[index] = keyframe,
+            None => {
+                let index = self
+                    .keyframes
+                    .partition_point(|existing| existing.time < keyframe.time);
+                self.keyframes.insert(index, keyframe);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.keyframes.len() {
+            self.keyframes.remove(index);
+        }
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|last| last.time).unwrap_or(0.0)
+    }
+
+    /// Times of the frames of the animation at `fps`, last keyframe included
+    pub fn frame_times(&self, fps: f32) -> Vec<f32> {
+        if self.keyframes.is_empty() || fps <= 0.0 {
+            return Vec::new();
+        }
+        let start = self.keyframes[0].time;
+        let count = ((self.duration() - start) * fps).round() as usize + 1;
+        (0..count).map(|frame| start + frame as f32 / fps).collect()
+    }
+
+    /// State at `time`, held at the first and last keyframes outside them
+    pub fn sample(&self, time: f32) -> Option<AnimationState> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 {
+            return Some(self.keyframes[0].state.clone());
+        }
+        if next > last {
+            return Some(self.keyframes[last].state.clone());
+        }
+        let (i1, i2) = (next - 1, next);
+        let state = |index: Option<usize>| {
+            index
+                .and_then(|index| self.keyframes.get(index))
+                .map(|keyframe| &keyframe.state)
+        };
+        let (t1, t2) = (self.keyframes[i1].time, self.keyframes[i2].time);
+        let t = (time - t1) / (t2 - t1);
+        Some(interpolate(
+            state(i1.checked_sub(1)),
+            &self.keyframes[i1].state,
+            &self.keyframes[i2].state,
+            state(Some(i2 + 1)),
+            t,
+        ))
+    }
+}
+
+/// How the frames of an animation are rendered for export
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExportSettings {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f32,
+    /// Jittered frames averaged into each exported frame
+    pub samples: u32,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            fps: 30.0,
+            samples: 16,
+        }
+    }
+}
+
+/// An export in progress, rendering one frame at a time into a ZIP archive
+/// of PNGs
+pub struct FrameExport {
+    pub settings: ExportSettings,
+    times: Vec<f32>,
+    next: usize,
+    archive: ZipWriter,
+    /// State and canvas `[width, height]` to go back to once done
+    pub restore: (AnimationState, [f32; 2]),
+}
+
+impl FrameExport {
+    pub fn new(
+        settings: ExportSettings,
+        times: Vec<f32>,
+        restore: (AnimationState, [f32; 2]),
+    ) -> Self {
+        Self {
+            settings,
+            times,
+            next: 0,
+            archive: ZipWriter::new(),
+            restore,
+        }
+    }
+
+    /// Time of the next frame to render, if any are left
+    pub fn next_time(&self) -> Option<f32> {
+        self.times.get(self.next).copied()
+    }
+
+    pub fn add_frame(&mut self, png: &[u8]) -> Result<(), Error> {
+        self.archive
+            .add_file(&format!("frame_{:05}.png", self.next), png)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.times.len()
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, Error> {
+        self.archive.finish()
+    }
+}
+
+/// Catmull-Rom spline through `p1` at `t = 0` and `p2` at `t = 1`
+pub fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// Catmull-Rom spline between `p1` and `p2`, where the outer points missing
+/// at the ends of the timeline are mirrored from the inner ones
+fn spline_between(p0: Option<f32>, p1: f32, p2: f32, p3: Option<f32>, t: f32) -> f32 {
+    let p0 = p0.unwrap_or(2.0 * p1 - p2);
+    let p3 = p3.unwrap_or(2.0 * p2 - p1);
+    catmull_rom(p0, p1, p2, p3, t)
+}
+
+/// Flips each rotation into the same hemisphere as `reference` so the
+/// camera turns the short way
+fn aligned(q: Quaternion<f32>, reference: Quaternion<f32>) -> Quaternion<f32> {
+    match q.dot(reference) < 0.0 {
+        true => -q,
+        false => q,
+    }
+}
+
+fn interpolate(
+    k0: Option<&AnimationState>,
+    k1: &AnimationState,
+    k2: &AnimationState,
+    k3: Option<&AnimationState>,
+    t: f32,
+) -> AnimationState {
+    let spline =
+        |f: &dyn Fn(&AnimationState) -> f32| spline_between(k0.map(f), f(k1), f(k2), k3.map(f), t);
+    let rotation = |state: &AnimationState| aligned(state.pose.rotation, k1.pose.rotation);
+
+    let pose = CameraPose {
+        center: Vector3::new(
+            spline(&|state| state.pose.center.x),
+            spline(&|state| state.pose.center.y),
+            spline(&|state| state.pose.center.z),
+        ),
+        // Spline through the components, then back onto the unit sphere
+        rotation: Quaternion::new(
+            spline(&|state| rotation(state).s),
+            spline(&|state| rotation(state).v.x),
+            spline(&|state| rotation(state).v.y),
+            spline(&|state| rotation(state).v.z),
+        )
+        .normalize(),
+        distance: spline(&|state| state.pose.distance).max(1e-3),
+    };
+
+    // Colours blend linearly, as a spline could overshoot them
+    let (from, to) = (&k1.transfer_function, &k2.transfer_function);
+    let colors = match from.colors.len() == to.colors.len() {
+        true => from
+            .colors
+            .iter()
+            .zip(&to.colors)
+            .map(|(a, b)| {
+                [0, 1, 2].map(|c| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * t).round() as u8)
+            })
+            .collect(),
+        false => from.colors.clone(),
+    };
+    let transfer_function = TransferFunction {
+        colors,
+        window: (
+            spline(&|state| state.transfer_function.window.0),
+            spline(&|state| state.transfer_function.window.1),
+        ),
+        max_opacity: spline(&|state| state.transfer_function.max_opacity).clamp(0.0, 1.0),
+    };
+
+    let clip_planes = std::array::from_fn(|index| {
+        let plane = |state: &AnimationState| state.clip_planes[index];
+        // Switches take effect at the keyframe that sets them
+        ClipPlane {
+            azimuth: spline(&|state| plane(state).azimuth),
+            elevation: spline(&|state| plane(state).elevation),
+            offset: spline(&|state| plane(state).offset),
+            ..plane(k1)
+        }
+    });
+
+    AnimationState {
+        pose,
+        transfer_function,
+        clip_planes,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{catmull_rom, AnimationState, Keyframe, Timeline};
+    use crate::camera::CameraPose;
+    use crate::clipping::{ClipPlane, MAX_CLIP_PLANES};
+    use crate::transfer_function::TransferFunction;
+    use cgmath::{assert_abs_diff_eq, Deg, InnerSpace, Quaternion, Rotation3, Vector3};
+
+    fn keyframe(time: f32, x: f32, angle: f32, clip_enabled: bool) -> Keyframe {
+        let mut clip_planes = [ClipPlane::default(); MAX_CLIP_PLANES];
+        clip_planes[0].enabled = clip_enabled;
+        clip_planes[0].offset = x;
+        Keyframe {
+            time,
+            state: AnimationState {
+                pose: CameraPose {
+                    center: Vector3::new(x, 0.5, 0.5),
+                    rotation: Quaternion::from_angle_y(Deg(angle)),
+                    distance: 2.0,
+                },
+                transfer_function: TransferFunction {
+                    window: (x * 100.0, 255.0),
+                    ..TransferFunction::default()
+                },
+                clip_planes,
+            },
+        }
+    }
+
+    #[test]
+    fn test_catmull_rom() {
+        assert_eq!(catmull_rom(0.0, 1.0, 2.0, 3.0, 0.0), 1.0);
+        assert_eq!(catmull_rom(0.0, 1.0, 2.0, 3.0, 1.0), 2.0);
+        // Evenly spaced points give linear motion
+        assert_abs_diff_eq!(catmull_rom(0.0, 1.0, 2.0, 3.0, 0.25), 1.25);
+    }
+
+    #[test]
+    fn test_keyframes_stay_sorted() {
+        let mut timeline = Timeline::default();
+        timeline.add(keyframe(2.0, 1.0, 0.0, false));
+        timeline.add(keyframe(0.0, 0.0, 0.0, false));
+        timeline.add(keyframe(1.0, 0.5, 0.0, false));
+        timeline.add(keyframe(1.0, 0.7, 0.0, false));
+        let times: Vec<f32> = timeline.keyframes().iter().map(|k| k.time).collect();
+        assert_eq!(times, [0.0, 1.0, 2.0]);
+        assert_eq!(timeline.keyframes()[1].state.pose.center.x, 0.7);
+        assert_eq!(timeline.frame_times(2.0), [0.0, 0.5, 1.0, 1.5, 2.0]);
+    }
+
+    #[test]
+    fn test_sample_passes_through_keyframes() {
+        let mut timeline = Timeline::default();
+        assert!(timeline.sample(0.0).is_none());
+        timeline.add(keyframe(0.0, 0.0, 0.0, false));
+        timeline.add(keyframe(1.0, 1.0, 80.0, true));
+        timeline.add(keyframe(2.0, 2.0, 160.0, false));
+
+        let at_key = timeline.sample(1.0).unwrap();
+        assert_abs_diff_eq!(at_key.pose.center.x, 1.0, epsilon = 1e-5);
+        assert!(at_key.clip_planes[0].enabled);
+
+        let between = timeline.sample(0.5).unwrap();
+        assert_abs_diff_eq!(between.pose.center.x, 0.5, epsilon = 1e-5);
+        assert_abs_diff_eq!(between.transfer_function.window.0, 50.0, epsilon = 1e-3);
+        assert_abs_diff_eq!(between.clip_planes[0].offset, 0.5, epsilon = 1e-5);
+        assert!(!between.clip_planes[0].enabled);
+        let expected = Quaternion::from_angle_y(Deg(40.0));
+        // Close to halfway, though a spline doesn't turn at constant speed
+        assert_abs_diff_eq!(
+            between.pose.rotation.dot(expected).abs(),
+            1.0,
+            epsilon = 1e-3
+        );
+
+        // Held outside the keyframes
+        assert_eq!(timeline.sample(5.0).unwrap().pose.center.x, 2.0);
+        assert_eq!(timeline.sample(-1.0).unwrap().pose.center.x, 0.0);
+    }
+}
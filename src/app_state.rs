@@ -1,12 +1,43 @@
+use crate::animation::{AnimationState, ExportSettings, FrameExport, Keyframe, Timeline};
+use crate::camera::{
+    Camera, CameraBookmark, CameraTransition, Turntable, TurntableAxis, ViewPreset,
+};
+use crate::capture::{CaptureCamera, CaptureSettings};
+use crate::clipping::{ClipPlane, CropBox, CropHandle, MAX_CLIP_PLANES};
+use crate::fusion::{Fusion, FusionMode, FusionTransform, SecondaryVolume};
+use crate::image::PngText;
+use crate::keyboard::{KeyAction, KeyBindings};
+use crate::labels::{LabelEntry, LabelMap, LabelOverlay};
+use crate::mesh::{Mesh, SceneMesh};
+use crate::pointers::Pointers;
+use crate::projection::{Projection, ProjectionMode};
+use crate::reslice::{ObliquePlane, ResliceImage, SlabMode};
+use crate::sampling::AdaptiveSampling;
+use crate::slices::{SliceCursor, SliceData, SliceOrientation};
+use crate::transfer_function::TransferFunction;
+use crate::util::spherical_direction;
+use crate::volume::Volume;
+use crate::zip::MAX_ENTRIES;
 use crate::{CanvasDims, Error, SharedMut};
 
 use anyhow::{Context, Result};
-use arcball::ArcballCamera;
-use cgmath::{Matrix4, Vector2, Vector3};
+use cgmath::{InnerSpace, Matrix4, One, Quaternion, Vector2, Vector3};
 use lazy_static::lazy_static;
+use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
+use wasm_timer::Instant;
 
-const CENTER: Vector3<f32> = Vector3::new(0.5, 0.5, 0.5);
+pub(crate) const CENTER: Vector3<f32> = Vector3::new(0.5, 0.5, 0.5);
+/// Radius of the sphere around the unit volume box
+const VOLUME_RADIUS: f32 = 0.866_025_4;
+const TARGET_FRAME_TIME: Duration = Duration::from_millis(33);
+/// Degrees turned by one press of a rotate key
+const KEY_ROTATE_STEP: f32 = 5.0;
+/// Fraction of the canvas moved by one press of a pan key
+const KEY_PAN_STEP: f32 = 0.05;
+/// Distance moved towards or away from the centre by one press
+const KEY_DOLLY_STEP: f32 = 0.1;
 
 pub fn update_dynamic_data(
     app_state: SharedMut<AppState>,
@@ -18,49 +49,236 @@ pub fn update_dynamic_data(
     let display_size = 0.9 * min_height_width;
 
     let mut data = app_state.lock().unwrap();
-    data.update_canvas(canvas_height, canvas_width);
+    data.update_canvas(canvas_width, canvas_height);
 }
 
 pub enum MouseButton {
     Left,
+    Middle,
     Right,
 }
 
+impl MouseButton {
+    /// From the `button` of a DOM mouse or pointer event
+    pub fn from_button(button: i16) -> Option<Self> {
+        match button {
+            0 => Some(Self::Left),
+            1 => Some(Self::Middle),
+            2 => Some(Self::Right),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Emission-absorption compositing through the colormap
+    Dvr,
+    /// First hit of the ray with the `iso_value` level set
+    Isosurface,
+}
+
+/// The light used to shade surfaces. Unless `headlight` is set the light sits
+/// on a sphere around the volume at the given angles, in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub headlight: bool,
+    pub azimuth: f32,
+    pub elevation: f32,
+}
+
+impl Light {
+    /// Direction the light travels in, in volume space
+    pub fn direction(&self) -> Vector3<f32> {
+        -spherical_direction(self.azimuth, self.elevation)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GradientSource {
+    /// Central differences of the volume texture in the shader
+    OnTheFly,
+    /// Sampled from a gradient texture built when the volume is loaded
+    Precomputed,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub mode: RenderMode,
+    /// Threshold for the isosurface in raw data units, i.e. `0.0..=255.0`
+    pub iso_value: f32,
+    pub light: Light,
+    /// Phong shading of each sample during direct volume rendering
+    pub dvr_shading: bool,
+    pub gradient_source: GradientSource,
+    /// Samples per voxel along a ray, the step size is its inverse
+    pub sampling_rate: f32,
+    /// Accumulated opacity at which rays stop marching
+    pub ert_threshold: f32,
+    /// Jump over macrocells the transfer function makes fully transparent
+    pub skip_empty_space: bool,
+    pub crop_box: CropBox,
+    pub clip_planes: [ClipPlane; MAX_CLIP_PLANES],
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            mode: RenderMode::Dvr,
+            iso_value: 100.0,
+            light: Light {
+                headlight: true,
+                azimuth: 45.0,
+                elevation: 30.0,
+            },
+            dvr_shading: false,
+            gradient_source: GradientSource::Precomputed,
+            sampling_rate: 1.0,
+            ert_threshold: 0.95,
+            skip_empty_space: true,
+            crop_box: CropBox::default(),
+            clip_planes: [ClipPlane::default(); MAX_CLIP_PLANES],
+        }
+    }
+}
+
 pub struct AppState {
+    /// Size of the canvas on the page in CSS pixels, which pointer positions
+    /// are given in
     pub canvas_height: f32,
     pub canvas_width: f32,
+    /// Device pixels per CSS pixel
+    pixel_ratio: f32,
+    render_scale: f32,
     mouse_prev: Vector2<f32>,
     pub mouse_button: Option<MouseButton>,
-    arcball: ArcballCamera<f32>,
+    /// Pointers pressed on the 3D view
+    pointers: Pointers,
+    key_bindings: KeyBindings,
+    /// Set by the screenshot key until the renderer takes the screenshot
+    screenshot_requested: bool,
+    /// High resolution capture waiting for the renderer
+    capture_request: Option<CaptureSettings>,
+    arcball: Camera,
     arcball_changed: bool,
+    /// Move to a bookmark in progress
+    camera_transition: Option<CameraTransition>,
+    transition_duration: Duration,
+    bookmarks: Vec<CameraBookmark>,
+    turntable: Turntable,
+    timeline: Timeline,
+    frame_export: Option<FrameExport>,
+    projection: Projection,
+    render_settings: RenderSettings,
+    adaptive_sampling: AdaptiveSampling,
+    transfer_function: TransferFunction,
+    /// Bumped on every change to `transfer_function` so renderers know to
+    /// re-upload it
+    transfer_function_generation: u64,
+    /// Whether the crop box handles are shown and can be dragged
+    crop_editing: bool,
+    dragged_handle: Option<CropHandle>,
+    slice_cursor: SliceCursor,
+    /// Slice view the cursor is being dragged in
+    slice_dragged: Option<SliceOrientation>,
+    /// Pointer position of the last drag event in the dragged slice view
+    slice_drag_uv: [f32; 2],
+    oblique_plane: ObliquePlane,
+    /// Whether the slices are drawn as planes in the 3D view
+    show_slice_planes: bool,
+    slices_changed: bool,
+    volume: Option<Arc<Volume>>,
+    /// Name of the loaded volume, recorded in captures
+    dataset: String,
+    meshes: Vec<SceneMesh>,
+    /// Bumped whenever `meshes` changes so the renderer re-uploads them
+    meshes_generation: u64,
+    labels: LabelOverlay,
+    /// Bumped on every change to `labels`
+    labels_generation: u64,
+    fusion: Fusion,
+    /// Bumped on every change to `fusion`
+    fusion_generation: u64,
     pub density_data: Vec<u8>,
 }
 
 impl AppState {
     pub fn new() -> Self {
-        let mut arcball = ArcballCamera::<f32>::new(CENTER, 1.0, [400.0, 600.0]);
-        arcball.zoom(-1.0, 1.0);
+        let projection = Projection::default();
+        let distance = projection.fit_distance(VOLUME_RADIUS, 1.0);
+        let arcball = Camera::new(CENTER, distance, 1.0, [800.0, 800.0]);
         Self {
-            canvas_height: 400.,
-            canvas_width: 600.,
+            canvas_height: 800.,
+            canvas_width: 800.,
+            pixel_ratio: 1.0,
+            render_scale: 1.0,
             mouse_prev: Vector2::new(0.0, 0.0),
             mouse_button: None,
+            pointers: Pointers::default(),
+            key_bindings: KeyBindings::default(),
+            screenshot_requested: false,
+            capture_request: None,
             arcball,
             arcball_changed: false,
+            camera_transition: None,
+            transition_duration: Duration::from_secs(1),
+            bookmarks: Vec::new(),
+            turntable: Turntable::default(),
+            timeline: Timeline::default(),
+            frame_export: None,
+            projection,
+            render_settings: RenderSettings::default(),
+            adaptive_sampling: AdaptiveSampling::new(TARGET_FRAME_TIME),
+            transfer_function: TransferFunction::default(),
+            transfer_function_generation: 0,
+            crop_editing: false,
+            dragged_handle: None,
+            slice_cursor: SliceCursor::default(),
+            slice_dragged: None,
+            slice_drag_uv: [0.0, 0.0],
+            oblique_plane: ObliquePlane::default(),
+            show_slice_planes: true,
+            slices_changed: true,
+            volume: None,
+            dataset: String::new(),
+            meshes: Vec::new(),
+            meshes_generation: 0,
+            labels: LabelOverlay::default(),
+            labels_generation: 0,
+            fusion: Fusion::default(),
+            fusion_generation: 0,
             density_data: Vec::new(),
         }
     }
 
-    pub fn update_canvas(&mut self, canvas_height: f32, canvas_width: f32) {
-        self.canvas_height = canvas_height;
-        self.canvas_width = canvas_width;
-        self.arcball.update_screen(canvas_width, canvas_height)
+    pub fn update_canvas(&mut self, canvas_width: f32, canvas_height: f32) {
+        self.canvas_width = canvas_width.max(1.0);
+        self.canvas_height = canvas_height.max(1.0);
+        self.arcball
+            .update_screen(self.canvas_width, self.canvas_height)
+    }
+
+    /// The canvas was laid out at a new size, in CSS pixels, or moved to a
+    /// screen with a different `pixel_ratio` of device to CSS pixels
+    pub fn resize_canvas(&mut self, canvas_width: f32, canvas_height: f32, pixel_ratio: f32) {
+        self.update_canvas(canvas_width, canvas_height);
+        self.pixel_ratio = pixel_ratio.max(0.1);
+        self.arcball_changed = true;
+    }
+
+    /// Renders at `render_scale` times the device resolution, trading
+    /// sharpness for speed below 1
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        self.render_scale = render_scale.clamp(0.1, 2.0);
+        self.arcball_changed = true;
     }
 
+    /// Size of the drawing buffer, in device pixels scaled by the render scale
     pub fn get_canvas_dims(&self) -> CanvasDims {
+        let scale = self.pixel_ratio * self.render_scale;
         CanvasDims {
-            width: self.canvas_width,
-            height: self.canvas_height,
+            width: (self.canvas_width * scale).round().max(1.0),
+            height: (self.canvas_height * scale).round().max(1.0),
         }
     }
 
@@ -69,6 +287,14 @@ impl AppState {
         mouse_new: Vector2<f32>,
         mouse_button: Option<MouseButton>,
     ) {
+        self.dragged_handle = match (&mouse_button, self.crop_editing) {
+            (Some(MouseButton::Left), true) => self.render_settings.crop_box.pick_handle(
+                &self.proj_view(),
+                [self.canvas_width, self.canvas_height],
+                mouse_new,
+            ),
+            _ => None,
+        };
         self.mouse_button = mouse_button;
         self.mouse_prev = mouse_new;
     }
@@ -78,31 +304,836 @@ impl AppState {
     }
 
     pub fn update_mouse_pos(&mut self, mouse_new: Vector2<f32>) {
-        if let Some(mouse_button) = &self.mouse_button {
+        if let Some(handle) = self.dragged_handle {
+            let proj_view = self.proj_view();
+            self.render_settings.crop_box.drag_handle(
+                handle,
+                &proj_view,
+                [self.canvas_width, self.canvas_height],
+                mouse_new - self.mouse_prev,
+            );
+            self.set_arcball_changed(true);
+        } else if let Some(mouse_button) = &self.mouse_button {
+            self.camera_transition = None;
             match mouse_button {
                 MouseButton::Left => self.arcball.rotate(self.mouse_prev, mouse_new),
-                MouseButton::Right => self.arcball.pan(mouse_new - self.mouse_prev),
+                MouseButton::Middle | MouseButton::Right => {
+                    self.arcball.pan(mouse_new - self.mouse_prev)
+                }
             }
             self.set_arcball_changed(true);
         };
         self.mouse_prev = mouse_new
     }
 
+    /// A pointer went down on the 3D view. One pointer drags as the mouse
+    /// does, touches rotating; a second one starts a pan and pinch gesture.
+    pub fn pointer_down(&mut self, id: i32, position: Vector2<f32>, button: Option<MouseButton>) {
+        self.pointers.press(id, position);
+        match self.pointers.len() {
+            1 => self.update_mouse_down(position, button),
+            2 => self.update_mouse_down(position, None),
+            _ => {}
+        }
+    }
+
+    pub fn pointer_move(&mut self, id: i32, position: Vector2<f32>) {
+        if self.pointers.len() < 2 {
+            self.pointers.update(id, position);
+            self.update_mouse_pos(position);
+        } else if let Some(motion) = self.pointers.update(id, position) {
+            self.camera_transition = None;
+            self.arcball.pan(motion.pan);
+            self.arcball
+                .zoom(2.0 * motion.spread / self.canvas_height, 1.0);
+            self.arcball_changed = true;
+        }
+    }
+
+    /// A pointer was lifted or cancelled. Lifting one finger of a pinch goes
+    /// back to rotating with the other.
+    pub fn pointer_up(&mut self, id: i32, position: Vector2<f32>) {
+        let pressed = self.pointers.len();
+        self.pointers.release(id);
+        match (pressed, self.pointers.first()) {
+            (_, None) => self.update_mouse_down(position, None),
+            (2, Some(remaining)) => self.update_mouse_down(remaining, Some(MouseButton::Left)),
+            _ => {}
+        }
+    }
+
+    pub fn get_key_bindings(&self) -> KeyBindings {
+        self.key_bindings.clone()
+    }
+
+    pub fn bind_key(&mut self, action: KeyAction, key: Option<String>) {
+        self.key_bindings.bind(action, key);
+    }
+
+    /// Does whatever `key` is bound to, returning whether it was bound
+    pub fn key_down(&mut self, key: &str) -> bool {
+        let Some(action) = self.key_bindings.action(key) else {
+            return false;
+        };
+        let pan = |x: f32, y: f32| Vector2::new(x, y) * KEY_PAN_STEP;
+        match action {
+            KeyAction::RotateLeft => self
+                .arcball
+                .orbit(TurntableAxis::ScreenUp, -KEY_ROTATE_STEP),
+            KeyAction::RotateRight => self.arcball.orbit(TurntableAxis::ScreenUp, KEY_ROTATE_STEP),
+            KeyAction::RotateUp => self
+                .arcball
+                .orbit(TurntableAxis::ScreenRight, KEY_ROTATE_STEP),
+            KeyAction::RotateDown => self
+                .arcball
+                .orbit(TurntableAxis::ScreenRight, -KEY_ROTATE_STEP),
+            // Pans are given as the drag that would move the view that way
+            KeyAction::PanLeft => self.arcball.pan(pan(self.canvas_width, 0.0)),
+            KeyAction::PanRight => self.arcball.pan(pan(-self.canvas_width, 0.0)),
+            KeyAction::PanUp => self.arcball.pan(pan(0.0, self.canvas_height)),
+            KeyAction::PanDown => self.arcball.pan(pan(0.0, -self.canvas_height)),
+            KeyAction::DollyIn => self.arcball.zoom(KEY_DOLLY_STEP, 1.0),
+            KeyAction::DollyOut => self.arcball.zoom(-KEY_DOLLY_STEP, 1.0),
+            KeyAction::ResetView => {
+                let mut pose = self.arcball.pose();
+                pose.rotation = Quaternion::one();
+                self.arcball.set_pose(pose);
+                self.fit_volume();
+            }
+            KeyAction::ViewPreset(preset) => self.set_view_preset(preset),
+            // These leave the camera where it is
+            KeyAction::CycleRenderMode => {
+                let mode = match self.render_settings.mode {
+                    RenderMode::Dvr => RenderMode::Isosurface,
+                    RenderMode::Isosurface => RenderMode::Dvr,
+                };
+                self.set_render_mode(mode);
+                return true;
+            }
+            KeyAction::Screenshot => {
+                self.screenshot_requested = true;
+                return true;
+            }
+        }
+        self.camera_transition = None;
+        self.arcball_changed = true;
+        true
+    }
+
+    /// Whether a screenshot was asked for and the view drawn since is
+    /// `converged`, in which case the request is cleared. Until then the
+    /// view keeps refining as usual.
+    pub fn take_screenshot_request(&mut self, converged: bool) -> bool {
+        let ready = self.screenshot_requested && converged && !self.arcball_changed;
+        if ready {
+            self.screenshot_requested = false;
+        }
+        ready
+    }
+
+    pub fn request_capture(&mut self, settings: CaptureSettings) {
+        self.capture_request = Some(settings);
+    }
+
+    /// The capture asked for, if any. Capturing reuses the accumulation
+    /// buffer, so the view is redrawn afterwards.
+    pub fn take_capture_request(&mut self) -> Option<CaptureSettings> {
+        let settings = self.capture_request.take()?;
+        self.arcball_changed = true;
+        Some(settings)
+    }
+
+    pub fn capture_camera(&self) -> CaptureCamera {
+        CaptureCamera {
+            pose: self.arcball.pose(),
+            projection: self.projection.mode,
+            fov: self.projection.fov,
+            near: self.projection.near,
+            far: self.projection.far,
+        }
+    }
+
+    /// PNG text chunks describing what a capture shows
+    pub fn capture_metadata(&self) -> PngText {
+        let dims = self.volume_dims();
+        let camera = serde_json::to_string(&self.capture_camera()).unwrap_or_default();
+        let transfer_function = serde_json::to_string(&self.transfer_function).unwrap_or_default();
+        vec![
+            (
+                "Dataset".to_string(),
+                format!("{} {}x{}x{}", self.dataset, dims[0], dims[1], dims[2]),
+            ),
+            ("Camera".to_string(), camera),
+            ("Transfer function".to_string(), transfer_function),
+            ("Software".to_string(), "volumetric-renderer".to_string()),
+        ]
+    }
+
     pub fn scroll_to_zoom(&mut self, scroll_delta: f32) {
         let adjusted_scroll = scroll_delta / self.canvas_height;
+        self.camera_transition = None;
         self.arcball.zoom(adjusted_scroll, 1.0);
         self.arcball_changed = true;
     }
 
     pub fn get_arcball_data(&self) -> DrawData {
-        let proj_view = self.arcball.get_mat4();
+        let proj_view = self.proj_view();
         let eye_pos = self.arcball.eye_pos();
-        DrawData { proj_view, eye_pos }
+        let crop_handles = match self.crop_editing {
+            true => Some((self.render_settings.crop_box, self.dragged_handle)),
+            false => None,
+        };
+        let slice_planes = match self.show_slice_planes {
+            true => Some(self.slice_cursor),
+            false => None,
+        };
+        DrawData {
+            proj_view,
+            eye_pos,
+            view_dir: self.view_dir(),
+            orthographic: self.projection.mode == ProjectionMode::Orthographic,
+            crop_handles,
+            slice_planes,
+        }
+    }
+
+    pub fn projection(&self) -> Matrix4<f32> {
+        self.projection_for(self.canvas_width / self.canvas_height)
+    }
+
+    fn projection_for(&self, aspect: f32) -> Matrix4<f32> {
+        let focus = self.arcball.get_mat4() * CENTER.extend(1.0);
+        self.projection.matrix(aspect, -focus.z)
+    }
+
+    /// Draw data for a `width` by `height` capture, without the crop handles
+    /// or slice plane outlines
+    pub fn get_capture_data(&self, width: u32, height: u32) -> DrawData {
+        let aspect = width.max(1) as f32 / height.max(1) as f32;
+        DrawData {
+            proj_view: self.projection_for(aspect) * self.arcball.get_mat4(),
+            crop_handles: None,
+            slice_planes: None,
+            ..self.get_arcball_data()
+        }
+    }
+
+    /// Direction the camera looks in, in volume space
+    pub fn view_dir(&self) -> Vector3<f32> {
+        let view = self.arcball.get_mat4();
+        -Vector3::new(view.x.z, view.y.z, view.z.z).normalize()
+    }
+
+    /// Frames the whole volume box for the current canvas, keeping the
+    /// direction it's viewed from
+    pub fn fit_volume(&mut self) {
+        let mut pose = self.arcball.pose();
+        pose.center = CENTER;
+        pose.distance = self
+            .projection
+            .fit_distance(VOLUME_RADIUS, self.canvas_width / self.canvas_height);
+        self.arcball.set_pose(pose);
+        self.arcball_changed = true;
+    }
+
+    /// Views the volume from `preset`, taking the volume's axes as LPS when
+    /// its orientation isn't known
+    pub fn set_view_preset(&mut self, preset: ViewPreset) {
+        let orientation = self
+            .volume
+            .as_ref()
+            .and_then(|volume| volume.orientation())
+            .unwrap_or_default();
+        let mut pose = self.arcball.pose();
+        pose.rotation = preset.rotation(&orientation);
+        self.arcball.set_pose(pose);
+        self.fit_volume();
+    }
+
+    pub fn get_bookmarks(&self) -> Vec<CameraBookmark> {
+        self.bookmarks.clone()
+    }
+
+    /// Replaces the bookmarks, such as with ones saved in an earlier session
+    pub fn set_bookmarks(&mut self, bookmarks: Vec<CameraBookmark>) {
+        self.bookmarks = bookmarks;
+    }
+
+    /// Bookmarks the current pose as `name`, replacing any bookmark with
+    /// the same name
+    pub fn add_bookmark(&mut self, name: String) {
+        let pose = self.arcball.pose();
+        match self
+            .bookmarks
+            .iter_mut()
+            .find(|bookmark| bookmark.name == name)
+        {
+            Some(bookmark) => bookmark.pose = pose,
+            None => self.bookmarks.push(CameraBookmark { name, pose }),
+        }
+    }
+
+    pub fn remove_bookmark(&mut self, name: &str) {
+        self.bookmarks.retain(|bookmark| bookmark.name != name);
+    }
+
+    /// Starts moving the camera to the bookmark called `name`
+    pub fn go_to_bookmark(&mut self, name: &str, now: Instant) -> Result<()> {
+        let bookmark = self
+            .bookmarks
+            .iter()
+            .find(|bookmark| bookmark.name == name)
+            .ok_or(Error::MissingItem)
+            .with_context(|| format!("No bookmark called {name}"))?;
+        self.camera_transition = Some(CameraTransition::new(
+            self.arcball.pose(),
+            bookmark.pose,
+            now,
+            self.transition_duration,
+        ));
+        self.arcball_changed = true;
+        Ok(())
+    }
+
+    pub fn set_transition_duration(&mut self, duration: Duration) {
+        self.transition_duration = duration;
+    }
+
+    pub fn set_turntable_enabled(&mut self, enabled: bool) {
+        self.turntable.enabled = enabled;
+    }
+
+    pub fn set_turntable_axis(&mut self, axis: TurntableAxis) {
+        self.turntable.axis = axis;
+    }
+
+    pub fn set_turntable_speed(&mut self, degrees_per_second: f32) {
+        self.turntable.speed = degrees_per_second;
+    }
+
+    /// Spins the camera unless the user or an animation is moving it
+    fn advance_turntable(&mut self, now: Instant) {
+        if let Some(degrees) = self.turntable.advance(now, self.turntable_paused()) {
+            self.arcball.orbit(self.turntable.axis, degrees);
+            self.arcball_changed = true;
+        }
+    }
+
+    /// Whether the user is dragging, pinching or otherwise moving the camera,
+    /// or an export is in progress, which holds the turntable still
+    fn turntable_paused(&self) -> bool {
+        self.mouse_button.is_some()
+            || !self.pointers.is_empty()
+            || self.dragged_handle.is_some()
+            || self.camera_transition.is_some()
+            || self.frame_export.is_some()
+    }
+
+    /// Moves the camera along the transition in progress, if any
+    fn advance_camera_transition(&mut self, now: Instant) {
+        if let Some(transition) = self.camera_transition {
+            let (pose, finished) = transition.pose_at(now);
+            self.arcball.set_pose(pose);
+            self.arcball_changed = true;
+            if finished {
+                self.camera_transition = None;
+            }
+        }
+    }
+
+    /// What a keyframe taken now would hold
+    pub fn animation_state(&self) -> AnimationState {
+        AnimationState {
+            pose: self.arcball.pose(),
+            transfer_function: self.transfer_function.clone(),
+            clip_planes: self.render_settings.clip_planes,
+        }
+    }
+
+    pub fn apply_animation_state(&mut self, state: AnimationState) {
+        self.arcball.set_pose(state.pose);
+        self.render_settings.clip_planes = state.clip_planes;
+        self.set_transfer_function(state.transfer_function);
+    }
+
+    /// Adds a keyframe of the current view at `time` seconds and returns the
+    /// times of all keyframes
+    pub fn add_keyframe(&mut self, time: f32) -> Vec<f32> {
+        let state = self.animation_state();
+        self.timeline.add(Keyframe { time, state });
+        self.keyframe_times()
+    }
+
+    pub fn remove_keyframe(&mut self, index: usize) -> Vec<f32> {
+        self.timeline.remove(index);
+        self.keyframe_times()
+    }
+
+    pub fn keyframe_times(&self) -> Vec<f32> {
+        self.timeline
+            .keyframes()
+            .iter()
+            .map(|keyframe| keyframe.time)
+            .collect()
+    }
+
+    /// Starts rendering the timeline frame by frame, returning the number of
+    /// frames
+    pub fn start_frame_export(&mut self, settings: ExportSettings) -> Result<()> {
+        if self.frame_export.is_some() {
+            return Err(Error::ExportInProgress.into());
+        }
+        let times = self.timeline.frame_times(settings.fps);
+        if times.is_empty() {
+            return Err(Error::MissingItem).context("Add keyframes before exporting");
+        }
+        if times.len() > MAX_ENTRIES {
+            return Err(Error::ArchiveTooLarge(format!("{} frames", times.len())))
+                .context("Lower the frame rate or shorten the timeline");
+        }
+        let restore = (
+            self.animation_state(),
+            [self.canvas_width, self.canvas_height],
+        );
+        self.frame_export = Some(FrameExport::new(settings, times, restore));
+        Ok(())
+    }
+
+    /// Sets up the next frame of the export in progress, returning how to
+    /// render it
+    pub fn next_export_frame(&mut self) -> Option<ExportSettings> {
+        let export = self.frame_export.as_ref()?;
+        let settings = export.settings;
+        let state = self.timeline.sample(export.next_time()?)?;
+        self.apply_animation_state(state);
+        self.update_canvas(settings.width as f32, settings.height as f32);
+        Some(settings)
+    }
+
+    /// Adds the rendered frame to the export. Once all frames are in,
+    /// restores the view and returns the ZIP archive of frames.
+    pub fn finish_export_frame(&mut self, png: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(export) = self.frame_export.as_mut() else {
+            return Ok(None);
+        };
+        export
+            .add_frame(png)
+            .context("Failed to add frame to export")?;
+        if !export.is_finished() {
+            return Ok(None);
+        }
+        let Some(export) = self.frame_export.take() else {
+            return Ok(None);
+        };
+        self.restore_after_export(&export);
+        Ok(Some(export.finish().context("Failed to finish export")?))
+    }
+
+    /// Stops the export in progress, dropping the frames rendered so far
+    pub fn cancel_frame_export(&mut self) {
+        if let Some(export) = self.frame_export.take() {
+            self.restore_after_export(&export);
+        }
+    }
+
+    fn restore_after_export(&mut self, export: &FrameExport) {
+        let (state, [width, height]) = export.restore.clone();
+        self.apply_animation_state(state);
+        self.update_canvas(width, height);
+    }
+
+    pub fn get_projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn update_projection(&mut self, update: impl FnOnce(&mut Projection)) {
+        update(&mut self.projection);
+        self.arcball_changed = true;
+    }
+
+    pub fn proj_view(&self) -> Matrix4<f32> {
+        self.projection() * self.arcball.get_mat4()
     }
 
     pub fn get_arcball_changed(&self) -> bool {
         self.arcball_changed
     }
+
+    pub fn get_render_settings(&self) -> RenderSettings {
+        self.render_settings.clone()
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render_settings.mode = mode;
+        self.arcball_changed = true;
+    }
+
+    pub fn set_iso_value(&mut self, iso_value: f32) {
+        self.render_settings.iso_value = iso_value.clamp(0.0, 255.0);
+        self.arcball_changed = true;
+    }
+
+    pub fn set_light(&mut self, light: Light) {
+        self.render_settings.light = light;
+        self.arcball_changed = true;
+    }
+
+    pub fn set_headlight(&mut self, headlight: bool) {
+        self.set_light(Light {
+            headlight,
+            ..self.render_settings.light
+        })
+    }
+
+    pub fn set_light_azimuth(&mut self, azimuth: f32) {
+        self.set_light(Light {
+            azimuth,
+            ..self.render_settings.light
+        })
+    }
+
+    pub fn set_light_elevation(&mut self, elevation: f32) {
+        self.set_light(Light {
+            elevation: elevation.clamp(-90.0, 90.0),
+            ..self.render_settings.light
+        })
+    }
+
+    pub fn set_dvr_shading(&mut self, dvr_shading: bool) {
+        self.render_settings.dvr_shading = dvr_shading;
+        self.arcball_changed = true;
+    }
+
+    pub fn set_gradient_source(&mut self, gradient_source: GradientSource) {
+        self.render_settings.gradient_source = gradient_source;
+        self.arcball_changed = true;
+    }
+
+    pub fn set_sampling_rate(&mut self, sampling_rate: f32) {
+        self.render_settings.sampling_rate = sampling_rate.max(0.01);
+        self.arcball_changed = true;
+    }
+
+    pub fn set_ert_threshold(&mut self, ert_threshold: f32) {
+        self.render_settings.ert_threshold = ert_threshold.clamp(0.0, 1.0);
+        self.arcball_changed = true;
+    }
+
+    pub fn set_skip_empty_space(&mut self, skip_empty_space: bool) {
+        self.render_settings.skip_empty_space = skip_empty_space;
+        self.arcball_changed = true;
+    }
+
+    pub fn set_crop_editing(&mut self, crop_editing: bool) {
+        self.crop_editing = crop_editing;
+        self.arcball_changed = true;
+    }
+
+    pub fn reset_crop_box(&mut self) {
+        self.render_settings.crop_box = CropBox::default();
+        self.arcball_changed = true;
+    }
+
+    /// Applies `update` to the clipping plane at `index`, if there is one
+    pub fn update_clip_plane(&mut self, index: usize, update: impl FnOnce(&mut ClipPlane)) {
+        if let Some(plane) = self.render_settings.clip_planes.get_mut(index) {
+            update(plane);
+            self.arcball_changed = true;
+        }
+    }
+
+    pub fn set_dataset(&mut self, name: &str) {
+        self.dataset = name.to_string();
+    }
+
+    /// Keeps the loaded volume for resampling on the CPU and for rebuilding
+    /// the GPU textures should the WebGL context be lost
+    pub fn set_volume(&mut self, volume: Arc<Volume>) {
+        self.volume = Some(volume);
+        self.slices_changed = true;
+    }
+
+    pub fn get_volume(&self) -> Option<Arc<Volume>> {
+        self.volume.clone()
+    }
+
+    fn volume_dims(&self) -> [usize; 3] {
+        self.volume
+            .as_ref()
+            .map(|volume| volume.dims())
+            .unwrap_or([1, 1, 1])
+    }
+
+    fn slice_cursor_changed(&mut self) {
+        self.slices_changed = true;
+        if self.show_slice_planes {
+            self.arcball_changed = true;
+        }
+    }
+
+    /// Starts or, with `None`, stops dragging in a slice view. Dragging moves
+    /// the cursor in the orthogonal views and turns the oblique plane.
+    pub fn update_slice_drag(&mut self, orientation: Option<SliceOrientation>, uv: [f32; 2]) {
+        self.slice_dragged = orientation;
+        self.slice_drag_uv = uv;
+        if let Some(SliceOrientation::Orthogonal(axis)) = orientation {
+            self.slice_cursor.point_at(axis, uv);
+            self.slice_cursor_changed();
+        }
+    }
+
+    pub fn update_slice_pointer(&mut self, orientation: SliceOrientation, uv: [f32; 2]) {
+        if self.slice_dragged != Some(orientation) {
+            return;
+        }
+        match orientation {
+            SliceOrientation::Orthogonal(axis) => {
+                self.slice_cursor.point_at(axis, uv);
+                self.slice_cursor_changed();
+            }
+            SliceOrientation::Oblique => {
+                let previous = self.slice_drag_uv;
+                // The views' v points up, rotation expects y to point down
+                let delta = Vector2::new(uv[0] - previous[0], previous[1] - uv[1]);
+                self.oblique_plane.rotate(delta);
+                self.slices_changed = true;
+            }
+        }
+        self.slice_drag_uv = uv;
+    }
+
+    /// Moves the slice shown in a view by `steps` voxels along its normal
+    pub fn step_slice(&mut self, orientation: SliceOrientation, steps: i32) {
+        let dims = self.volume_dims();
+        match orientation {
+            SliceOrientation::Orthogonal(axis) => self.slice_cursor.step_slice(axis, steps, dims),
+            SliceOrientation::Oblique => {
+                let [_, _, normal] = self.oblique_plane.axes();
+                let voxel_size = 1.0 / dims.iter().copied().max().unwrap_or(1) as f32;
+                self.slice_cursor
+                    .step_along(normal, steps as f32 * voxel_size);
+            }
+        }
+        self.slice_cursor_changed();
+    }
+
+    pub fn set_show_slice_planes(&mut self, show_slice_planes: bool) {
+        self.show_slice_planes = show_slice_planes;
+        self.arcball_changed = true;
+    }
+
+    /// Thickness of the oblique slab in voxels
+    pub fn set_slab_thickness(&mut self, thickness: f32) {
+        self.oblique_plane.slab_thickness = thickness.max(0.0);
+        self.slices_changed = true;
+    }
+
+    pub fn set_slab_mode(&mut self, mode: SlabMode) {
+        self.oblique_plane.slab_mode = mode;
+        self.slices_changed = true;
+    }
+
+    pub fn get_slice_data(&self) -> SliceData {
+        SliceData {
+            cursor: self.slice_cursor,
+            oblique: self.oblique_plane,
+            volume_dims: self.volume_dims(),
+        }
+    }
+
+    /// What to draw the slice views with, if they need redrawing
+    pub fn take_slice_update(&mut self) -> Option<SliceData> {
+        match std::mem::take(&mut self.slices_changed) {
+            true => Some(self.get_slice_data()),
+            false => None,
+        }
+    }
+
+    /// Resamples the current oblique slice on the CPU at `size`² pixels
+    pub fn reslice_oblique(&self, size: usize) -> Option<ResliceImage> {
+        let volume = self.volume.as_ref()?;
+        let geometry = self.get_slice_data().geometry(SliceOrientation::Oblique);
+        Some(geometry.resample(volume, size, size))
+    }
+
+    /// Places `mesh`, given in voxel coordinates, in the volume
+    pub fn add_mesh(&mut self, name: String, mesh: Mesh) {
+        let index = self.meshes.len();
+        let mesh = SceneMesh::in_voxel_space(name, mesh, self.volume_dims(), index);
+        self.meshes.push(mesh);
+        self.meshes_generation += 1;
+        self.arcball_changed = true;
+    }
+
+    pub fn clear_meshes(&mut self) {
+        self.meshes.clear();
+        self.meshes_generation += 1;
+        self.arcball_changed = true;
+    }
+
+    pub fn get_meshes(&self) -> (u64, Vec<SceneMesh>) {
+        (self.meshes_generation, self.meshes.clone())
+    }
+
+    fn labels_changed(&mut self) {
+        self.labels_generation += 1;
+        self.slices_changed = true;
+        self.arcball_changed = true;
+    }
+
+    /// Shows the raw labels in `bytes`, which must have the volume's
+    /// dimensions, over the volume and adds default entries for its labels
+    pub fn load_label_map(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.volume.is_none() {
+            return Err(Error::Labels("Load the volume before its label map".into()).into());
+        }
+        let map = LabelMap::from_raw(self.volume_dims(), bytes)?;
+        self.labels.table.add_missing(&map.labels_present());
+        self.labels.map = Some(Arc::new(map));
+        self.labels_changed();
+        Ok(())
+    }
+
+    /// Names and styles labels from a description file
+    pub fn apply_label_descriptions(&mut self, descriptions: Vec<LabelEntry>) {
+        self.labels.table.apply_descriptions(descriptions);
+        self.labels_changed();
+    }
+
+    /// Applies `update` to the entry for label `value`, if there is one
+    pub fn update_label(&mut self, value: u8, update: impl FnOnce(&mut LabelEntry)) {
+        if let Some(entry) = self.labels.table.entry_mut(value) {
+            update(entry);
+            self.labels_changed();
+        }
+    }
+
+    pub fn set_label_blend(&mut self, blend: f32) {
+        self.labels.blend = blend;
+        self.labels_changed();
+    }
+
+    pub fn get_labels(&self) -> (u64, LabelOverlay) {
+        (self.labels_generation, self.labels.clone())
+    }
+
+    fn fusion_changed(&mut self) {
+        self.fusion_generation += 1;
+        self.arcball_changed = true;
+    }
+
+    /// Fuses the raw 8 bit volume in `bytes` with the first one, see
+    /// [`Volume::from_raw`] for what's read from `filename`
+    pub fn load_secondary_volume(&mut self, filename: &str, bytes: Vec<u8>) -> Result<()> {
+        let volume = Volume::from_raw(filename, bytes).context("Failed to load fusion volume")?;
+        let transfer_function = match &self.fusion.secondary {
+            Some(secondary) => secondary.transfer_function.clone(),
+            None => TransferFunction::hot(),
+        };
+        self.fusion.secondary = Some(SecondaryVolume {
+            volume: Arc::new(volume),
+            transfer_function,
+        });
+        self.fusion_changed();
+        Ok(())
+    }
+
+    pub fn set_fusion_mode(&mut self, mode: FusionMode) {
+        self.fusion.mode = mode;
+        self.fusion_changed();
+    }
+
+    pub fn set_fusion_weight(&mut self, weight: f32) {
+        self.fusion.weight = weight;
+        self.fusion_changed();
+    }
+
+    pub fn update_fusion_transform(&mut self, update: impl FnOnce(&mut FusionTransform)) {
+        update(&mut self.fusion.transform);
+        self.fusion_changed();
+    }
+
+    /// Applies `update` to the second volume's transfer function, if loaded
+    pub fn update_secondary_transfer_function(
+        &mut self,
+        update: impl FnOnce(&mut TransferFunction),
+    ) {
+        if let Some(secondary) = &mut self.fusion.secondary {
+            update(&mut secondary.transfer_function);
+            self.fusion_changed();
+        }
+    }
+
+    pub fn get_fusion(&self) -> (u64, Fusion) {
+        (self.fusion_generation, self.fusion.clone())
+    }
+
+    pub fn get_transfer_function(&self) -> (u64, TransferFunction) {
+        (
+            self.transfer_function_generation,
+            self.transfer_function.clone(),
+        )
+    }
+
+    pub fn set_transfer_function(&mut self, transfer_function: TransferFunction) {
+        self.transfer_function = transfer_function;
+        self.transfer_function_generation += 1;
+        self.arcball_changed = true;
+    }
+
+    pub fn set_opacity_window_low(&mut self, low: f32) {
+        let (_, high) = self.transfer_function.window;
+        self.set_transfer_function(TransferFunction {
+            window: (low, high),
+            ..self.transfer_function.clone()
+        })
+    }
+
+    pub fn set_opacity_window_high(&mut self, high: f32) {
+        let (low, _) = self.transfer_function.window;
+        self.set_transfer_function(TransferFunction {
+            window: (low, high),
+            ..self.transfer_function.clone()
+        })
+    }
+
+    pub fn set_max_opacity(&mut self, max_opacity: f32) {
+        self.set_transfer_function(TransferFunction {
+            max_opacity,
+            ..self.transfer_function.clone()
+        })
+    }
+
+    pub fn set_adaptive_sampling(&mut self, enabled: bool) {
+        self.adaptive_sampling.enabled = enabled;
+        self.arcball_changed = true;
+    }
+
+    pub fn set_target_frame_time(&mut self, target_frame_time: Duration) {
+        self.adaptive_sampling.target_frame_time = target_frame_time;
+    }
+
+    /// Decides what kind of frame, if any, should be drawn at `now`
+    pub fn plan_frame(&mut self, now: Instant) -> Option<FramePlan> {
+        self.advance_camera_transition(now);
+        self.advance_turntable(now);
+        if self.arcball_changed {
+            self.adaptive_sampling.record_change(now);
+        }
+        if self.arcball_changed || self.adaptive_sampling.needs_refinement(now) {
+            Some(FramePlan::Restart {
+                step_scale: self.adaptive_sampling.step_scale(now),
+            })
+        } else if self.adaptive_sampling.is_settled(now) {
+            Some(FramePlan::Accumulate)
+        } else {
+            None
+        }
+    }
+
+    pub fn record_frame_time(&mut self, frame_time: Duration, step_scale: f32) {
+        self.adaptive_sampling.record_frame(frame_time, step_scale)
+    }
 }
 
 pub fn set_arcball_changed_to_false_after_draw(app_state: &SharedMut<AppState>) {
@@ -110,9 +1141,27 @@ pub fn set_arcball_changed_to_false_after_draw(app_state: &SharedMut<AppState>)
     app_state.set_arcball_changed(false)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FramePlan {
+    /// Something changed, so start a new image drawn with the step size
+    /// multiplied by `step_scale`
+    Restart { step_scale: f32 },
+    /// Nothing changed, so average another jittered full quality frame into
+    /// the current image
+    Accumulate,
+}
+
+#[derive(Clone)]
 pub struct DrawData {
     pub proj_view: Matrix4<f32>,
     pub eye_pos: Vector3<f32>,
+    /// Direction of the parallel rays of an orthographic view
+    pub view_dir: Vector3<f32>,
+    pub orthographic: bool,
+    /// Crop box handles to draw and the one being dragged, if editing
+    pub crop_handles: Option<(CropBox, Option<CropHandle>)>,
+    /// Cursor whose slices are drawn as planes, if shown
+    pub slice_planes: Option<SliceCursor>,
 }
 
 pub fn should_i_draw(app_state: &SharedMut<AppState>) -> bool {
@@ -120,6 +1169,27 @@ pub fn should_i_draw(app_state: &SharedMut<AppState>) -> bool {
     app_state.get_arcball_changed()
 }
 
+pub fn plan_frame(app_state: &SharedMut<AppState>, now: Instant) -> Result<Option<FramePlan>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("failed to plan frame")?;
+    Ok(app_state.plan_frame(now))
+}
+
+pub fn record_frame_time(
+    app_state: &SharedMut<AppState>,
+    frame_time: Duration,
+    step_scale: f32,
+) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("failed to record frame time")?;
+    app_state.record_frame_time(frame_time, step_scale);
+    Ok(())
+}
+
 pub fn get_canvas_dims(app_state: &SharedMut<AppState>) -> Result<CanvasDims> {
     let app_state = app_state
         .lock()
@@ -128,7 +1198,179 @@ pub fn get_canvas_dims(app_state: &SharedMut<AppState>) -> Result<CanvasDims> {
     Ok(app_state.get_canvas_dims())
 }
 
+pub fn get_render_settings(app_state: &SharedMut<AppState>) -> Result<RenderSettings> {
+    let app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("failed to get render settings")?;
+    Ok(app_state.get_render_settings())
+}
+
+/// Returns the transfer function if its generation differs from `generation`
+pub fn get_transfer_function_update(
+    app_state: &SharedMut<AppState>,
+    generation: u64,
+) -> Result<Option<(u64, TransferFunction)>> {
+    let app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("failed to get transfer function")?;
+    Ok(match app_state.transfer_function_generation == generation {
+        true => None,
+        false => Some(app_state.get_transfer_function()),
+    })
+}
+
+/// Returns the label overlay if its generation differs from `generation`
+pub fn get_label_update(
+    app_state: &SharedMut<AppState>,
+    generation: u64,
+) -> Result<Option<(u64, LabelOverlay)>> {
+    let app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("failed to get labels")?;
+    Ok(match app_state.labels_generation == generation {
+        true => None,
+        false => Some(app_state.get_labels()),
+    })
+}
+
+/// Returns the fusion settings if their generation differs from `generation`
+pub fn get_fusion_update(
+    app_state: &SharedMut<AppState>,
+    generation: u64,
+) -> Result<Option<(u64, Fusion)>> {
+    let app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("failed to get fusion")?;
+    Ok(match app_state.fusion_generation == generation {
+        true => None,
+        false => Some(app_state.get_fusion()),
+    })
+}
+
+/// Returns the meshes if their generation differs from `generation`
+pub fn get_mesh_update(
+    app_state: &SharedMut<AppState>,
+    generation: u64,
+) -> Result<Option<(u64, Vec<SceneMesh>)>> {
+    let app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("failed to get meshes")?;
+    Ok(match app_state.meshes_generation == generation {
+        true => None,
+        false => Some(app_state.get_meshes()),
+    })
+}
+
+pub fn next_export_frame(app_state: &SharedMut<AppState>) -> Result<Option<ExportSettings>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to get the next export frame")?;
+    Ok(app_state.next_export_frame())
+}
+
+pub fn take_capture_request(app_state: &SharedMut<AppState>) -> Result<Option<CaptureSettings>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to check for a capture")?;
+    Ok(app_state.take_capture_request())
+}
+
+/// Draw data and PNG metadata for a `width` by `height` capture
+pub fn get_capture_data(
+    app_state: &SharedMut<AppState>,
+    width: u32,
+    height: u32,
+) -> Result<(DrawData, PngText)> {
+    let app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to get capture data")?;
+    Ok((
+        app_state.get_capture_data(width, height),
+        app_state.capture_metadata(),
+    ))
+}
+
+pub fn take_screenshot_request(app_state: &SharedMut<AppState>, converged: bool) -> Result<bool> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to check for a screenshot")?;
+    Ok(app_state.take_screenshot_request(converged))
+}
+
+pub fn finish_export_frame(app_state: &SharedMut<AppState>, png: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to finish an export frame")?;
+    app_state.finish_export_frame(png)
+}
+
+pub fn cancel_frame_export(app_state: &SharedMut<AppState>) -> Result<()> {
+    app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to cancel the export")?
+        .cancel_frame_export();
+    Ok(())
+}
+
 pub fn get_arcball_data(app_state: &SharedMut<AppState>) -> DrawData {
     let app_state = app_state.lock().unwrap();
     app_state.get_arcball_data()
 }
+
+pub fn take_slice_update(app_state: &SharedMut<AppState>) -> Result<Option<SliceData>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to get slice update")?;
+    Ok(app_state.take_slice_update())
+}
+
+pub fn get_slice_data(app_state: &SharedMut<AppState>) -> Result<SliceData> {
+    let app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to get slice data")?;
+    Ok(app_state.get_slice_data())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AppState, MouseButton};
+    use cgmath::Vector2;
+    use std::time::Duration;
+    use wasm_timer::Instant;
+
+    #[test]
+    fn test_turntable_pauses_during_pinch() {
+        let mut state = AppState::new();
+        state.set_turntable_enabled(true);
+        state.pointer_down(0, Vector2::new(10.0, 10.0), Some(MouseButton::Left));
+        state.pointer_down(1, Vector2::new(20.0, 20.0), None);
+        let now = Instant::now();
+        for tick in 0..2 {
+            let now = now + Duration::from_millis(16 * tick);
+            let paused = state.turntable_paused();
+            assert_eq!(state.turntable.advance(now, paused), None);
+        }
+    }
+
+    #[test]
+    fn test_screenshot_waits_for_convergence() {
+        let mut state = AppState::new();
+        assert!(state.key_down("S"));
+        assert!(!state.take_screenshot_request(false));
+        assert!(state.take_screenshot_request(true));
+        assert!(!state.take_screenshot_request(true));
+    }
+}
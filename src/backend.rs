@@ -0,0 +1,373 @@
+use anyhow::{Context, Result};
+use cgmath::{Vector4, Zero};
+use wasm_timer::Instant;
+
+use crate::app_state::{
+    get_arcball_data, get_canvas_dims, get_fusion_update, get_label_update, get_mesh_update,
+    get_render_settings, get_transfer_function_update, plan_frame, record_frame_time,
+    set_arcball_changed_to_false_after_draw, AppState, DrawData, FramePlan, RenderSettings,
+};
+use crate::cpu_render::{compose, CpuRenderer};
+use crate::fusion::Fusion;
+use crate::image::RgbaImage;
+use crate::labels::LabelOverlay;
+use crate::mesh::SceneMesh;
+use crate::transfer_function::TransferFunction;
+use crate::volume::Volume;
+use crate::{Error, SharedMut};
+
+/// Number of jittered frames averaged once the view is static
+pub const MAX_ACCUMULATED_FRAMES: u32 = 64;
+
+/// Everything a frame of the 3D view is drawn with besides the volume and
+/// the transfer function
+#[derive(Clone)]
+pub struct FrameUniforms {
+    pub draw_data: DrawData,
+    pub render_settings: RenderSettings,
+    pub plan: FramePlan,
+    /// Size of the drawing buffer in pixels
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What drawing the 3D view needs from a graphics API, so the frame logic in
+/// [`render_frame`] doesn't depend on WebGL
+pub trait RenderBackend {
+    /// Replaces the volume being drawn
+    fn upload_volume(&mut self, volume: &Volume) -> Result<()>;
+
+    fn upload_transfer_function(
+        &mut self,
+        generation: u64,
+        transfer_function: &TransferFunction,
+    ) -> Result<()>;
+
+    /// Generation of the transfer function last uploaded
+    fn transfer_function_generation(&self) -> u64;
+
+    fn upload_labels(&mut self, generation: u64, overlay: &LabelOverlay) -> Result<()>;
+
+    /// Generation of the label overlay last uploaded
+    fn labels_generation(&self) -> u64;
+
+    fn upload_fusion(&mut self, generation: u64, fusion: Fusion) -> Result<()>;
+
+    /// Generation of the fusion settings last uploaded
+    fn fusion_generation(&self) -> u64;
+
+    fn upload_meshes(&mut self, generation: u64, meshes: &[SceneMesh]) -> Result<()>;
+
+    /// Generation of the meshes last uploaded
+    fn meshes_generation(&self) -> u64;
+
+    /// Sizes the frames drawn to `width` by `height`. Returns whether that
+    /// left an accumulated image to be restarted.
+    fn resize(&mut self, width: u32, height: u32) -> Result<bool>;
+
+    /// Whether averaging in more frames would no longer change the image
+    fn is_converged(&self) -> bool;
+
+    fn set_uniforms(&mut self, uniforms: &FrameUniforms) -> Result<()>;
+
+    /// Draws a frame with the last uniforms set
+    fn draw(&mut self) -> Result<()>;
+}
+
+/// Draws the next frame of the 3D view if anything changed or the image can
+/// still be refined. Returns whether it drew.
+pub fn render_frame<B: RenderBackend>(
+    backend: &mut B,
+    app_state: &SharedMut<AppState>,
+    frame_start: Instant,
+) -> Result<bool> {
+    let canvas_dims = get_canvas_dims(app_state)?;
+    let (width, height) = (canvas_dims.width as u32, canvas_dims.height as u32);
+    let size_changed = backend.resize(width, height)?;
+    let plan = match plan_frame(app_state, frame_start)? {
+        // A resized accumulation buffer holds nothing to average into
+        Some(FramePlan::Accumulate) if size_changed => FramePlan::Restart { step_scale: 1.0 },
+        Some(FramePlan::Accumulate) if !backend.is_converged() => FramePlan::Accumulate,
+        Some(FramePlan::Accumulate) | None => return Ok(false),
+        Some(plan) => plan,
+    };
+    let draw_data = get_arcball_data(app_state);
+    let render_settings = get_render_settings(app_state)?;
+    if let Some((generation, transfer_function)) =
+        get_transfer_function_update(app_state, backend.transfer_function_generation())?
+    {
+        backend.upload_transfer_function(generation, &transfer_function)?;
+    }
+    if let Some((generation, overlay)) = get_label_update(app_state, backend.labels_generation())? {
+        backend.upload_labels(generation, &overlay)?;
+    }
+    if let Some((generation, fusion)) = get_fusion_update(app_state, backend.fusion_generation())? {
+        backend.upload_fusion(generation, fusion)?;
+    }
+    if let Some((generation, meshes)) = get_mesh_update(app_state, backend.meshes_generation())? {
+        backend.upload_meshes(generation, &meshes)?;
+    }
+    backend.set_uniforms(&FrameUniforms {
+        draw_data,
+        render_settings,
+        plan,
+        width,
+        height,
+    })?;
+    backend.draw()?;
+    set_arcball_changed_to_false_after_draw(app_state);
+    if let FramePlan::Restart { step_scale } = plan {
+        record_frame_time(app_state, frame_start.elapsed(), step_scale)?;
+    }
+    Ok(true)
+}
+
+/// A call made to a [`CpuBackend`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackendCall {
+    UploadVolume([usize; 3]),
+    UploadTransferFunction(u64),
+    UploadLabels(u64),
+    UploadFusion(u64),
+    UploadMeshes(u64),
+    Resize(u32, u32),
+    SetUniforms(FramePlan),
+    Draw,
+}
+
+/// Draws frames with the CPU ray marcher, averaging them like the WebGL
+/// accumulation buffer, and records the calls made to it. Labels, fused
+/// volumes and meshes aren't drawn, only their generations are kept.
+#[derive(Default)]
+pub struct CpuBackend {
+    volume: Option<Volume>,
+    transfer_function: (u64, TransferFunction),
+    labels_generation: u64,
+    fusion_generation: u64,
+    meshes_generation: u64,
+    uniforms: Option<FrameUniforms>,
+    size: (u32, u32),
+    /// Sum of the premultiplied linear colours of the frames drawn since the
+    /// last restart, bottom row first
+    sum: Vec<Vector4<f32>>,
+    frames: u32,
+    pub calls: Vec<BackendCall>,
+}
+
+impl CpuBackend {
+    /// The average of the frames drawn since the last restart over the
+    /// premultiplied `background`
+    pub fn image(&self, background: [f32; 4]) -> RgbaImage {
+        let frames = self.frames.max(1) as f32;
+        let average: Vec<_> = self.sum.iter().map(|color| color / frames).collect();
+        compose(
+            &average,
+            self.size.0 as usize,
+            self.size.1 as usize,
+            background,
+        )
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+}
+
+impl RenderBackend for CpuBackend {
+    fn upload_volume(&mut self, volume: &Volume) -> Result<()> {
+        self.calls.push(BackendCall::UploadVolume(volume.dims()));
+        self.volume = Some(volume.clone());
+        Ok(())
+    }
+
+    fn upload_transfer_function(
+        &mut self,
+        generation: u64,
+        transfer_function: &TransferFunction,
+    ) -> Result<()> {
+        self.calls
+            .push(BackendCall::UploadTransferFunction(generation));
+        self.transfer_function = (generation, transfer_function.clone());
+        Ok(())
+    }
+
+    fn transfer_function_generation(&self) -> u64 {
+        self.transfer_function.0
+    }
+
+    fn upload_labels(&mut self, generation: u64, _overlay: &LabelOverlay) -> Result<()> {
+        self.calls.push(BackendCall::UploadLabels(generation));
+        self.labels_generation = generation;
+        Ok(())
+    }
+
+    fn labels_generation(&self) -> u64 {
+        self.labels_generation
+    }
+
+    fn upload_fusion(&mut self, generation: u64, _fusion: Fusion) -> Result<()> {
+        self.calls.push(BackendCall::UploadFusion(generation));
+        self.fusion_generation = generation;
+        Ok(())
+    }
+
+    fn fusion_generation(&self) -> u64 {
+        self.fusion_generation
+    }
+
+    fn upload_meshes(&mut self, generation: u64, _meshes: &[SceneMesh]) -> Result<()> {
+        self.calls.push(BackendCall::UploadMeshes(generation));
+        self.meshes_generation = generation;
+        Ok(())
+    }
+
+    fn meshes_generation(&self) -> u64 {
+        self.meshes_generation
+    }
+
+    fn resize(&mut self, width: u32, height: u32) -> Result<bool> {
+        if self.size == (width, height) {
+            return Ok(false);
+        }
+        self.calls.push(BackendCall::Resize(width, height));
+        self.size = (width, height);
+        self.sum = vec![Vector4::zero(); (width * height) as usize];
+        self.frames = 0;
+        Ok(true)
+    }
+
+    fn is_converged(&self) -> bool {
+        self.frames >= MAX_ACCUMULATED_FRAMES
+    }
+
+    fn set_uniforms(&mut self, uniforms: &FrameUniforms) -> Result<()> {
+        self.calls.push(BackendCall::SetUniforms(uniforms.plan));
+        self.uniforms = Some(uniforms.clone());
+        Ok(())
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        self.calls.push(BackendCall::Draw);
+        let volume = self
+            .volume
+            .as_ref()
+            .ok_or(Error::MissingItem)
+            .context("No volume uploaded to draw")?;
+        let uniforms = self
+            .uniforms
+            .as_ref()
+            .ok_or(Error::MissingItem)
+            .context("No uniforms set to draw with")?;
+        let step_scale = match uniforms.plan {
+            FramePlan::Restart { step_scale } => {
+                self.sum.fill(Vector4::zero());
+                self.frames = 0;
+                step_scale
+            }
+            FramePlan::Accumulate => 1.0,
+        };
+        let renderer =
+            CpuRenderer::new(volume, &self.transfer_function.1, &uniforms.render_settings);
+        let frame = renderer.render_frame(
+            &uniforms.draw_data,
+            (uniforms.width as usize, uniforms.height as usize),
+            self.frames as i32,
+            step_scale / uniforms.render_settings.sampling_rate,
+        );
+        for (sum, color) in self.sum.iter_mut().zip(frame) {
+            *sum += color;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render_frame, BackendCall, CpuBackend, RenderBackend, MAX_ACCUMULATED_FRAMES};
+    use crate::app_state::{AppState, FramePlan};
+    use crate::cpu_render::CpuRenderer;
+    use crate::shared_mut;
+    use crate::transfer_function::TransferFunction;
+    use crate::volume::Volume;
+    use wasm_timer::Instant;
+
+    #[test]
+    fn test_frame_sequence() {
+        let volume = Volume::new([4, 4, 4], (0..64).map(|i| i * 4).collect()).unwrap();
+        let mut state = AppState::new();
+        state.update_canvas(3.0, 2.0);
+        let app_state = shared_mut(state);
+        let mut backend = CpuBackend::default();
+        backend.upload_volume(&volume).unwrap();
+
+        // A new buffer restarts even a settled view, which then accumulates
+        assert!(render_frame(&mut backend, &app_state, Instant::now()).unwrap());
+        assert!(render_frame(&mut backend, &app_state, Instant::now()).unwrap());
+        assert_eq!(
+            backend.calls,
+            [
+                BackendCall::UploadVolume([4, 4, 4]),
+                BackendCall::Resize(3, 2),
+                BackendCall::SetUniforms(FramePlan::Restart { step_scale: 1.0 }),
+                BackendCall::Draw,
+                BackendCall::SetUniforms(FramePlan::Accumulate),
+                BackendCall::Draw,
+            ]
+        );
+
+        // The accumulated frames match the reference renderer's samples
+        let (draw_data, settings) = {
+            let state = app_state.lock().unwrap();
+            (state.get_arcball_data(), state.get_render_settings())
+        };
+        let renderer = CpuRenderer::new(&volume, &TransferFunction::default(), &settings);
+        assert_eq!(
+            backend.image([1.0; 4]),
+            renderer.render(&draw_data, 3, 2, 2, [1.0; 4])
+        );
+
+        // Changing the transfer function uploads it and restarts
+        backend.calls.clear();
+        app_state
+            .lock()
+            .unwrap()
+            .set_transfer_function(TransferFunction::hot());
+        assert!(render_frame(&mut backend, &app_state, Instant::now()).unwrap());
+        assert_eq!(backend.calls[0], BackendCall::UploadTransferFunction(1));
+        assert!(matches!(
+            backend.calls[1],
+            BackendCall::SetUniforms(FramePlan::Restart { .. })
+        ));
+        assert_eq!(backend.frames(), 1);
+
+        // Labels are handed over once per change
+        backend.calls.clear();
+        app_state.lock().unwrap().set_label_blend(0.5);
+        assert!(render_frame(&mut backend, &app_state, Instant::now()).unwrap());
+        app_state.lock().unwrap().set_arcball_changed(true);
+        assert!(render_frame(&mut backend, &app_state, Instant::now()).unwrap());
+        assert_eq!(
+            backend
+                .calls
+                .iter()
+                .filter(|call| matches!(call, BackendCall::UploadLabels(_)))
+                .count(),
+            1
+        );
+        assert_eq!(backend.labels_generation(), 1);
+    }
+
+    #[test]
+    fn test_stops_once_converged() {
+        let volume = Volume::new([2, 2, 2], vec![0; 8]).unwrap();
+        let mut state = AppState::new();
+        state.update_canvas(1.0, 1.0);
+        let app_state = shared_mut(state);
+        let mut backend = CpuBackend::default();
+        backend.upload_volume(&volume).unwrap();
+        while render_frame(&mut backend, &app_state, Instant::now()).unwrap() {}
+        assert_eq!(backend.frames(), MAX_ACCUMULATED_FRAMES);
+        assert!(backend.is_converged());
+    }
+}
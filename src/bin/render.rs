@@ -0,0 +1,133 @@
+//! Renders a volume to a PNG on the CPU, without a browser, for batch
+//! generating thumbnails:
+//!
+//! ```text
+//! render <volume.raw> <transfer_function.json> <camera.json> <output.png>
+//!        [--size <width>x<height>] [--samples <n>] [--transparent]
+//! ```
+//!
+//! The volume is raw 8 bit data whose dimensions, and optionally voxel size
+//! and orientation, are written into its name, such as
+//! `skull_256x256x256_uint8.raw`. Without dimensions it's taken to be a cube. The
+//! transfer function and camera JSON are in the format of the "Transfer
+//! function" and "Camera" text chunks of the app's captures.
+
+use anyhow::{bail, Context, Result};
+
+use volumetric_renderer::app_state::RenderSettings;
+use volumetric_renderer::capture::{unpremultiply, CaptureCamera};
+use volumetric_renderer::cpu_render::CpuRenderer;
+use volumetric_renderer::transfer_function::TransferFunction;
+use volumetric_renderer::volume::Volume;
+
+const USAGE: &str = "Usage: render <volume.raw> <transfer_function.json> <camera.json> \
+                     <output.png> [--size <width>x<height>] [--samples <n>] [--transparent]";
+
+struct Args {
+    volume: String,
+    transfer_function: String,
+    camera: String,
+    output: String,
+    width: u32,
+    height: u32,
+    samples: u32,
+    transparent: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut paths = Vec::new();
+    let mut size = (256, 256);
+    let mut samples = 8;
+    let mut transparent = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => {
+                let value = args.next().context("--size needs a value")?;
+                size = value
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .with_context(|| format!("Invalid size {value}, expected <width>x<height>"))?;
+            }
+            "--samples" => {
+                let value = args.next().context("--samples needs a value")?;
+                samples = value
+                    .parse()
+                    .with_context(|| format!("Invalid sample count {value}"))?;
+            }
+            "--transparent" => transparent = true,
+            "-h" | "--help" => bail!(USAGE),
+            _ if arg.starts_with("--") => bail!("Unknown option {arg}\n{USAGE}"),
+            _ => paths.push(arg),
+        }
+    }
+    let [volume, transfer_function, camera, output]: [String; 4] =
+        paths.try_into().map_err(|_| anyhow::anyhow!(USAGE))?;
+    if size.0 == 0 || size.1 == 0 {
+        bail!("Image size must not be zero");
+    }
+    Ok(Args {
+        volume,
+        transfer_function,
+        camera,
+        output,
+        width: size.0,
+        height: size.1,
+        samples,
+        transparent,
+    })
+}
+
+fn load_volume(path: &str) -> Result<Volume> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read volume {path}"))?;
+    Volume::from_raw(path, bytes).with_context(|| format!("Failed to load volume {path}"))
+}
+
+fn load_json<T: serde::de::DeserializeOwned>(path: &str) -> Result<T> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+    serde_json::from_str(&text).with_context(|| format!("Failed to parse {path}"))
+}
+
+fn main() -> Result<()> {
+    let args = parse_args(std::env::args().skip(1))?;
+    let volume = load_volume(&args.volume)?;
+    let transfer_function: TransferFunction = load_json(&args.transfer_function)?;
+    let camera: CaptureCamera = load_json(&args.camera)?;
+
+    let settings = RenderSettings::default();
+    let renderer = CpuRenderer::new(&volume, &transfer_function, &settings);
+    let background = match args.transparent {
+        true => [0.0; 4],
+        false => [1.0; 4],
+    };
+    let mut image = renderer.render(
+        &camera.draw_data(args.width, args.height),
+        args.width as usize,
+        args.height as usize,
+        args.samples,
+        background,
+    );
+    if args.transparent {
+        unpremultiply(&mut image.data);
+    }
+
+    let dims = volume.dims();
+    let dataset = std::path::Path::new(&args.volume)
+        .file_name()
+        .map_or(args.volume.clone(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+    let text = vec![
+        (
+            "Dataset".to_string(),
+            format!("{dataset} {}x{}x{}", dims[0], dims[1], dims[2]),
+        ),
+        ("Camera".to_string(), serde_json::to_string(&camera)?),
+        (
+            "Transfer function".to_string(),
+            serde_json::to_string(&transfer_function)?,
+        ),
+        ("Software".to_string(), "volumetric-renderer".to_string()),
+    ];
+    let png = image.to_png_with_text(&text)?;
+    std::fs::write(&args.output, png).with_context(|| format!("Failed to write {}", args.output))
+}
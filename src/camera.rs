@@ -0,0 +1,426 @@
+use cgmath::{
+    Deg, InnerSpace, Matrix, Matrix3, Matrix4, One, Quaternion, Rotation3, SquareMatrix, Vector2,
+    Vector3, VectorSpace,
+};
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use wasm_timer::Instant;
+
+use crate::volume::PatientOrientation;
+
+/// Where the camera is, independent of the screen it's drawn to
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraPose {
+    /// Point the camera orbits and looks at
+    pub center: Vector3<f32>,
+    /// Rotation from volume to view space
+    pub rotation: Quaternion<f32>,
+    /// Distance from the eye to `center`
+    pub distance: f32,
+}
+
+impl CameraPose {
+    pub fn view(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(Vector3::new(0.0, 0.0, -self.distance))
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_translation(-self.center)
+    }
+
+    /// The pose `t` of the way from `self` to `to`, turning along the
+    /// shortest arc
+    pub fn interpolate(&self, to: &CameraPose, t: f32) -> CameraPose {
+        CameraPose {
+            center: self.center.lerp(to.center, t),
+            rotation: self.rotation.slerp(to.rotation, t),
+            distance: self.distance + (to.distance - self.distance) * t,
+        }
+    }
+}
+
+/// A named pose the user can return to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub pose: CameraPose,
+}
+
+/// An eased move between two poses, advanced once per animation frame
+#[derive(Clone, Copy, Debug)]
+pub struct CameraTransition {
+    from: CameraPose,
+    to: CameraPose,
+    start: Instant,
+    duration: Duration,
+}
+
+impl CameraTransition {
+    pub fn new(from: CameraPose, to: CameraPose, start: Instant, duration: Duration) -> Self {
+        Self {
+            from,
+            to,
+            start,
+            duration,
+        }
+    }
+
+    /// Pose at `now` and whether the transition has finished
+    pub fn pose_at(&self, now: Instant) -> (CameraPose, bool) {
+        let elapsed = now.saturating_duration_since(self.start).as_secs_f32();
+        let t = match self.duration.is_zero() {
+            true => 1.0,
+            false => (elapsed / self.duration.as_secs_f32()).min(1.0),
+        };
+        // Smoothstep, so the camera eases in and out
+        let eased = t * t * (3.0 - 2.0 * t);
+        (self.from.interpolate(&self.to, eased), t >= 1.0)
+    }
+}
+
+/// The Shoemake arcball camera, with its pose exposed so views can be set
+/// directly
+pub struct Camera {
+    pose: CameraPose,
+    view: Matrix4<f32>,
+    inv_view: Matrix4<f32>,
+    zoom_speed: f32,
+    inv_screen: [f32; 2],
+}
+
+impl Camera {
+    /// `screen` is `[width, height]` in pixels
+    pub fn new(center: Vector3<f32>, distance: f32, zoom_speed: f32, screen: [f32; 2]) -> Self {
+        let mut camera = Self {
+            pose: CameraPose {
+                center,
+                rotation: Quaternion::one(),
+                distance,
+            },
+            view: Matrix4::one(),
+            inv_view: Matrix4::one(),
+            zoom_speed,
+            inv_screen: [1.0 / screen[0], 1.0 / screen[1]],
+        };
+        camera.update_view();
+        camera
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        self.pose
+    }
+
+    pub fn set_pose(&mut self, pose: CameraPose) {
+        self.pose = pose;
+        self.update_view();
+    }
+
+    pub fn get_mat4(&self) -> Matrix4<f32> {
+        self.view
+    }
+
+    pub fn eye_pos(&self) -> Vector3<f32> {
+        self.inv_view.w.truncate()
+    }
+
+    /// Rotates from the orientation under `mouse_prev` to the one under
+    /// `mouse_cur`, both in pixels
+    pub fn rotate(&mut self, mouse_prev: Vector2<f32>, mouse_cur: Vector2<f32>) {
+        let to_ball = |mouse: Vector2<f32>| {
+            screen_to_arcball(Vector2::new(
+                (mouse.x * 2.0 * self.inv_screen[0] - 1.0).clamp(-1.0, 1.0),
+                (1.0 - 2.0 * mouse.y * self.inv_screen[1]).clamp(-1.0, 1.0),
+            ))
+        };
+        let rotation = to_ball(mouse_cur) * to_ball(mouse_prev) * self.pose.rotation;
+        self.pose.rotation = rotation.normalize();
+        self.update_view();
+    }
+
+    /// Positive amounts move the eye towards the centre
+    pub fn zoom(&mut self, amount: f32, elapsed: f32) {
+        self.pose.distance -= amount * self.zoom_speed * elapsed;
+        self.update_view();
+    }
+
+    /// Moves the centre with the mouse, `mouse_delta` in pixels
+    pub fn pan(&mut self, mouse_delta: Vector2<f32>) {
+        let delta = Vector3::new(
+            mouse_delta.x * self.inv_screen[0],
+            -mouse_delta.y * self.inv_screen[1],
+            0.0,
+        );
+        self.pose.center -= (self.inv_view * delta.extend(0.0)).truncate();
+        self.update_view();
+    }
+
+    /// Turns the camera about an axis through the centre by `degrees`
+    pub fn orbit(&mut self, axis: TurntableAxis, degrees: f32) {
+        let turn = |axis| Quaternion::from_axis_angle(axis, Deg(-degrees));
+        self.pose.rotation = match axis {
+            TurntableAxis::X => self.pose.rotation * turn(Vector3::unit_x()),
+            TurntableAxis::Y => self.pose.rotation * turn(Vector3::unit_y()),
+            TurntableAxis::Z => self.pose.rotation * turn(Vector3::unit_z()),
+            // Turning in view space rather than volume space
+            TurntableAxis::ScreenUp => turn(Vector3::unit_y()) * self.pose.rotation,
+            TurntableAxis::ScreenRight => turn(Vector3::unit_x()) * self.pose.rotation,
+        }
+        .normalize();
+        self.update_view();
+    }
+
+    pub fn update_screen(&mut self, width: f32, height: f32) {
+        self.inv_screen = [1.0 / width, 1.0 / height];
+    }
+
+    fn update_view(&mut self) {
+        self.view = self.pose.view();
+        self.inv_view = self.view.invert().unwrap_or_else(Matrix4::one);
+    }
+}
+
+fn screen_to_arcball(p: Vector2<f32>) -> Quaternion<f32> {
+    let dist = p.magnitude2();
+    if dist <= 1.0 {
+        Quaternion::new(0.0, p.x, p.y, (1.0 - dist).sqrt())
+    } else {
+        let p = p.normalize();
+        Quaternion::new(0.0, p.x, p.y, 0.0)
+    }
+}
+
+/// Axis the turntable spins the volume about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurntableAxis {
+    /// Volume axes
+    X,
+    Y,
+    Z,
+    /// Vertical on screen, whichever way the volume is turned
+    ScreenUp,
+    /// Horizontal on screen
+    ScreenRight,
+}
+
+/// Spins the camera around the volume at a steady speed
+#[derive(Clone, Copy, Debug)]
+pub struct Turntable {
+    pub enabled: bool,
+    pub axis: TurntableAxis,
+    /// Degrees per second, negative to spin the other way
+    pub speed: f32,
+    /// When the turntable last moved, unset while paused so it doesn't jump
+    /// on resuming
+    last_tick: Option<Instant>,
+}
+
+impl Default for Turntable {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            axis: TurntableAxis::ScreenUp,
+            speed: 30.0,
+            last_tick: None,
+        }
+    }
+}
+
+impl Turntable {
+    /// Degrees to turn by at `now`, if running and not `paused`
+    pub fn advance(&mut self, now: Instant, paused: bool) -> Option<f32> {
+        if !self.enabled || paused {
+            self.last_tick = None;
+            return None;
+        }
+        let last_tick = self.last_tick.replace(now)?;
+        Some(self.speed * now.saturating_duration_since(last_tick).as_secs_f32())
+    }
+}
+
+/// Standard radiological views, named after the side of the patient the
+/// eye is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewPreset {
+    Anterior,
+    Posterior,
+    Left,
+    Right,
+    Superior,
+    Inferior,
+}
+
+impl ViewPreset {
+    pub const ALL: [ViewPreset; 6] = [
+        ViewPreset::Anterior,
+        ViewPreset::Posterior,
+        ViewPreset::Left,
+        ViewPreset::Right,
+        ViewPreset::Superior,
+        ViewPreset::Inferior,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewPreset::Anterior => "Anterior",
+            ViewPreset::Posterior => "Posterior",
+            ViewPreset::Left => "Left",
+            ViewPreset::Right => "Right",
+            ViewPreset::Superior => "Superior",
+            ViewPreset::Inferior => "Inferior",
+        }
+    }
+
+    /// Viewing direction and screen up in patient (LPS) coordinates.
+    /// Superior and inferior views keep anterior up.
+    fn patient_axes(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let superior = Vector3::unit_z();
+        let anterior = -Vector3::unit_y();
+        match self {
+            ViewPreset::Anterior => (-anterior, superior),
+            ViewPreset::Posterior => (anterior, superior),
+            ViewPreset::Left => (-Vector3::unit_x(), superior),
+            ViewPreset::Right => (Vector3::unit_x(), superior),
+            ViewPreset::Superior => (-superior, anterior),
+            ViewPreset::Inferior => (superior, anterior),
+        }
+    }
+
+    /// Rotation from volume to view space for this view of a volume with
+    /// `orientation`
+    pub fn rotation(&self, orientation: &PatientOrientation) -> Quaternion<f32> {
+        let (view_dir, up) = self.patient_axes();
+        let view_dir = orientation.to_volume(view_dir);
+        let up = orientation.to_volume(up);
+        let right = view_dir.cross(up).normalize();
+        let up = right.cross(view_dir);
+        // The rows are the view space axes in volume coordinates
+        let to_view = Matrix3::from_cols(right, up, -view_dir).transpose();
+        Quaternion::from(to_view).normalize()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Camera, CameraPose, CameraTransition, Turntable, TurntableAxis, ViewPreset};
+    use crate::volume::PatientOrientation;
+    use cgmath::{assert_abs_diff_eq, Deg, InnerSpace, Matrix3, Quaternion, Rotation3, Vector3};
+    use std::time::Duration;
+    use wasm_timer::Instant;
+
+    fn view_axes(camera: &Camera) -> (Vector3<f32>, Vector3<f32>) {
+        let view = camera.get_mat4();
+        let forward = -Vector3::new(view.x.z, view.y.z, view.z.z).normalize();
+        let up = Vector3::new(view.x.y, view.y.y, view.z.y).normalize();
+        (forward, up)
+    }
+
+    #[test]
+    fn test_presets_follow_orientation() {
+        let mut camera = Camera::new(Vector3::new(0.5, 0.5, 0.5), 2.0, 1.0, [800.0, 800.0]);
+        let mut pose = camera.pose();
+        pose.rotation = ViewPreset::Anterior.rotation(&PatientOrientation::default());
+        camera.set_pose(pose);
+        let (forward, up) = view_axes(&camera);
+        assert_abs_diff_eq!(forward, Vector3::unit_y(), epsilon = 1e-5);
+        assert_abs_diff_eq!(up, Vector3::unit_z(), epsilon = 1e-5);
+        assert_abs_diff_eq!(
+            camera.eye_pos(),
+            Vector3::new(0.5, -1.5, 0.5),
+            epsilon = 1e-5
+        );
+
+        // A volume stored with its y axis pointing superior and z anterior
+        let orientation = PatientOrientation {
+            axes: Matrix3::from_cols(Vector3::unit_x(), Vector3::unit_z(), -Vector3::unit_y()),
+        };
+        pose.rotation = ViewPreset::Superior.rotation(&orientation);
+        camera.set_pose(pose);
+        let (forward, up) = view_axes(&camera);
+        assert_abs_diff_eq!(forward, -Vector3::unit_y(), epsilon = 1e-5);
+        assert_abs_diff_eq!(up, Vector3::unit_z(), epsilon = 1e-5);
+    }
+
+    #[test]
+    fn test_zoom_and_pan() {
+        let mut camera = Camera::new(Vector3::new(0.5, 0.5, 0.5), 2.0, 1.0, [800.0, 400.0]);
+        camera.zoom(0.5, 1.0);
+        assert_abs_diff_eq!(
+            camera.eye_pos(),
+            Vector3::new(0.5, 0.5, 2.0),
+            epsilon = 1e-5
+        );
+        camera.pan(cgmath::Vector2::new(400.0, 0.0));
+        assert_abs_diff_eq!(
+            camera.pose().center,
+            Vector3::new(0.0, 0.5, 0.5),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn test_transition() {
+        let from = CameraPose {
+            center: Vector3::new(0.5, 0.5, 0.5),
+            rotation: Quaternion::from_angle_y(Deg(0.0)),
+            distance: 2.0,
+        };
+        let to = CameraPose {
+            center: Vector3::new(1.5, 0.5, 0.5),
+            rotation: Quaternion::from_angle_y(Deg(90.0)),
+            distance: 4.0,
+        };
+        let start = Instant::now();
+        let transition = CameraTransition::new(from, to, start, Duration::from_secs(2));
+        let (pose, finished) = transition.pose_at(start);
+        assert!(!finished);
+        assert_abs_diff_eq!(pose.center, from.center);
+
+        let (pose, finished) = transition.pose_at(start + Duration::from_secs(1));
+        assert!(!finished);
+        assert_abs_diff_eq!(pose.center, Vector3::new(1.0, 0.5, 0.5), epsilon = 1e-5);
+        assert_abs_diff_eq!(pose.distance, 3.0, epsilon = 1e-5);
+        let halfway = Quaternion::from_angle_y(Deg(45.0));
+        assert_abs_diff_eq!(pose.rotation.dot(halfway).abs(), 1.0, epsilon = 1e-5);
+
+        let (pose, finished) = transition.pose_at(start + Duration::from_secs(3));
+        assert!(finished);
+        assert_abs_diff_eq!(pose.distance, to.distance);
+    }
+
+    #[test]
+    fn test_turntable() {
+        let mut turntable = Turntable {
+            enabled: true,
+            ..Turntable::default()
+        };
+        let start = Instant::now();
+        // The first tick only starts the clock
+        assert_eq!(turntable.advance(start, false), None);
+        let angle = turntable.advance(start + Duration::from_secs(2), false);
+        assert_abs_diff_eq!(angle.unwrap(), 60.0, epsilon = 1e-3);
+        // Time spent paused isn't made up for afterwards
+        assert_eq!(
+            turntable.advance(start + Duration::from_secs(3), true),
+            None
+        );
+        assert_eq!(
+            turntable.advance(start + Duration::from_secs(10), false),
+            None
+        );
+        let angle = turntable.advance(start + Duration::from_secs(11), false);
+        assert_abs_diff_eq!(angle.unwrap(), 30.0, epsilon = 1e-3);
+
+        let mut camera = Camera::new(Vector3::new(0.5, 0.5, 0.5), 2.0, 1.0, [800.0, 800.0]);
+        camera.orbit(TurntableAxis::Y, 90.0);
+        assert_abs_diff_eq!(
+            camera.eye_pos(),
+            Vector3::new(2.5, 0.5, 0.5),
+            epsilon = 1e-5
+        );
+        camera.orbit(TurntableAxis::ScreenUp, 90.0);
+        assert_abs_diff_eq!(
+            camera.eye_pos(),
+            Vector3::new(0.5, 0.5, -1.5),
+            epsilon = 1e-5
+        );
+    }
+}
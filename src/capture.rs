@@ -0,0 +1,191 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::app_state::{DrawData, CENTER};
+use crate::camera::CameraPose;
+use crate::projection::{Projection, ProjectionMode};
+
+/// How a still capture of the 3D view is rendered
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CaptureSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Jittered frames averaged into each tile
+    pub samples: u32,
+    /// Leaves the background see-through instead of white
+    pub transparent: bool,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            width: 4096,
+            height: 4096,
+            samples: 32,
+            transparent: false,
+        }
+    }
+}
+
+/// The camera a capture was taken with, as stored in its PNG metadata and
+/// read by the headless renderer
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CaptureCamera {
+    pub pose: CameraPose,
+    pub projection: ProjectionMode,
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl CaptureCamera {
+    pub fn projection(&self) -> Projection {
+        Projection {
+            mode: self.projection,
+            fov: self.fov,
+            near: self.near,
+            far: self.far,
+        }
+    }
+
+    /// Draw data for a `width` by `height` image, framed as the app frames
+    /// its canvas
+    pub fn draw_data(&self, width: u32, height: u32) -> DrawData {
+        let aspect = width.max(1) as f32 / height.max(1) as f32;
+        let view = self.pose.view();
+        let focus = view * CENTER.extend(1.0);
+        let inv_view = view.invert().unwrap_or_else(Matrix4::identity);
+        DrawData {
+            proj_view: self.projection().matrix(aspect, -focus.z) * view,
+            eye_pos: inv_view.w.truncate(),
+            view_dir: -Vector3::new(view.x.z, view.y.z, view.z.z).normalize(),
+            orthographic: self.projection == ProjectionMode::Orthographic,
+            crop_handles: None,
+            slice_planes: None,
+        }
+    }
+}
+
+/// Part of a capture rendered on its own, in pixels from the bottom left as
+/// WebGL counts them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Maps the tile's part of the clip space of the whole capture onto the
+    /// full clip space, to be applied after the capture's projection
+    pub fn projection(&self, width: u32, height: u32) -> Matrix4<f32> {
+        let scale_x = width as f32 / self.width as f32;
+        let scale_y = height as f32 / self.height as f32;
+        // Centre of the tile in the normalised device coordinates of the capture
+        let center_x = (self.x as f32 + self.width as f32 / 2.0) / width as f32 * 2.0 - 1.0;
+        let center_y = (self.y as f32 + self.height as f32 / 2.0) / height as f32 * 2.0 - 1.0;
+        Matrix4::from_nonuniform_scale(scale_x, scale_y, 1.0)
+            * Matrix4::from_translation(Vector3::new(-center_x, -center_y, 0.0))
+    }
+}
+
+/// Splits a `width` by `height` capture into tiles no bigger than `max_tile`
+/// on a side, row by row from the bottom left
+pub fn tiles(width: u32, height: u32, max_tile: u32) -> Vec<Tile> {
+    let max_tile = max_tile.max(1);
+    let starts = |size: u32| (0..size).step_by(max_tile as usize);
+    starts(height)
+        .flat_map(|y| {
+            starts(width).map(move |x| Tile {
+                x,
+                y,
+                width: max_tile.min(width - x),
+                height: max_tile.min(height - y),
+            })
+        })
+        .collect()
+}
+
+/// Copies the RGBA pixels of `tile`, bottom row first as read back from
+/// WebGL, into `image` of the whole capture laid out the same way
+pub fn paste_tile(image: &mut [u8], width: u32, tile: &Tile, pixels: &[u8]) {
+    let row = tile.width as usize * 4;
+    for (tile_row, source) in pixels.chunks_exact(row).enumerate() {
+        let start = ((tile.y as usize + tile_row) * width as usize + tile.x as usize) * 4;
+        image[start..start + row].copy_from_slice(source);
+    }
+}
+
+/// Divides the colour of premultiplied RGBA pixels by their alpha, as PNGs
+/// store it
+pub fn unpremultiply(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel[..3] {
+            if let Some(straight) = (*channel as u32 * 255 + alpha / 2).checked_div(alpha) {
+                *channel = straight.min(255) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{paste_tile, tiles, unpremultiply, Tile};
+    use cgmath::{assert_abs_diff_eq, Vector4};
+
+    #[test]
+    fn test_tiles_cover_capture() {
+        let tiles = tiles(5, 3, 2);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[2],
+            Tile {
+                x: 4,
+                y: 0,
+                width: 1,
+                height: 2
+            }
+        );
+        let area: u32 = tiles.iter().map(|tile| tile.width * tile.height).sum();
+        assert_eq!(area, 15);
+    }
+
+    #[test]
+    fn test_tile_projection() {
+        let tile = Tile {
+            x: 100,
+            y: 0,
+            width: 100,
+            height: 50,
+        };
+        let projection = tile.projection(400, 200);
+        // The tile's corners in the capture's clip space land on the edges
+        let bottom_left = projection * Vector4::new(-0.5, -1.0, 0.3, 1.0);
+        let top_right = projection * Vector4::new(0.0, -0.5, 0.3, 1.0);
+        assert_abs_diff_eq!(bottom_left, Vector4::new(-1.0, -1.0, 0.3, 1.0));
+        assert_abs_diff_eq!(top_right, Vector4::new(1.0, 1.0, 0.3, 1.0));
+    }
+
+    #[test]
+    fn test_paste_tile() {
+        let mut image = vec![0; 3 * 2 * 4];
+        let tile = Tile {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 1,
+        };
+        paste_tile(&mut image, 3, &tile, &[1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(&image[12..16], &[0; 4]);
+        assert_eq!(&image[16..], &[1, 1, 1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_unpremultiply() {
+        let mut pixels = [50, 100, 0, 100, 7, 7, 7, 0, 200, 10, 30, 255];
+        unpremultiply(&mut pixels);
+        assert_eq!(pixels, [128, 255, 0, 100, 7, 7, 7, 0, 200, 10, 30, 255]);
+    }
+}
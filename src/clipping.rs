@@ -0,0 +1,289 @@
+use cgmath::{InnerSpace, Matrix4, Vector2, Vector3, Vector4};
+
+use crate::util::spherical_direction;
+
+/// Most clipping planes the shader supports at once
+pub const MAX_CLIP_PLANES: usize = 4;
+/// Handles further than this many pixels from the cursor can't be grabbed
+const HANDLE_PICK_RADIUS: f32 = 12.0;
+/// Smallest extent of the crop box along any axis
+const MIN_CROP_EXTENT: f32 = 0.01;
+
+/// Axis aligned box in volume coordinates, everything outside it is cut away
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Default for CropBox {
+    fn default() -> Self {
+        Self {
+            min: [0.0; 3],
+            max: [1.0; 3],
+        }
+    }
+}
+
+/// One of the six faces of the crop box
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CropHandle {
+    pub axis: usize,
+    /// Whether this is the face at `max` rather than `min`
+    pub is_max: bool,
+}
+
+impl CropHandle {
+    pub const ALL: [CropHandle; 6] = [
+        CropHandle {
+            axis: 0,
+            is_max: false,
+        },
+        CropHandle {
+            axis: 0,
+            is_max: true,
+        },
+        CropHandle {
+            axis: 1,
+            is_max: false,
+        },
+        CropHandle {
+            axis: 1,
+            is_max: true,
+        },
+        CropHandle {
+            axis: 2,
+            is_max: false,
+        },
+        CropHandle {
+            axis: 2,
+            is_max: true,
+        },
+    ];
+}
+
+impl CropBox {
+    /// Centre of the face belonging to `handle`
+    pub fn handle_position(&self, handle: CropHandle) -> Vector3<f32> {
+        let mut position = [0.0f32; 3];
+        for (axis, p) in position.iter_mut().enumerate() {
+            *p = 0.5 * (self.min[axis] + self.max[axis]);
+        }
+        position[handle.axis] = match handle.is_max {
+            true => self.max[handle.axis],
+            false => self.min[handle.axis],
+        };
+        position.into()
+    }
+
+    /// Finds the handle drawn closest to `cursor`, in pixels, if any is close
+    /// enough to grab
+    pub fn pick_handle(
+        &self,
+        proj_view: &Matrix4<f32>,
+        canvas: [f32; 2],
+        cursor: Vector2<f32>,
+    ) -> Option<CropHandle> {
+        CropHandle::ALL
+            .iter()
+            .filter_map(|handle| {
+                let screen = to_screen(proj_view, canvas, self.handle_position(*handle))?;
+                Some((*handle, (screen - cursor).magnitude()))
+            })
+            .filter(|(_, distance)| *distance <= HANDLE_PICK_RADIUS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(handle, _)| handle)
+    }
+
+    /// Moves the face of `handle` along its axis by as much as the cursor
+    /// moved along that axis on screen
+    pub fn drag_handle(
+        &mut self,
+        handle: CropHandle,
+        proj_view: &Matrix4<f32>,
+        canvas: [f32; 2],
+        cursor_delta: Vector2<f32>,
+    ) {
+        let origin = self.handle_position(handle);
+        let mut axis = Vector3::new(0.0, 0.0, 0.0);
+        axis[handle.axis] = 1.0;
+        let screen_axis = match (
+            to_screen(proj_view, canvas, origin),
+            to_screen(proj_view, canvas, origin + axis),
+        ) {
+            (Some(start), Some(end)) => end - start,
+            _ => return,
+        };
+        let pixels_per_unit = screen_axis.magnitude2();
+        if pixels_per_unit < f32::EPSILON {
+            // The axis points straight at the camera
+            return;
+        }
+        let moved = cursor_delta.dot(screen_axis) / pixels_per_unit;
+        let a = handle.axis;
+        match handle.is_max {
+            true => self.max[a] = (self.max[a] + moved).clamp(self.min[a] + MIN_CROP_EXTENT, 1.0),
+            false => self.min[a] = (self.min[a] + moved).clamp(0.0, self.max[a] - MIN_CROP_EXTENT),
+        }
+    }
+}
+
+/// Projects a point in volume coordinates to pixel coordinates with the origin
+/// in the top left, or `None` if it's behind the camera
+pub fn to_screen(
+    proj_view: &Matrix4<f32>,
+    canvas: [f32; 2],
+    point: Vector3<f32>,
+) -> Option<Vector2<f32>> {
+    let clip = proj_view * Vector4::new(point.x, point.y, point.z, 1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    let ndc = Vector2::new(clip.x / clip.w, clip.y / clip.w);
+    Some(Vector2::new(
+        (ndc.x * 0.5 + 0.5) * canvas[0],
+        (0.5 - ndc.y * 0.5) * canvas[1],
+    ))
+}
+
+/// Half space cut away from the volume. The plane passes through the volume
+/// centre moved by `offset` along its normal, which points at the removed side
+/// unless `invert` is set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipPlane {
+    pub enabled: bool,
+    pub invert: bool,
+    /// Orientation of the normal in degrees
+    pub azimuth: f32,
+    pub elevation: f32,
+    pub offset: f32,
+}
+
+impl Default for ClipPlane {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            invert: false,
+            azimuth: 0.0,
+            elevation: 0.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl ClipPlane {
+    /// Plane equation `(n, d)` whose kept side satisfies `dot(n, p) + d <= 0`
+    pub fn equation(&self) -> [f32; 4] {
+        let center = Vector3::new(0.5, 0.5, 0.5);
+        let normal = spherical_direction(self.azimuth, self.elevation);
+        let point = center + normal * self.offset;
+        let normal = match self.invert {
+            true => -normal,
+            false => normal,
+        };
+        [normal.x, normal.y, normal.z, -normal.dot(point)]
+    }
+}
+
+/// Shrinks the interval `(t0, t1)` of the ray from `origin` along `dir` to the
+/// kept side of each plane equation, as the fragment shader does. The result is
+/// empty, with `t0 > t1`, if nothing is kept.
+pub fn clip_interval(
+    origin: Vector3<f32>,
+    dir: Vector3<f32>,
+    planes: &[[f32; 4]],
+    (mut t0, mut t1): (f32, f32),
+) -> (f32, f32) {
+    for [x, y, z, w] in planes {
+        let normal = Vector3::new(*x, *y, *z);
+        let start = normal.dot(origin) + w;
+        let rate = normal.dot(dir);
+        if rate == 0.0 {
+            if start > 0.0 {
+                return (1.0, 0.0);
+            }
+            continue;
+        }
+        let t_cross = -start / rate;
+        match rate > 0.0 {
+            true => t1 = t1.min(t_cross),
+            false => t0 = t0.max(t_cross),
+        }
+    }
+    (t0, t1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clip_interval, ClipPlane, CropBox, CropHandle};
+    use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector2, Vector3, Vector4};
+
+    fn side(equation: [f32; 4], point: [f32; 3]) -> f32 {
+        Vector4::from(equation).dot(Vector4::new(point[0], point[1], point[2], 1.0))
+    }
+
+    #[test]
+    fn test_plane_keeps_side_away_from_normal() {
+        // Azimuth and elevation 0 point the normal along +z
+        let plane = ClipPlane {
+            enabled: true,
+            offset: 0.25,
+            ..Default::default()
+        };
+        let equation = plane.equation();
+        assert!(side(equation, [0.5, 0.5, 0.6]) < 0.0);
+        assert!(side(equation, [0.5, 0.5, 0.9]) > 0.0);
+
+        let inverted = ClipPlane {
+            invert: true,
+            ..plane
+        }
+        .equation();
+        assert!(side(inverted, [0.5, 0.5, 0.6]) > 0.0);
+        assert!(side(inverted, [0.5, 0.5, 0.9]) < 0.0);
+    }
+
+    #[test]
+    fn test_clip_interval() {
+        // Keeps z <= 0.5
+        let planes = [[0.0, 0.0, 1.0, -0.5]];
+        let origin = Vector3::new(0.5, 0.5, 0.0);
+        let along_z = Vector3::new(0.0, 0.0, 1.0);
+        assert_eq!(
+            clip_interval(origin, along_z, &planes, (0.0, 1.0)),
+            (0.0, 0.5)
+        );
+        assert_eq!(
+            clip_interval(origin + along_z, -along_z, &planes, (0.0, 1.0)),
+            (0.5, 1.0)
+        );
+
+        // Rays parallel to the plane are kept or cut away whole
+        let along_x = Vector3::new(1.0, 0.0, 0.0);
+        assert_eq!(
+            clip_interval(origin, along_x, &planes, (0.0, 1.0)),
+            (0.0, 1.0)
+        );
+        let (t0, t1) = clip_interval(origin + along_z, along_x, &planes, (0.0, 1.0));
+        assert!(t0 > t1);
+    }
+
+    #[test]
+    fn test_drag_max_x_face() {
+        // Identity maps the volume's x axis across the right half of the screen
+        let identity = Matrix4::identity();
+        let mut crop_box = CropBox::default();
+        let handle = CropHandle {
+            axis: 0,
+            is_max: true,
+        };
+        assert_eq!(
+            crop_box.pick_handle(&identity, [200.0, 200.0], Vector2::new(198.0, 52.0)),
+            Some(handle)
+        );
+        crop_box.drag_handle(handle, &identity, [200.0, 200.0], Vector2::new(-25.0, 7.0));
+        assert_eq!(crop_box.max, [0.75, 1.0, 1.0]);
+        crop_box.drag_handle(handle, &identity, [200.0, 200.0], Vector2::new(-500.0, 0.0));
+        assert_eq!(crop_box.max[0], crop_box.min[0] + 0.01);
+    }
+}
@@ -0,0 +1,795 @@
+use anyhow::{Context, Result};
+use std::time::Duration;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use wasm_timer::Instant;
+use web_sys::*;
+
+use crate::animation::ExportSettings;
+use crate::app_state::{AppState, GradientSource, RenderMode};
+use crate::camera::{CameraBookmark, TurntableAxis, ViewPreset};
+use crate::capture::CaptureSettings;
+use crate::fusion::FusionMode;
+use crate::keyboard::KeyAction;
+use crate::labels::{parse_hex_color, parse_label_descriptions, LabelEntry};
+use crate::mesh::Mesh;
+use crate::projection::ProjectionMode;
+use crate::reslice::{SlabMode, RESLICE_EXPORT_SIZE};
+use crate::util::{download_bytes, load_from_storage, save_to_storage, LogErrWasm};
+use crate::Error;
+use crate::SharedMut;
+
+fn input_element(event: Event) -> Result<HtmlInputElement> {
+    event
+        .target()
+        .ok_or(Error::MissingItem)
+        .context("Failed to get event target for input event")?
+        .dyn_into::<HtmlInputElement>()
+        .map_err(|_| Error::JsCast)
+        .context("Failed to convert event target to input element")
+}
+
+fn select_element(event: Event) -> Result<HtmlSelectElement> {
+    event
+        .target()
+        .ok_or(Error::MissingItem)
+        .context("Failed to get event target for select event")?
+        .dyn_into::<HtmlSelectElement>()
+        .map_err(|_| Error::JsCast)
+        .context("Failed to convert event target to select element")
+}
+
+fn slider_value(event: Event) -> Result<f32> {
+    let input = input_element(event)?;
+    input
+        .value()
+        .parse()
+        .with_context(|| format!("Slider value {} is not a number", input.value()))
+}
+
+pub fn render_mode_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let mode = match select_element(event)?.value().as_str() {
+        "isosurface" => RenderMode::Isosurface,
+        _ => RenderMode::Dvr,
+    };
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in render mode handler")?;
+    app_state.set_render_mode(mode);
+    Ok(())
+}
+
+pub fn iso_value_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let iso_value = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in iso value handler")?;
+    app_state.set_iso_value(iso_value);
+    Ok(())
+}
+
+pub fn headlight_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let headlight = input_element(event)?.checked();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in headlight handler")?;
+    app_state.set_headlight(headlight);
+    Ok(())
+}
+
+pub fn light_azimuth_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let azimuth = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in light azimuth handler")?;
+    app_state.set_light_azimuth(azimuth);
+    Ok(())
+}
+
+pub fn light_elevation_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let elevation = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in light elevation handler")?;
+    app_state.set_light_elevation(elevation);
+    Ok(())
+}
+
+pub fn dvr_shading_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let dvr_shading = input_element(event)?.checked();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in shading handler")?;
+    app_state.set_dvr_shading(dvr_shading);
+    Ok(())
+}
+
+pub fn gradient_source_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let gradient_source = match select_element(event)?.value().as_str() {
+        "on-the-fly" => GradientSource::OnTheFly,
+        _ => GradientSource::Precomputed,
+    };
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in gradient source handler")?;
+    app_state.set_gradient_source(gradient_source);
+    Ok(())
+}
+
+pub fn sampling_rate_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let sampling_rate = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in sampling rate handler")?;
+    app_state.set_sampling_rate(sampling_rate);
+    Ok(())
+}
+
+pub fn ert_threshold_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let ert_threshold = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in early ray termination handler")?;
+    app_state.set_ert_threshold(ert_threshold);
+    Ok(())
+}
+
+pub fn adaptive_sampling_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let enabled = input_element(event)?.checked();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in adaptive sampling handler")?;
+    app_state.set_adaptive_sampling(enabled);
+    Ok(())
+}
+
+pub fn target_frame_time_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let target_frame_time_ms = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in target frame time handler")?;
+    app_state.set_target_frame_time(Duration::from_secs_f32(
+        target_frame_time_ms.max(1.0) / 1000.0,
+    ));
+    Ok(())
+}
+
+pub fn skip_empty_space_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let skip_empty_space = input_element(event)?.checked();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in empty space skipping handler")?;
+    app_state.set_skip_empty_space(skip_empty_space);
+    Ok(())
+}
+
+pub fn opacity_window_low_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let low = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in opacity window handler")?;
+    app_state.set_opacity_window_low(low);
+    Ok(())
+}
+
+pub fn opacity_window_high_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let high = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in opacity window handler")?;
+    app_state.set_opacity_window_high(high);
+    Ok(())
+}
+
+pub fn max_opacity_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let max_opacity = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in max opacity handler")?;
+    app_state.set_max_opacity(max_opacity);
+    Ok(())
+}
+
+pub fn projection_mode_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let mode = match select_element(event)?.value().as_str() {
+        "orthographic" => ProjectionMode::Orthographic,
+        _ => ProjectionMode::Perspective,
+    };
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in projection mode handler")?;
+    app_state.update_projection(|projection| projection.mode = mode);
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+pub enum ProjectionField {
+    Fov,
+    Near,
+    Far,
+}
+
+pub fn projection_handler(
+    event: Event,
+    app_state: &SharedMut<AppState>,
+    field: ProjectionField,
+) -> Result<()> {
+    let value = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in projection handler")?;
+    app_state.update_projection(|projection| match field {
+        ProjectionField::Fov => projection.fov = value,
+        ProjectionField::Near => projection.near = value,
+        ProjectionField::Far => projection.far = value,
+    });
+    Ok(())
+}
+
+pub fn view_preset_handler(app_state: &SharedMut<AppState>, preset: ViewPreset) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in view preset handler")?;
+    app_state.set_view_preset(preset);
+    Ok(())
+}
+
+pub fn fit_volume_handler(app_state: &SharedMut<AppState>) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in fit volume handler")?;
+    app_state.fit_volume();
+    Ok(())
+}
+
+/// Local storage key the camera bookmarks are kept under between sessions
+const BOOKMARKS_STORAGE_KEY: &str = "volumetric-renderer.camera-bookmarks";
+
+/// Loads the bookmarks saved in an earlier session and returns their names
+pub fn restore_bookmarks(app_state: &SharedMut<AppState>) -> Result<Vec<String>> {
+    let Some(json) = load_from_storage(BOOKMARKS_STORAGE_KEY)? else {
+        return Ok(Vec::new());
+    };
+    let bookmarks: Vec<CameraBookmark> =
+        serde_json::from_str(&json).context("Saved camera bookmarks are invalid")?;
+    let names = bookmarks
+        .iter()
+        .map(|bookmark| bookmark.name.clone())
+        .collect();
+    app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to restore bookmarks")?
+        .set_bookmarks(bookmarks);
+    Ok(names)
+}
+
+/// Saves the bookmarks for later sessions and returns their names
+fn store_bookmarks(app_state: &AppState) -> Result<Vec<String>> {
+    let bookmarks = app_state.get_bookmarks();
+    let json = serde_json::to_string(&bookmarks).context("Failed to serialise bookmarks")?;
+    save_to_storage(BOOKMARKS_STORAGE_KEY, &json)?;
+    Ok(bookmarks
+        .into_iter()
+        .map(|bookmark| bookmark.name)
+        .collect())
+}
+
+pub fn add_bookmark_handler(app_state: &SharedMut<AppState>, name: String) -> Result<Vec<String>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in add bookmark handler")?;
+    app_state.add_bookmark(name);
+    store_bookmarks(&app_state)
+}
+
+pub fn remove_bookmark_handler(app_state: &SharedMut<AppState>, name: &str) -> Result<Vec<String>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in remove bookmark handler")?;
+    app_state.remove_bookmark(name);
+    store_bookmarks(&app_state)
+}
+
+pub fn go_to_bookmark_handler(app_state: &SharedMut<AppState>, name: &str) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in go to bookmark handler")?;
+    app_state.go_to_bookmark(name, Instant::now())
+}
+
+pub fn transition_duration_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let seconds = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in transition duration handler")?;
+    app_state.set_transition_duration(Duration::from_secs_f32(seconds.max(0.0)));
+    Ok(())
+}
+
+pub fn add_keyframe_handler(app_state: &SharedMut<AppState>, time: f32) -> Result<Vec<f32>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in add keyframe handler")?;
+    Ok(app_state.add_keyframe(time.max(0.0)))
+}
+
+pub fn remove_keyframe_handler(app_state: &SharedMut<AppState>, index: usize) -> Result<Vec<f32>> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in remove keyframe handler")?;
+    Ok(app_state.remove_keyframe(index))
+}
+
+/// Starts rendering the keyframed animation, which is downloaded as a ZIP of
+/// PNG frames once done
+pub fn export_frames_handler(
+    app_state: &SharedMut<AppState>,
+    settings: ExportSettings,
+) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in export frames handler")?;
+    app_state.start_frame_export(settings)
+}
+
+/// Asks for a still of the current view, rendered offscreen at `settings`
+/// and downloaded as a PNG
+pub fn capture_handler(app_state: &SharedMut<AppState>, settings: CaptureSettings) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in capture handler")?;
+    app_state.request_capture(settings);
+    Ok(())
+}
+
+pub fn render_scale_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let render_scale = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in render scale handler")?;
+    app_state.set_render_scale(render_scale);
+    Ok(())
+}
+
+pub fn turntable_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let enabled = input_element(event)?.checked();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in turntable handler")?;
+    app_state.set_turntable_enabled(enabled);
+    Ok(())
+}
+
+pub fn turntable_axis_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let axis = match select_element(event)?.value().as_str() {
+        "x" => TurntableAxis::X,
+        "y" => TurntableAxis::Y,
+        "z" => TurntableAxis::Z,
+        "screen-right" => TurntableAxis::ScreenRight,
+        _ => TurntableAxis::ScreenUp,
+    };
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in turntable axis handler")?;
+    app_state.set_turntable_axis(axis);
+    Ok(())
+}
+
+pub fn turntable_speed_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let speed = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in turntable speed handler")?;
+    app_state.set_turntable_speed(speed);
+    Ok(())
+}
+
+/// Binds the key pressed in a binding's input to `action`. Backspace or
+/// Delete unbind it and Escape leaves it as it was. Returns the bindings to
+/// show.
+pub fn bind_key_handler(
+    event: Event,
+    app_state: &SharedMut<AppState>,
+    action: KeyAction,
+) -> Result<Vec<(KeyAction, Option<String>)>> {
+    let keyboard_event = event
+        .dyn_into::<KeyboardEvent>()
+        .map_err(|_| Error::JsCast)
+        .context("Failed to read event as keyboard event in bind key handler")?;
+    let key = keyboard_event.key();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in bind key handler")?;
+    match key.as_str() {
+        // Leave Tab to move between the inputs
+        "Tab" | "Escape" => {}
+        "Backspace" | "Delete" => app_state.bind_key(action, None),
+        _ => app_state.bind_key(action, Some(key)),
+    }
+    if keyboard_event.key() != "Tab" {
+        keyboard_event.prevent_default();
+    }
+    Ok(app_state.get_key_bindings().bindings().to_vec())
+}
+
+pub fn get_key_bindings(
+    app_state: &SharedMut<AppState>,
+) -> Result<Vec<(KeyAction, Option<String>)>> {
+    let app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state to get key bindings")?;
+    Ok(app_state.get_key_bindings().bindings().to_vec())
+}
+
+pub fn crop_editing_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let crop_editing = input_element(event)?.checked();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in crop editing handler")?;
+    app_state.set_crop_editing(crop_editing);
+    Ok(())
+}
+
+pub fn reset_crop_handler(app_state: &SharedMut<AppState>) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in reset crop handler")?;
+    app_state.reset_crop_box();
+    Ok(())
+}
+
+/// Which property of a clipping plane a control edits
+#[derive(Clone, Copy, Debug)]
+pub enum ClipPlaneField {
+    Enabled,
+    Invert,
+    Azimuth,
+    Elevation,
+    Offset,
+}
+
+pub fn clip_plane_handler(
+    event: Event,
+    app_state: &SharedMut<AppState>,
+    index: usize,
+    field: ClipPlaneField,
+) -> Result<()> {
+    let input = input_element(event)?;
+    let value = match field {
+        ClipPlaneField::Enabled | ClipPlaneField::Invert => 0.0,
+        _ => input
+            .value()
+            .parse()
+            .with_context(|| format!("Slider value {} is not a number", input.value()))?,
+    };
+    let checked = input.checked();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in clip plane handler")?;
+    app_state.update_clip_plane(index, |plane| match field {
+        ClipPlaneField::Enabled => plane.enabled = checked,
+        ClipPlaneField::Invert => plane.invert = checked,
+        ClipPlaneField::Azimuth => plane.azimuth = value,
+        ClipPlaneField::Elevation => plane.elevation = value,
+        ClipPlaneField::Offset => plane.offset = value,
+    });
+    Ok(())
+}
+
+pub fn slice_planes_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let show_slice_planes = input_element(event)?.checked();
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in slice planes handler")?;
+    app_state.set_show_slice_planes(show_slice_planes);
+    Ok(())
+}
+
+pub fn slab_thickness_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let thickness = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in slab thickness handler")?;
+    app_state.set_slab_thickness(thickness);
+    Ok(())
+}
+
+pub fn slab_mode_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let mode = match select_element(event)?.value().as_str() {
+        "average" => SlabMode::Average,
+        _ => SlabMode::Mip,
+    };
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in slab mode handler")?;
+    app_state.set_slab_mode(mode);
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ResliceFormat {
+    Png,
+    /// Unsigned 8 bit values, top row first, like the volume files
+    Raw,
+}
+
+/// Resamples the oblique slice on the CPU and downloads its raw values, not
+/// windowed or coloured as in the oblique view
+pub fn export_reslice_handler(
+    app_state: &SharedMut<AppState>,
+    format: ResliceFormat,
+) -> Result<()> {
+    let image = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in export reslice handler")?
+        .reslice_oblique(RESLICE_EXPORT_SIZE)
+        .ok_or(Error::MissingItem)
+        .context("No volume loaded to reslice")?;
+    match format {
+        ResliceFormat::Png => download_bytes("oblique.png", "image/png", &image.to_png()?),
+        ResliceFormat::Raw => download_bytes(
+            &format!("oblique_{}x{}_uint8.raw", image.width, image.height),
+            "application/octet-stream",
+            &image.data,
+        ),
+    }
+}
+
+/// Loads the STL or OBJ files picked in a file input as meshes
+pub fn mesh_file_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let files = input_element(event)?
+        .files()
+        .ok_or(Error::MissingItem)
+        .context("Mesh file input has no files")?;
+    for file in (0..files.length()).filter_map(|index| files.get(index)) {
+        let app_state = app_state.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            load_mesh_file(file, &app_state).await.log_err()
+        });
+    }
+    Ok(())
+}
+
+/// First file picked in a file input
+pub fn picked_file(event: Event) -> Result<File> {
+    input_element(event)?
+        .files()
+        .and_then(|files| files.get(0))
+        .ok_or(Error::MissingItem)
+        .context("No file picked")
+}
+
+async fn read_file(file: &File) -> Result<Vec<u8>> {
+    let buffer = JsFuture::from(file.array_buffer())
+        .await
+        .map_err(|_| Error::JsCast)
+        .with_context(|| format!("Failed to read {}", file.name()))?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+async fn load_mesh_file(file: File, app_state: &SharedMut<AppState>) -> Result<()> {
+    let bytes = read_file(&file).await?;
+    let mesh = Mesh::from_file(&file.name(), &bytes)
+        .with_context(|| format!("Failed to parse mesh {}", file.name()))?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in mesh file handler")?;
+    app_state.add_mesh(file.name(), mesh);
+    Ok(())
+}
+
+pub fn clear_meshes_handler(app_state: &SharedMut<AppState>) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in clear meshes handler")?;
+    app_state.clear_meshes();
+    Ok(())
+}
+
+/// Loads a raw label map and returns the label table to show
+pub async fn load_label_map_file(
+    file: File,
+    app_state: &SharedMut<AppState>,
+) -> Result<Vec<LabelEntry>> {
+    let bytes = read_file(&file).await?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in label map handler")?;
+    app_state
+        .load_label_map(&bytes)
+        .with_context(|| format!("Failed to load label map {}", file.name()))?;
+    Ok(app_state.get_labels().1.table.entries)
+}
+
+/// Loads label names, colours and visibility from a JSON or ITK-SNAP label
+/// description file and returns the label table to show
+pub async fn load_label_descriptions_file(
+    file: File,
+    app_state: &SharedMut<AppState>,
+) -> Result<Vec<LabelEntry>> {
+    let bytes = read_file(&file).await?;
+    let text = String::from_utf8(bytes).context("Label descriptions are not valid UTF-8")?;
+    let descriptions = parse_label_descriptions(&file.name(), &text)
+        .with_context(|| format!("Failed to parse label descriptions {}", file.name()))?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in label descriptions handler")?;
+    app_state.apply_label_descriptions(descriptions);
+    Ok(app_state.get_labels().1.table.entries)
+}
+
+#[derive(Clone, Copy)]
+pub enum LabelField {
+    Visible,
+    Color,
+    Opacity,
+}
+
+pub fn label_handler(
+    event: Event,
+    app_state: &SharedMut<AppState>,
+    value: u8,
+    field: LabelField,
+) -> Result<()> {
+    let input = input_element(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in label handler")?;
+    match field {
+        LabelField::Visible => {
+            let visible = input.checked();
+            app_state.update_label(value, |entry| entry.visible = visible);
+        }
+        LabelField::Color => {
+            let color = parse_hex_color(&input.value())
+                .ok_or(Error::MissingItem)
+                .with_context(|| format!("{} is not a colour", input.value()))?;
+            app_state.update_label(value, |entry| entry.color = color);
+        }
+        LabelField::Opacity => {
+            let opacity = input
+                .value()
+                .parse()
+                .with_context(|| format!("Slider value {} is not a number", input.value()))?;
+            app_state.update_label(value, |entry| entry.opacity = opacity);
+        }
+    }
+    Ok(())
+}
+
+pub fn label_blend_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let blend = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in label blend handler")?;
+    app_state.set_label_blend(blend);
+    Ok(())
+}
+
+/// Loads the raw volume picked in a file input as the second volume of a
+/// fusion
+pub fn fusion_file_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let file = picked_file(event)?;
+    let app_state = app_state.clone();
+    wasm_bindgen_futures::spawn_local(
+        async move { load_fusion_file(file, &app_state).await.log_err() },
+    );
+    Ok(())
+}
+
+async fn load_fusion_file(file: File, app_state: &SharedMut<AppState>) -> Result<()> {
+    let bytes = read_file(&file).await?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in fusion file handler")?;
+    app_state
+        .load_secondary_volume(&file.name(), bytes)
+        .with_context(|| format!("Failed to load fusion volume {}", file.name()))
+}
+
+pub fn fusion_mode_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let mode = match select_element(event)?.value().as_str() {
+        "additive" => FusionMode::Additive,
+        "mask" => FusionMode::Mask,
+        _ => FusionMode::AlphaBlend,
+    };
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in fusion mode handler")?;
+    app_state.set_fusion_mode(mode);
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+pub enum FusionField {
+    Weight,
+    WindowLow,
+    WindowHigh,
+    MaxOpacity,
+    Offset(usize),
+    Rotation(usize),
+    Scale,
+}
+
+pub fn fusion_handler(
+    event: Event,
+    app_state: &SharedMut<AppState>,
+    field: FusionField,
+) -> Result<()> {
+    let value = slider_value(event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in fusion handler")?;
+    match field {
+        FusionField::Weight => app_state.set_fusion_weight(value),
+        FusionField::WindowLow => {
+            app_state.update_secondary_transfer_function(|tf| tf.window.0 = value)
+        }
+        FusionField::WindowHigh => {
+            app_state.update_secondary_transfer_function(|tf| tf.window.1 = value)
+        }
+        FusionField::MaxOpacity => {
+            app_state.update_secondary_transfer_function(|tf| tf.max_opacity = value)
+        }
+        FusionField::Offset(axis) => {
+            app_state.update_fusion_transform(|transform| transform.offset[axis] = value)
+        }
+        FusionField::Rotation(axis) => {
+            app_state.update_fusion_transform(|transform| transform.rotation[axis] = value)
+        }
+        FusionField::Scale => {
+            app_state.update_fusion_transform(|transform| transform.scale = value)
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,417 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4, Zero};
+
+use crate::app_state::{DrawData, RenderMode, RenderSettings};
+use crate::clipping::clip_interval;
+use crate::image::RgbaImage;
+use crate::transfer_function::{TransferFunction, TRANSFER_FUNCTION_SIZE};
+use crate::volume::Volume;
+
+/// Normalised gradient magnitude above which a sample is fully shaded
+const GRADIENT_SATURATION: f32 = 0.1;
+
+/// Ray in volume space, where the volume fills the unit cube
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    /// Unit direction
+    pub dir: Vector3<f32>,
+}
+
+impl Ray {
+    /// Ray through the centre of pixel `(x, y)`, counted from the bottom left,
+    /// set up like the vertex shader does for the fragment there
+    pub fn through_pixel(
+        draw_data: &DrawData,
+        inv_proj_view: &Matrix4<f32>,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+    ) -> Self {
+        let ndc_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+        let ndc_y = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+        let point = inv_proj_view * Vector4::new(ndc_x, ndc_y, 0.0, 1.0);
+        let point = point.truncate() / point.w;
+        let eye = draw_data.eye_pos;
+        match draw_data.orthographic {
+            true => {
+                let dir = draw_data.view_dir.normalize();
+                Self {
+                    origin: point - (point - eye).dot(dir) * dir,
+                    dir,
+                }
+            }
+            false => Self {
+                origin: eye,
+                dir: (point - eye).normalize(),
+            },
+        }
+    }
+}
+
+/// Ray marches a volume on the CPU the same way `FRAG_SHADER` does, for
+/// reference images and where WebGL is not available. Labels, fused volumes
+/// and meshes are not drawn, and shading always takes gradients on the fly.
+/// Empty space skipping is left out as it does not change the image.
+pub struct CpuRenderer<'a> {
+    volume: &'a Volume,
+    settings: &'a RenderSettings,
+    /// RGBA8 lookup table, as uploaded to the colormap texture
+    colormap: Vec<u8>,
+    /// Maps gradients of the normalised volume to unit length at the steepest
+    gradient_scale: f32,
+    /// Equations of the enabled clipping planes
+    clip_planes: Vec<[f32; 4]>,
+}
+
+impl<'a> CpuRenderer<'a> {
+    pub fn new(
+        volume: &'a Volume,
+        transfer_function: &TransferFunction,
+        settings: &'a RenderSettings,
+    ) -> Self {
+        let max_gradient_magnitude = volume.max_gradient_magnitude();
+        let gradient_scale = match max_gradient_magnitude > 0.0 {
+            true => 255.0 / max_gradient_magnitude,
+            false => 0.0,
+        };
+        let clip_planes = settings
+            .clip_planes
+            .iter()
+            .filter(|plane| plane.enabled)
+            .map(|plane| plane.equation())
+            .collect();
+        Self {
+            volume,
+            settings,
+            colormap: transfer_function.lookup_table(),
+            gradient_scale,
+            clip_planes,
+        }
+    }
+
+    /// Renders a `width` by `height` image of the view in `draw_data`,
+    /// averaging `samples` jittered frames like the accumulation buffer does
+    /// and compositing the result over the premultiplied `background`
+    pub fn render(
+        &self,
+        draw_data: &DrawData,
+        width: usize,
+        height: usize,
+        samples: u32,
+        background: [f32; 4],
+    ) -> RgbaImage {
+        let dt_scale = 1.0 / self.settings.sampling_rate;
+        let samples = samples.max(1);
+        let mut sum = vec![Vector4::zero(); width * height];
+        for frame_seed in 0..samples as i32 {
+            let frame = self.render_frame(draw_data, (width, height), frame_seed, dt_scale);
+            for (sum, color) in sum.iter_mut().zip(frame) {
+                *sum += color;
+            }
+        }
+        let average: Vec<_> = sum.iter().map(|color| color / samples as f32).collect();
+        compose(&average, width, height, background)
+    }
+
+    /// Premultiplied linear colour of each pixel, bottom row first, of one
+    /// frame jittered by `frame_seed` and sampled `dt_scale` voxels apart
+    pub fn render_frame(
+        &self,
+        draw_data: &DrawData,
+        (width, height): (usize, usize),
+        frame_seed: i32,
+        dt_scale: f32,
+    ) -> Vec<Vector4<f32>> {
+        let inv_proj_view = draw_data
+            .proj_view
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+        let mut colors = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let ray = Ray::through_pixel(draw_data, &inv_proj_view, (x, y), (width, height));
+                let pixel = x as i32 + width as i32 * y as i32;
+                let offset = wang_hash(pixel ^ frame_seed.wrapping_mul(0x01000193));
+                colors.push(self.trace(&ray, dt_scale, offset));
+            }
+        }
+        colors
+    }
+
+    /// Premultiplied linear colour along `ray`, with samples `dt_scale` voxels
+    /// apart shifted by `offset` steps. Transparent where the ray misses the
+    /// cropped and clipped volume.
+    pub fn trace(&self, ray: &Ray, dt_scale: f32, offset: f32) -> Vector4<f32> {
+        let crop_box = &self.settings.crop_box;
+        let (t_min, t_max) = intersect_box(ray, crop_box.min.into(), crop_box.max.into());
+        let (t_min, t_max) = clip_interval(ray.origin, ray.dir, &self.clip_planes, (t_min, t_max));
+        let t_min = t_min.max(0.0);
+        if t_min > t_max {
+            return Vector4::zero();
+        }
+        let dims = self.volume.dims();
+        let dt = (0..3)
+            .map(|axis| 1.0 / (dims[axis] as f32 * ray.dir[axis].abs()))
+            .fold(f32::INFINITY, f32::min)
+            * dt_scale;
+        match self.settings.mode {
+            RenderMode::Dvr => self.march_volume(ray, (t_min, t_max), dt, dt_scale, offset),
+            RenderMode::Isosurface => self.march_isosurface(ray, (t_min, t_max), dt, offset),
+        }
+    }
+
+    fn march_volume(
+        &self,
+        ray: &Ray,
+        (t_min, t_max): (f32, f32),
+        dt: f32,
+        dt_scale: f32,
+        offset: f32,
+    ) -> Vector4<f32> {
+        let mut color = Vector4::zero();
+        let mut p = ray.origin + (t_min + offset * dt) * ray.dir;
+        let mut t = t_min;
+        while t < t_max {
+            let mut val_color = self.colormap(self.value(p));
+            if self.settings.dvr_shading {
+                let grad = self.gradient(p) * self.gradient_scale;
+                let magnitude = grad.magnitude();
+                if magnitude > 0.0 {
+                    let base = val_color.truncate();
+                    let lit = self.blinn_phong(base, -grad / magnitude, ray.dir);
+                    let shading = (magnitude / GRADIENT_SATURATION).min(1.0);
+                    val_color = (base + (lit - base) * shading).extend(val_color.w);
+                }
+            }
+            // Opacity correction
+            let alpha = 1.0 - (1.0 - val_color.w).powf(dt_scale);
+            let weight = (1.0 - color.w) * alpha;
+            color += (val_color.truncate() * weight).extend(weight);
+            if color.w >= self.settings.ert_threshold {
+                break;
+            }
+            p += ray.dir * dt;
+            t += dt;
+        }
+        color
+    }
+
+    fn march_isosurface(
+        &self,
+        ray: &Ray,
+        (t_min, t_max): (f32, f32),
+        dt: f32,
+        offset: f32,
+    ) -> Vector4<f32> {
+        let iso_value = self.settings.iso_value / 255.0;
+        let at = |t: f32| self.value(ray.origin + t * ray.dir);
+        let mut t = t_min + offset * dt;
+        let mut prev_t = t;
+        let mut prev_val = at(t);
+        while t < t_max {
+            let val = at(t);
+            if (prev_val < iso_value) != (val < iso_value) {
+                // Refine the crossing by bisecting the last step
+                let (mut t_lo, mut t_hi) = (prev_t, t);
+                for _ in 0..6 {
+                    let t_mid = 0.5 * (t_lo + t_hi);
+                    match (at(t_mid) < iso_value) == (prev_val < iso_value) {
+                        true => t_lo = t_mid,
+                        false => t_hi = t_mid,
+                    }
+                }
+                let hit = ray.origin + 0.5 * (t_lo + t_hi) * ray.dir;
+                let grad = self.gradient(hit);
+                let normal = match grad.magnitude() > 0.0 {
+                    true => -grad.normalize(),
+                    false => -ray.dir,
+                };
+                let base_color = self.colormap(iso_value).truncate();
+                return self.blinn_phong(base_color, normal, ray.dir).extend(1.0);
+            }
+            prev_t = t;
+            prev_val = val;
+            t += dt;
+        }
+        Vector4::zero()
+    }
+
+    /// Normalised volume value at `p`, clamped to the edge like the texture
+    fn value(&self, p: Vector3<f32>) -> f32 {
+        let point = [p.x, p.y, p.z].map(|c| c.clamp(0.0, 1.0));
+        self.volume.sample(point).unwrap_or(0.0) / 255.0
+    }
+
+    /// Central differences per voxel of the normalised volume, pointing
+    /// towards increasing density
+    fn gradient(&self, p: Vector3<f32>) -> Vector3<f32> {
+        let dims = self.volume.dims();
+        let mut grad = Vector3::zero();
+        for axis in 0..3 {
+            let mut h = Vector3::zero();
+            h[axis] = 1.0 / dims[axis] as f32;
+            grad[axis] = (self.value(p + h) - self.value(p - h)) / 2.0;
+        }
+        grad
+    }
+
+    /// Linearly filtered colormap entry at `val`, like the colormap texture
+    fn colormap(&self, val: f32) -> Vector4<f32> {
+        let last = TRANSFER_FUNCTION_SIZE - 1;
+        let x = (val * TRANSFER_FUNCTION_SIZE as f32 - 0.5).clamp(0.0, last as f32);
+        let lo = x.floor() as usize;
+        let hi = (lo + 1).min(last);
+        let entry = |i: usize| {
+            let [r, g, b, a] = [0, 1, 2, 3].map(|c| self.colormap[4 * i + c] as f32 / 255.0);
+            Vector4::new(r, g, b, a)
+        };
+        entry(lo) + (entry(hi) - entry(lo)) * (x - lo as f32)
+    }
+
+    fn blinn_phong(
+        &self,
+        base_color: Vector3<f32>,
+        normal: Vector3<f32>,
+        ray_dir: Vector3<f32>,
+    ) -> Vector3<f32> {
+        let to_eye = -ray_dir;
+        let light = &self.settings.light;
+        let to_light = match light.headlight {
+            true => to_eye,
+            false => -light.direction().normalize(),
+        };
+        // Shade both sides of the surface the same way
+        let normal = match normal.dot(to_eye) < 0.0 {
+            true => -normal,
+            false => normal,
+        };
+        let half_vec = (to_light + to_eye).normalize();
+        let diffuse = normal.dot(to_light).max(0.0);
+        let specular = normal.dot(half_vec).max(0.0).powf(32.0);
+        base_color * (0.2 + 0.7 * diffuse) + Vector3::new(1.0, 1.0, 1.0) * (0.3 * specular)
+    }
+}
+
+/// Distances along `ray` to where it enters and leaves the box, which it
+/// misses when the first is larger
+fn intersect_box(ray: &Ray, box_min: Vector3<f32>, box_max: Vector3<f32>) -> (f32, f32) {
+    let mut t0 = f32::NEG_INFINITY;
+    let mut t1 = f32::INFINITY;
+    for axis in 0..3 {
+        let inv_dir = 1.0 / ray.dir[axis];
+        let t_lo = (box_min[axis] - ray.origin[axis]) * inv_dir;
+        let t_hi = (box_max[axis] - ray.origin[axis]) * inv_dir;
+        t0 = t0.max(t_lo.min(t_hi));
+        t1 = t1.min(t_lo.max(t_hi));
+    }
+    (t0, t1)
+}
+
+/// Converts premultiplied linear colours, bottom row first, to sRGB over the
+/// premultiplied `background` as the present pass does
+pub fn compose(
+    colors: &[Vector4<f32>],
+    width: usize,
+    height: usize,
+    background: [f32; 4],
+) -> RgbaImage {
+    let data = colors
+        .iter()
+        .flat_map(|color| {
+            let srgb = [
+                linear_to_srgb(color.x),
+                linear_to_srgb(color.y),
+                linear_to_srgb(color.z),
+                color.w,
+            ];
+            let mut pixel = [0; 4];
+            for ((out, channel), background) in pixel.iter_mut().zip(srgb).zip(background) {
+                let blended = channel + (1.0 - color.w) * background;
+                *out = (blended.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+            pixel
+        })
+        .collect();
+    RgbaImage::from_bottom_up(width, height, data)
+}
+
+/// The shader's per pixel jitter, with GLSL's wrapping integer arithmetic
+pub fn wang_hash(seed: i32) -> f32 {
+    let mut seed = (seed ^ 61) ^ (seed >> 16);
+    seed = seed.wrapping_mul(9);
+    seed ^= seed >> 4;
+    seed = seed.wrapping_mul(0x27d4eb2d);
+    seed ^= seed >> 15;
+    (seed % 2147483647) as f32 / 2147483647.0
+}
+
+pub fn linear_to_srgb(x: f32) -> f32 {
+    match x <= 0.0031308 {
+        true => 12.92 * x,
+        false => 1.055 * x.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CpuRenderer, Ray};
+    use crate::app_state::{DrawData, RenderSettings};
+    use crate::transfer_function::TransferFunction;
+    use crate::volume::Volume;
+    use cgmath::{Matrix4, SquareMatrix, Vector3};
+
+    fn uniform_volume() -> Volume {
+        Volume::new([4, 4, 4], vec![255; 64]).unwrap()
+    }
+
+    #[test]
+    fn test_trace_uniform_volume() {
+        let volume = uniform_volume();
+        let settings = RenderSettings {
+            ert_threshold: 1.0,
+            ..Default::default()
+        };
+        let renderer = CpuRenderer::new(&volume, &TransferFunction::default(), &settings);
+        let ray = Ray {
+            origin: Vector3::new(0.5, 0.5, -1.0),
+            dir: Vector3::new(0.0, 0.0, 1.0),
+        };
+        // Four samples of opacity 0.2 across four voxels
+        let color = renderer.trace(&ray, 1.0, 0.0);
+        assert!((color.w - (1.0 - 0.8f32.powi(4))).abs() < 1e-5);
+        // Half as far apart, each sample lets through the square root as much
+        let color = renderer.trace(&ray, 0.5, 0.0);
+        assert!((color.w - (1.0 - 0.8f32.powi(4))).abs() < 1e-5);
+
+        let miss = Ray {
+            origin: Vector3::new(2.0, 0.5, -1.0),
+            ..ray
+        };
+        assert_eq!(renderer.trace(&miss, 1.0, 0.0).w, 0.0);
+    }
+
+    #[test]
+    fn test_render_over_background() {
+        let volume = uniform_volume();
+        let settings = RenderSettings {
+            ert_threshold: 1.0,
+            ..Default::default()
+        };
+        let renderer = CpuRenderer::new(&volume, &TransferFunction::default(), &settings);
+        // Clip space is volume space, so the volume fills the top right quarter
+        let draw_data = DrawData {
+            proj_view: Matrix4::identity(),
+            eye_pos: Vector3::new(0.5, 0.5, -5.0),
+            view_dir: Vector3::new(0.0, 0.0, 1.0),
+            orthographic: true,
+            crop_handles: None,
+            slice_planes: None,
+        };
+        let image = renderer.render(&draw_data, 4, 4, 2, [0.0; 4]);
+        let pixel = |x: usize, y: usize| &image.data[(y * 4 + x) * 4..(y * 4 + x) * 4 + 4];
+        assert_eq!(pixel(0, 3), &[0; 4]);
+        assert_eq!(pixel(3, 0)[3], 151);
+
+        let image = renderer.render(&draw_data, 4, 4, 1, [1.0; 4]);
+        assert_eq!(image.data[(3 * 4) * 4..(3 * 4) * 4 + 4], [255; 4]);
+    }
+}
@@ -0,0 +1,288 @@
+use cgmath::{Deg, Matrix4, SquareMatrix, Vector3};
+use std::sync::Arc;
+
+use crate::transfer_function::TransferFunction;
+use crate::volume::{PatientOrientation, Volume};
+
+/// How samples of the second volume are combined with the first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FusionMode {
+    /// The second volume is composited over the first, its opacity scaled by
+    /// the weight
+    AlphaBlend,
+    /// Colours and opacities are summed, the second's scaled by the weight
+    Additive,
+    /// Only parts of the first volume the second gives some opacity are
+    /// shown, with the weight setting how much the rest is hidden
+    Mask,
+}
+
+/// Registration of the second volume on top of where the volumes' model
+/// matrices place it, in the first's volume coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FusionTransform {
+    /// Shift of the box's centre, in volume units
+    pub offset: [f32; 3],
+    /// Degrees turned about the x, then y, then z axis through the centre
+    pub rotation: [f32; 3],
+    pub scale: f32,
+}
+
+impl Default for FusionTransform {
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: 1.0,
+        }
+    }
+}
+
+impl FusionTransform {
+    /// Moves points in the first volume's texture coordinates as the second
+    /// volume is moved
+    pub fn model(&self) -> Matrix4<f32> {
+        let center = Vector3::new(0.5, 0.5, 0.5);
+        let [x, y, z] = self.rotation;
+        Matrix4::from_translation(center + Vector3::from(self.offset))
+            * Matrix4::from_angle_z(Deg(z))
+            * Matrix4::from_angle_y(Deg(y))
+            * Matrix4::from_angle_x(Deg(x))
+            * Matrix4::from_scale(self.scale)
+            * Matrix4::from_translation(-center)
+    }
+}
+
+/// A second volume co-registered with the first, drawn with its own
+/// transfer function
+#[derive(Clone, Debug)]
+pub struct SecondaryVolume {
+    pub volume: Arc<Volume>,
+    pub transfer_function: TransferFunction,
+}
+
+/// Everything the second volume is drawn from
+#[derive(Clone, Debug)]
+pub struct Fusion {
+    pub secondary: Option<SecondaryVolume>,
+    pub mode: FusionMode,
+    pub weight: f32,
+    pub transform: FusionTransform,
+}
+
+impl Fusion {
+    /// Maps texture coordinates of the first volume, placed in patient
+    /// coordinates by `primary_model`, to the second's. The grids may differ
+    /// in shape and voxel size, as each volume keeps its own model matrix.
+    pub fn secondary_inv_model(&self, primary_model: &Matrix4<f32>) -> Matrix4<f32> {
+        let Some(secondary) = &self.secondary else {
+            return Matrix4::identity();
+        };
+        let invert = |matrix: Matrix4<f32>| matrix.invert().unwrap_or_else(Matrix4::identity);
+        invert(secondary.volume.model()) * primary_model * invert(self.transform.model())
+    }
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Self {
+            secondary: None,
+            mode: FusionMode::AlphaBlend,
+            weight: 0.5,
+            transform: FusionTransform::default(),
+        }
+    }
+}
+
+/// Combines a sample of each volume, given as colour and opacity before
+/// opacity correction, the same way the ray marcher does
+pub fn combine_samples(
+    mode: FusionMode,
+    weight: f32,
+    first: [f32; 4],
+    second: [f32; 4],
+) -> [f32; 4] {
+    let [r, g, b, a] = first;
+    let [sr, sg, sb, sa] = second;
+    let (premultiplied, alpha) = match mode {
+        FusionMode::AlphaBlend => {
+            let sa = sa * weight;
+            let alpha = sa + a * (1.0 - sa);
+            let color = [
+                sr * sa + r * a * (1.0 - sa),
+                sg * sa + g * a * (1.0 - sa),
+                sb * sa + b * a * (1.0 - sa),
+            ];
+            (color, alpha)
+        }
+        FusionMode::Additive => {
+            let sa = sa * weight;
+            (
+                [r * a + sr * sa, g * a + sg * sa, b * a + sb * sa],
+                (a + sa).min(1.0),
+            )
+        }
+        FusionMode::Mask => {
+            let kept = match sa > 0.0 {
+                true => 1.0,
+                false => 1.0 - weight,
+            };
+            return [r, g, b, a * kept];
+        }
+    };
+    match alpha > 0.0 {
+        true => {
+            let [r, g, b] = premultiplied.map(|c| (c / alpha).min(1.0));
+            [r, g, b, alpha]
+        }
+        false => [0.0; 4],
+    }
+}
+
+/// Dimensions written into a raw file's name as `<x>x<y>x<z>`, such as
+/// `pet_128x128x64_uint8.raw`
+pub fn parse_raw_dims(filename: &str) -> Option<[usize; 3]> {
+    filename
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .find_map(|part| {
+            let mut sizes = part.split('x').map(|size| size.parse::<usize>().ok());
+            match (sizes.next(), sizes.next(), sizes.next(), sizes.next()) {
+                (Some(Some(x)), Some(Some(y)), Some(Some(z)), None) => Some([x, y, z]),
+                _ => None,
+            }
+        })
+}
+
+/// Voxel size written into a raw file's name as `<x>x<y>x<z>mm`, such as
+/// `pet_128x128x64_2x2x2.5mm_uint8.raw`
+pub fn parse_raw_spacing(filename: &str) -> Option<[f32; 3]> {
+    filename.split(['_', '-']).find_map(|part| {
+        let mut sizes = part
+            .strip_suffix("mm")?
+            .split('x')
+            .map(|size| size.parse().ok());
+        match (sizes.next(), sizes.next(), sizes.next(), sizes.next()) {
+            (Some(Some(x)), Some(Some(y)), Some(Some(z)), None) => Some([x, y, z]),
+            _ => None,
+        }
+    })
+}
+
+/// Orientation written into a raw file's name as a code such as `LPS`, see
+/// [`PatientOrientation::from_code`]
+pub fn parse_raw_orientation(filename: &str) -> Option<PatientOrientation> {
+    filename
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .find_map(PatientOrientation::from_code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        combine_samples, parse_raw_dims, parse_raw_spacing, Fusion, FusionMode, FusionTransform,
+        SecondaryVolume,
+    };
+    use crate::transfer_function::TransferFunction;
+    use crate::volume::Volume;
+    use cgmath::{assert_abs_diff_eq, Vector4};
+    use std::sync::Arc;
+
+    fn fusion(secondary: Volume, transform: FusionTransform) -> Fusion {
+        Fusion {
+            secondary: Some(SecondaryVolume {
+                volume: Arc::new(secondary),
+                transfer_function: TransferFunction::default(),
+            }),
+            transform,
+            ..Fusion::default()
+        }
+    }
+
+    #[test]
+    fn test_transform_moves_centre() {
+        let transform = FusionTransform {
+            offset: [0.1, -0.2, 0.05],
+            rotation: [10.0, 20.0, 90.0],
+            scale: 0.5,
+        };
+        // The centre of the second volume lands on the offset centre
+        let center = transform.model() * Vector4::new(0.5, 0.5, 0.5, 1.0);
+        assert_abs_diff_eq!(center, Vector4::new(0.6, 0.3, 0.55, 1.0), epsilon = 1e-6);
+        // Volumes of the same size and place only differ by the registration
+        let primary = Volume::new([2, 2, 2], vec![0; 8]).unwrap();
+        let fusion = fusion(primary.clone(), transform);
+        let back = fusion.secondary_inv_model(&primary.model()) * center;
+        assert_abs_diff_eq!(back, Vector4::new(0.5, 0.5, 0.5, 1.0), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_resample_between_grids() {
+        // 4 voxels of 1 mm against 2 voxels of 1 mm along x, 1 voxel of 4 mm
+        // along y and the same along z, all centred
+        let primary = Volume::new([4, 4, 4], vec![0; 64]).unwrap();
+        let secondary = Volume::new([2, 1, 4], vec![0; 8])
+            .unwrap()
+            .with_spacing([1.0, 4.0, 1.0]);
+        let fusion = fusion(secondary, FusionTransform::default());
+        let to_secondary = fusion.secondary_inv_model(&primary.model());
+        // The second volume covers the middle half of the first along x
+        let corner = to_secondary * Vector4::new(0.25, 0.0, 0.0, 1.0);
+        assert_abs_diff_eq!(corner, Vector4::new(0.0, 0.0, 0.0, 1.0), epsilon = 1e-6);
+        let center = to_secondary * Vector4::new(0.5, 0.5, 0.5, 1.0);
+        assert_abs_diff_eq!(center, Vector4::new(0.5, 0.5, 0.5, 1.0), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_combine_modes() {
+        let first = [1.0, 0.0, 0.0, 0.5];
+        let second = [0.0, 0.0, 1.0, 1.0];
+        let blended = combine_samples(FusionMode::AlphaBlend, 0.5, first, second);
+        assert_abs_diff_eq!(
+            &blended[..],
+            &[0.25 / 0.75, 0.0, 0.5 / 0.75, 0.75][..],
+            epsilon = 1e-6
+        );
+        // Nothing of the second volume shows without weight
+        assert_eq!(
+            combine_samples(FusionMode::AlphaBlend, 0.0, first, second),
+            first
+        );
+
+        let added = combine_samples(FusionMode::Additive, 0.25, first, second);
+        assert_abs_diff_eq!(
+            &added[..],
+            &[0.5 / 0.75, 0.0, 0.25 / 0.75, 0.75][..],
+            epsilon = 1e-6
+        );
+
+        assert_eq!(combine_samples(FusionMode::Mask, 1.0, first, second), first);
+        let outside = [0.0; 4];
+        assert_eq!(
+            combine_samples(FusionMode::Mask, 1.0, first, outside)[3],
+            0.0
+        );
+        assert_eq!(
+            combine_samples(FusionMode::Mask, 0.5, first, outside)[3],
+            0.25
+        );
+    }
+
+    #[test]
+    fn test_raw_dims_from_name() {
+        assert_eq!(
+            parse_raw_dims("pet_128x96x64_uint8.raw"),
+            Some([128, 96, 64])
+        );
+        assert_eq!(parse_raw_dims("oblique_512x512_uint8.raw"), None);
+        assert_eq!(parse_raw_dims("skull.raw"), None);
+    }
+
+    #[test]
+    fn test_raw_spacing_from_name() {
+        assert_eq!(
+            parse_raw_spacing("pet_128x96x64_2x2x2.5mm_uint8.raw"),
+            Some([2.0, 2.0, 2.5])
+        );
+        assert_eq!(parse_raw_spacing("pet_128x96x64_uint8.raw"), None);
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
 use cgmath::Vector2;
+use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::JsValue;
 use web_sys::WebGl2RenderingContext as WebGl;
@@ -7,6 +8,8 @@ use web_sys::*;
 
 use crate::app_state::AppState;
 use crate::app_state::MouseButton;
+use crate::slices::SliceOrientation;
+use crate::volumetric_3d::WebGlBackend;
 use crate::Error;
 use crate::SharedMut;
 
@@ -26,25 +29,39 @@ pub fn initialize_webgl_context() -> Result<WebGl, JsValue> {
     Ok(gl)
 }
 
-pub fn mouse_down_handler(event: Event, app_state_signal: &SharedMut<AppState>) -> Result<()> {
-    let mouse_event = event
-        .dyn_into::<MouseEvent>()
+/// Element around the 3D canvas, whose size the canvas follows
+pub const CANVAS_CONTAINER_ID: &str = "volumetric-3d-container";
+
+/// Keeps the canvas size in `app_state` up to date with its container as
+/// the page is laid out, the container resized or the page zoomed
+pub fn observe_canvas_size(app_state: SharedMut<AppState>) -> Result<()> {
+    let window = window()
+        .ok_or(Error::MissingItem)
+        .context("No window to observe the canvas in")?;
+    let container = window
+        .document()
+        .and_then(|document| document.get_element_by_id(CANVAS_CONTAINER_ID))
+        .ok_or(Error::MissingItem)
+        .context("Failed to find the canvas container")?;
+    let on_resize = Closure::<dyn FnMut(js_sys::Array)>::new(move |entries: js_sys::Array| {
+        let Ok(entry) = entries.get(0).dyn_into::<ResizeObserverEntry>() else {
+            return;
+        };
+        let rect = entry.content_rect();
+        let pixel_ratio = window.device_pixel_ratio() as f32;
+        match app_state.lock() {
+            Ok(mut app_state) => {
+                app_state.resize_canvas(rect.width() as f32, rect.height() as f32, pixel_ratio)
+            }
+            Err(_) => web_sys::console::error_1(&"Poisoned app_state in resize observer".into()),
+        }
+    });
+    let observer = ResizeObserver::new(on_resize.as_ref().unchecked_ref())
         .map_err(|_| Error::JsCast)
-        .context("Failed to get canvas in mouse down handler")?;
-    web_sys::console::log_1(&"How do you do".into());
-    let mouse_button_option = match mouse_event.button() {
-        0 => Some(MouseButton::Left),
-        1 => Some(MouseButton::Right),
-        _ => None,
-    };
-    let mut app_state = app_state_signal
-        .lock()
-        .map_err(Error::from)
-        .context("app state mutex is poisoned, you should restart")?;
-    app_state.update_mouse_down(
-        Vector2::new(mouse_event.client_x() as f32, mouse_event.client_y() as f32),
-        mouse_button_option,
-    );
+        .context("Failed to create resize observer")?;
+    observer.observe(&container);
+    // Observes for as long as the page is open
+    on_resize.forget();
     Ok(())
 }
 
@@ -62,37 +79,213 @@ pub fn mouse_scroll_handler(event: Event, app_state: &SharedMut<AppState>) -> Re
     Ok(())
 }
 
-pub fn mouse_up_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
-    let mouse_event = event
-        .dyn_into::<MouseEvent>()
+fn pointer_event(event: Event, handler: &str) -> Result<PointerEvent> {
+    event
+        .dyn_into::<PointerEvent>()
         .map_err(|_| Error::JsCast)
-        .context("Failed to read event as mouse event in mouse up handler")?;
+        .with_context(|| format!("Failed to read event as pointer event in {handler} handler"))
+}
 
+fn pointer_position(pointer_event: &PointerEvent) -> Vector2<f32> {
+    Vector2::new(
+        pointer_event.offset_x() as f32,
+        pointer_event.offset_y() as f32,
+    )
+}
+
+pub fn pointer_down_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let pointer_event = pointer_event(event, "pointer down")?;
+    // Keep receiving the drag when the pointer leaves the canvas
+    if let Some(target) = pointer_event
+        .target()
+        .and_then(|target| target.dyn_into::<Element>().ok())
+    {
+        target
+            .set_pointer_capture(pointer_event.pointer_id())
+            .map_err(|_| Error::JsCast)
+            .context("Failed to capture pointer")?;
+    }
+    // Touches and pens have no buttons to tell apart, they always rotate
+    let button = match pointer_event.pointer_type().as_str() {
+        "mouse" => MouseButton::from_button(pointer_event.button()),
+        _ => Some(MouseButton::Left),
+    };
     let mut app_state = app_state
         .lock()
         .map_err(Error::from)
-        .context("poisoned mutex in mouse up handler. Time to restart")?;
-    app_state.update_mouse_down(
-        Vector2::new(mouse_event.client_x() as f32, mouse_event.client_y() as f32),
-        None,
+        .context("Failed to lock app_state in pointer down handler")?;
+    app_state.pointer_down(
+        pointer_event.pointer_id(),
+        pointer_position(&pointer_event),
+        button,
     );
+    Ok(())
+}
+
+pub fn pointer_up_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let pointer_event = pointer_event(event, "pointer up")?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in pointer up handler")?;
+    app_state.pointer_up(pointer_event.pointer_id(), pointer_position(&pointer_event));
+    Ok(())
+}
+
+pub fn pointer_move_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let pointer_event = pointer_event(event, "pointer move")?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in pointer move handler")?;
+    app_state.pointer_move(pointer_event.pointer_id(), pointer_position(&pointer_event));
+    Ok(())
+}
+
+/// Keys pressed with the 3D view focused. Presses with a modifier other than
+/// Shift are left to the browser.
+pub fn key_down_handler(event: Event, app_state: &SharedMut<AppState>) -> Result<()> {
+    let keyboard_event = event
+        .dyn_into::<KeyboardEvent>()
+        .map_err(|_| Error::JsCast)
+        .context("Failed to read event as keyboard event in key down handler")?;
+    if keyboard_event.ctrl_key() || keyboard_event.alt_key() || keyboard_event.meta_key() {
+        return Ok(());
+    }
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in key down handler")?;
+    if app_state.key_down(&keyboard_event.key()) {
+        // Arrow keys would scroll the page otherwise
+        keyboard_event.prevent_default();
+    }
+    Ok(())
+}
+
+/// The GPU was reset or the browser reclaimed the 3D view's context. Without
+/// preventing the default the context is never restored.
+pub fn context_lost_handler(event: Event, backend: &SharedMut<Option<WebGlBackend>>) -> Result<()> {
+    event.prevent_default();
+    web_sys::console::log_1(&"WebGL context lost".into());
+    let mut backend = backend
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock backend in context lost handler")?;
+    if let Some(backend) = backend.as_mut() {
+        backend.lose_context();
+    }
+    Ok(())
+}
+
+/// Rebuilds everything on the GPU from the volume and transfer function kept
+/// in `app_state`, without downloading the volume again
+pub fn context_restored_handler(
+    app_state: &SharedMut<AppState>,
+    backend: &SharedMut<Option<WebGlBackend>>,
+) -> Result<()> {
+    web_sys::console::log_1(&"WebGL context restored".into());
+    let volume = {
+        let mut app_state = app_state
+            .lock()
+            .map_err(Error::from)
+            .context("Failed to lock app_state in context restored handler")?;
+        app_state.set_arcball_changed(true);
+        app_state.get_volume()
+    };
+    let mut backend = backend
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock backend in context restored handler")?;
+    if let (Some(backend), Some(volume)) = (backend.as_mut(), volume) {
+        backend.restore_context(&volume)?;
+    }
+    Ok(())
+}
+
+/// Position of a mouse event in a slice view, from (0, 0) at the bottom left
+/// to (1, 1) at the top right
+fn slice_view_uv(mouse_event: &MouseEvent) -> Result<[f32; 2]> {
+    let canvas = mouse_event
+        .target()
+        .ok_or(Error::MissingItem)
+        .context("Slice view mouse event has no target")?
+        .dyn_into::<HtmlElement>()
+        .map_err(|_| Error::JsCast)
+        .context("Slice view mouse event target is not an element")?;
+    let width = canvas.client_width().max(1) as f32;
+    let height = canvas.client_height().max(1) as f32;
+    Ok([
+        mouse_event.offset_x() as f32 / width,
+        1.0 - mouse_event.offset_y() as f32 / height,
+    ])
+}
+
+pub fn slice_mouse_down_handler(
+    event: Event,
+    app_state: &SharedMut<AppState>,
+    orientation: SliceOrientation,
+) -> Result<()> {
+    let mouse_event = event
+        .dyn_into::<MouseEvent>()
+        .map_err(|_| Error::JsCast)
+        .context("Failed to read event as mouse event in slice mouse down handler")?;
+    let uv = slice_view_uv(&mouse_event)?;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in slice mouse down handler")?;
+    let dragged = match mouse_event.button() {
+        0 => Some(orientation),
+        _ => None,
+    };
+    app_state.update_slice_drag(dragged, uv);
+    Ok(())
+}
 
+pub fn slice_mouse_up_handler(app_state: &SharedMut<AppState>) -> Result<()> {
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in slice mouse up handler")?;
+    app_state.update_slice_drag(None, [0.0, 0.0]);
     Ok(())
 }
 
-pub fn mouse_move_handler(event: Event, app_state_ref: &SharedMut<AppState>) -> Result<()> {
+pub fn slice_mouse_move_handler(
+    event: Event,
+    app_state: &SharedMut<AppState>,
+    orientation: SliceOrientation,
+) -> Result<()> {
     let mouse_event = event
         .dyn_into::<MouseEvent>()
         .map_err(|_| Error::JsCast)
-        .context("Failed to get mouse event for event in mouse move handler")?;
-    let mut app_state = app_state_ref
+        .context("Failed to read event as mouse event in slice mouse move handler")?;
+    let uv = slice_view_uv(&mouse_event)?;
+    let mut app_state = app_state
         .lock()
         .map_err(Error::from)
-        .context("poisoned state mutex in mouse move handler. Time to restart")?;
-    app_state.update_mouse_pos(Vector2::new(
-        mouse_event.client_x() as f32,
-        mouse_event.client_y() as f32,
-    ));
+        .context("Failed to lock app_state in slice mouse move handler")?;
+    app_state.update_slice_pointer(orientation, uv);
+    Ok(())
+}
 
+/// Scrolling moves the slice by one voxel per wheel notch
+pub fn slice_scroll_handler(
+    event: Event,
+    app_state: &SharedMut<AppState>,
+    orientation: SliceOrientation,
+) -> Result<()> {
+    let wheel_event = event
+        .dyn_into::<WheelEvent>()
+        .map_err(|_| Error::JsCast)
+        .context("Failed to read event as scroll event in slice scroll handler")?;
+    wheel_event.prevent_default();
+    let steps = -wheel_event.delta_y().signum() as i32;
+    let mut app_state = app_state
+        .lock()
+        .map_err(Error::from)
+        .context("Failed to lock app_state in slice scroll handler")?;
+    app_state.step_slice(orientation, steps);
     Ok(())
 }
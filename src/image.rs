@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+
+/// Keywords and texts of PNG text chunks
+pub type PngText = Vec<(String, String)>;
+
+/// 8 bit RGBA pixels, top row first
+#[derive(Clone, Debug, PartialEq)]
+pub struct RgbaImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Pixels read back from WebGL, which start at the bottom row
+    pub fn from_bottom_up(width: usize, height: usize, data: Vec<u8>) -> Self {
+        let row = width * 4;
+        let data = data.chunks_exact(row).rev().flatten().copied().collect();
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        self.to_png_with_text(&[])
+    }
+
+    /// Encodes a PNG with a text chunk for each keyword and text pair. Text
+    /// that isn't Latin-1, which tEXt chunks are limited to, goes in a UTF-8
+    /// iTXt chunk instead.
+    pub fn to_png_with_text(&self, text: &[(String, String)]) -> Result<Vec<u8>> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (keyword, text) in text {
+            match text.chars().all(|c| c <= '\u{ff}') {
+                true => encoder.add_text_chunk(keyword.clone(), text.clone()),
+                false => encoder.add_itxt_chunk(keyword.clone(), text.clone()),
+            }
+            .with_context(|| format!("Failed to add PNG text chunk {keyword}"))?;
+        }
+        encoder
+            .write_header()
+            .context("Failed to write PNG header")?
+            .write_image_data(&self.data)
+            .context("Failed to write PNG data")?;
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RgbaImage;
+
+    #[test]
+    fn test_flip_and_encode() {
+        let bottom_up = [
+            [1, 1, 1, 255],
+            [2, 2, 2, 255],
+            [3, 3, 3, 255],
+            [4, 4, 4, 255],
+        ];
+        let image = RgbaImage::from_bottom_up(2, 2, bottom_up.concat());
+        assert_eq!(&image.data[..8], &[3, 3, 3, 255, 4, 4, 4, 255]);
+
+        let text = [("Camera".to_string(), "{\"distance\":2}".to_string())];
+        let png = image.to_png_with_text(&text).unwrap();
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let chunks = &reader.info().uncompressed_latin1_text;
+        assert_eq!(chunks[0].keyword, "Camera");
+        assert_eq!(chunks[0].text, text[0].1);
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, image.data);
+    }
+
+    #[test]
+    fn test_text_beyond_latin1() {
+        let image = RgbaImage::from_bottom_up(1, 1, vec![0, 0, 0, 255]);
+        let text = [
+            (
+                "Dataset".to_string(),
+                "頭部CT_256x256x256.raw 256x256x256".to_string(),
+            ),
+            ("Software".to_string(), "volumetric-renderer".to_string()),
+        ];
+        let png = image.to_png_with_text(&text).unwrap();
+        let reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.utf8_text[0].keyword, "Dataset");
+        assert_eq!(info.utf8_text[0].get_text().unwrap(), text[0].1);
+        assert_eq!(info.uncompressed_latin1_text[0].text, text[1].1);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::camera::ViewPreset;
+
+/// Something a key press does in the 3D view
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAction {
+    RotateLeft,
+    RotateRight,
+    RotateUp,
+    RotateDown,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    DollyIn,
+    DollyOut,
+    ResetView,
+    ViewPreset(ViewPreset),
+    CycleRenderMode,
+    Screenshot,
+}
+
+impl KeyAction {
+    pub const ALL: [KeyAction; 19] = [
+        KeyAction::RotateLeft,
+        KeyAction::RotateRight,
+        KeyAction::RotateUp,
+        KeyAction::RotateDown,
+        KeyAction::PanLeft,
+        KeyAction::PanRight,
+        KeyAction::PanUp,
+        KeyAction::PanDown,
+        KeyAction::DollyIn,
+        KeyAction::DollyOut,
+        KeyAction::ResetView,
+        KeyAction::ViewPreset(ViewPreset::ALL[0]),
+        KeyAction::ViewPreset(ViewPreset::ALL[1]),
+        KeyAction::ViewPreset(ViewPreset::ALL[2]),
+        KeyAction::ViewPreset(ViewPreset::ALL[3]),
+        KeyAction::ViewPreset(ViewPreset::ALL[4]),
+        KeyAction::ViewPreset(ViewPreset::ALL[5]),
+        KeyAction::CycleRenderMode,
+        KeyAction::Screenshot,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyAction::RotateLeft => "Rotate left",
+            KeyAction::RotateRight => "Rotate right",
+            KeyAction::RotateUp => "Rotate up",
+            KeyAction::RotateDown => "Rotate down",
+            KeyAction::PanLeft => "Pan left",
+            KeyAction::PanRight => "Pan right",
+            KeyAction::PanUp => "Pan up",
+            KeyAction::PanDown => "Pan down",
+            KeyAction::DollyIn => "Move closer",
+            KeyAction::DollyOut => "Move away",
+            KeyAction::ResetView => "Reset view",
+            KeyAction::ViewPreset(preset) => preset.name(),
+            KeyAction::CycleRenderMode => "Next render mode",
+            KeyAction::Screenshot => "Screenshot",
+        }
+    }
+}
+
+/// Which key, as given by `KeyboardEvent.key`, triggers each action. Keys
+/// are case sensitive, so Shift+S takes a screenshot while S pans.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    bindings: Vec<(KeyAction, Option<String>)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let key = |action| match action {
+            KeyAction::RotateLeft => "ArrowLeft",
+            KeyAction::RotateRight => "ArrowRight",
+            KeyAction::RotateUp => "ArrowUp",
+            KeyAction::RotateDown => "ArrowDown",
+            KeyAction::PanLeft => "a",
+            KeyAction::PanRight => "d",
+            KeyAction::PanUp => "w",
+            KeyAction::PanDown => "s",
+            KeyAction::DollyIn => "q",
+            KeyAction::DollyOut => "e",
+            KeyAction::ResetView => "r",
+            KeyAction::ViewPreset(ViewPreset::Anterior) => "1",
+            KeyAction::ViewPreset(ViewPreset::Posterior) => "2",
+            KeyAction::ViewPreset(ViewPreset::Left) => "3",
+            KeyAction::ViewPreset(ViewPreset::Right) => "4",
+            KeyAction::ViewPreset(ViewPreset::Superior) => "5",
+            KeyAction::ViewPreset(ViewPreset::Inferior) => "6",
+            KeyAction::CycleRenderMode => "m",
+            KeyAction::Screenshot => "S",
+        };
+        Self {
+            bindings: KeyAction::ALL
+                .iter()
+                .map(|action| (*action, Some(key(*action).to_string())))
+                .collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn bindings(&self) -> &[(KeyAction, Option<String>)] {
+        &self.bindings
+    }
+
+    pub fn action(&self, key: &str) -> Option<KeyAction> {
+        self.bindings
+            .iter()
+            .find(|(_, bound)| bound.as_deref() == Some(key))
+            .map(|(action, _)| *action)
+    }
+
+    pub fn key(&self, action: KeyAction) -> Option<&str> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .and_then(|(_, key)| key.as_deref())
+    }
+
+    /// Binds `key` to `action`, unbinding it from whatever it did before.
+    /// `None` leaves the action without a key.
+    pub fn bind(&mut self, action: KeyAction, key: Option<String>) {
+        for (bound_action, bound_key) in &mut self.bindings {
+            if *bound_action == action {
+                *bound_key = key.clone();
+            } else if key.is_some() && *bound_key == key {
+                *bound_key = None;
+            }
+        }
+    }
+}
+
+/// How `key` is shown to the user. Upper case letters are only typed with
+/// Shift held, so they're shown as Shift+ the letter.
+pub fn key_label(key: &str) -> String {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), None) if letter.is_uppercase() => format!("Shift+{letter}"),
+        (Some(' '), None) => "Space".to_string(),
+        _ => key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{key_label, KeyAction, KeyBindings};
+    use crate::camera::ViewPreset;
+
+    #[test]
+    fn test_rebinding() {
+        let mut bindings = KeyBindings::default();
+        assert_eq!(bindings.action("s"), Some(KeyAction::PanDown));
+        assert_eq!(bindings.action("S"), Some(KeyAction::Screenshot));
+        assert_eq!(
+            bindings.action("5"),
+            Some(KeyAction::ViewPreset(ViewPreset::Superior))
+        );
+
+        // Taking a key from another action leaves that one unbound
+        bindings.bind(KeyAction::Screenshot, Some("p".to_string()));
+        bindings.bind(KeyAction::RotateLeft, Some("s".to_string()));
+        assert_eq!(bindings.action("S"), None);
+        assert_eq!(bindings.action("s"), Some(KeyAction::RotateLeft));
+        assert_eq!(bindings.key(KeyAction::PanDown), None);
+        assert_eq!(bindings.key(KeyAction::Screenshot), Some("p"));
+
+        bindings.bind(KeyAction::Screenshot, None);
+        assert_eq!(bindings.action("p"), None);
+        assert_eq!(bindings.key(KeyAction::RotateLeft), Some("s"));
+    }
+
+    #[test]
+    fn test_key_label() {
+        assert_eq!(key_label("S"), "Shift+S");
+        assert_eq!(key_label("s"), "s");
+        assert_eq!(key_label(" "), "Space");
+        assert_eq!(key_label("ArrowLeft"), "ArrowLeft");
+    }
+}
@@ -0,0 +1,360 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::Error;
+
+/// Labels are stored in 8 bits, with 0 meaning unlabelled
+pub const MAX_LABELS: usize = 256;
+/// Colours given to labels without a description
+const LABEL_COLORS: [[u8; 3]; 8] = [
+    [230, 25, 75],
+    [60, 180, 75],
+    [255, 225, 25],
+    [0, 130, 200],
+    [245, 130, 48],
+    [145, 30, 180],
+    [70, 240, 240],
+    [240, 50, 230],
+];
+const DEFAULT_LABEL_OPACITY: f32 = 0.5;
+
+/// Segmentation with one label per voxel of the intensity volume
+#[derive(Clone, Debug)]
+pub struct LabelMap {
+    dims: [usize; 3],
+    data: Vec<u8>,
+}
+
+impl LabelMap {
+    /// Reads raw 8 bit labels, or little endian 16 bit labels below
+    /// `MAX_LABELS`, telling the two apart by the data's size
+    pub fn from_raw(dims: [usize; 3], bytes: &[u8]) -> Result<Self> {
+        let voxels: usize = dims.iter().product();
+        let data = if bytes.len() == voxels {
+            bytes.to_vec()
+        } else if bytes.len() == 2 * voxels {
+            bytes
+                .chunks_exact(2)
+                .map(|pair| {
+                    let label = u16::from_le_bytes([pair[0], pair[1]]);
+                    u8::try_from(label).map_err(|_| {
+                        Error::Labels(format!("Label {label} is above {}", MAX_LABELS - 1))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            return Err(Error::Labels(format!(
+                "{} bytes is neither 8 nor 16 bits per voxel for dimensions {dims:?}",
+                bytes.len()
+            ))
+            .into());
+        };
+        Ok(Self { dims, data })
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Label of the voxel nearest to `point` in volume coordinates
+    pub fn label_at(&self, point: [f32; 3]) -> Option<u8> {
+        if point.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return None;
+        }
+        let [x, y, z] = [0, 1, 2]
+            .map(|axis| ((point[axis] * self.dims[axis] as f32) as usize).min(self.dims[axis] - 1));
+        Some(self.data[x + self.dims[0] * (y + self.dims[1] * z)])
+    }
+
+    /// Every label other than 0 found in the map, in increasing order
+    pub fn labels_present(&self) -> Vec<u8> {
+        let mut present = [false; MAX_LABELS];
+        for label in &self.data {
+            present[*label as usize] = true;
+        }
+        (1..MAX_LABELS)
+            .filter(|label| present[*label])
+            .map(|label| label as u8)
+            .collect()
+    }
+
+    /// Labels found in each block of `cell_size`³ voxels, laid out like the
+    /// macrocells of the intensity volume
+    pub fn cells(&self, cell_size: usize) -> LabelCells {
+        let dims = self.dims.map(|d| d.div_ceil(cell_size));
+        let mut labels = vec![[0u64; MAX_LABELS / 64]; dims.iter().product()];
+        for z in 0..self.dims[2] {
+            for y in 0..self.dims[1] {
+                for x in 0..self.dims[0] {
+                    let label = self.data[x + self.dims[0] * (y + self.dims[1] * z)] as usize;
+                    let [cx, cy, cz] = [x, y, z].map(|c| c / cell_size);
+                    labels[cx + dims[0] * (cy + dims[1] * cz)][label / 64] |= 1 << (label % 64);
+                }
+            }
+        }
+        LabelCells { labels }
+    }
+}
+
+/// Set of labels in each macrocell, for skipping space no shown label is in
+#[derive(Clone, Debug)]
+pub struct LabelCells {
+    labels: Vec<[u64; MAX_LABELS / 64]>,
+}
+
+impl LabelCells {
+    /// One byte per cell, 255 where the cell holds a label `table` shows
+    pub fn occupancy(&self, table: &LabelTable) -> Vec<u8> {
+        let mut shown = [0u64; MAX_LABELS / 64];
+        for entry in table.entries.iter().filter(|entry| entry.is_shown()) {
+            shown[entry.value as usize / 64] |= 1 << (entry.value % 64);
+        }
+        self.labels
+            .iter()
+            .map(
+                |cell| match cell.iter().zip(shown).any(|(c, s)| c & s != 0) {
+                    true => u8::MAX,
+                    false => 0,
+                },
+            )
+            .collect()
+    }
+}
+
+/// How one label is drawn
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct LabelEntry {
+    #[serde(alias = "label")]
+    pub value: u8,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub color: [u8; 3],
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+}
+
+fn default_opacity() -> f32 {
+    DEFAULT_LABEL_OPACITY
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+impl LabelEntry {
+    fn new(value: u8) -> Self {
+        Self {
+            value,
+            name: format!("Label {value}"),
+            color: LABEL_COLORS[(value as usize + LABEL_COLORS.len() - 1) % LABEL_COLORS.len()],
+            opacity: DEFAULT_LABEL_OPACITY,
+            visible: true,
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.visible && self.opacity > 0.0
+    }
+
+    /// Colour as `#rrggbb`, as used by colour inputs
+    pub fn hex_color(&self) -> String {
+        let [r, g, b] = self.color;
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+/// Inverse of [`LabelEntry::hex_color`]
+pub fn parse_hex_color(hex: &str) -> Option<[u8; 3]> {
+    let digits = hex.strip_prefix('#').filter(|digits| digits.len() == 6)?;
+    let channel = |i: usize| u8::from_str_radix(digits.get(2 * i..2 * i + 2)?, 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+/// Colour, opacity and visibility of each label, sorted by label value
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LabelTable {
+    pub entries: Vec<LabelEntry>,
+}
+
+impl LabelTable {
+    /// Adds default entries for any of `labels` the table doesn't have yet
+    pub fn add_missing(&mut self, labels: &[u8]) {
+        for label in labels {
+            if !self.entries.iter().any(|entry| entry.value == *label) {
+                self.entries.push(LabelEntry::new(*label));
+            }
+        }
+        self.entries.sort_by_key(|entry| entry.value);
+    }
+
+    /// Replaces the entries for the described labels, adding any new ones
+    pub fn apply_descriptions(&mut self, descriptions: Vec<LabelEntry>) {
+        for description in descriptions.into_iter().filter(|d| d.value != 0) {
+            match self
+                .entries
+                .iter_mut()
+                .find(|e| e.value == description.value)
+            {
+                Some(entry) => *entry = description,
+                None => self.entries.push(description),
+            }
+        }
+        self.entries.sort_by_key(|entry| entry.value);
+    }
+
+    pub fn entry_mut(&mut self, value: u8) -> Option<&mut LabelEntry> {
+        self.entries.iter_mut().find(|entry| entry.value == value)
+    }
+
+    /// RGBA for every label value, with hidden and unlisted labels transparent
+    pub fn lookup_table(&self) -> Vec<u8> {
+        let mut table = vec![0; 4 * MAX_LABELS];
+        for entry in self.entries.iter().filter(|entry| entry.is_shown()) {
+            let start = 4 * entry.value as usize;
+            table[start..start + 3].copy_from_slice(&entry.color);
+            table[start + 3] = (entry.opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+        table
+    }
+}
+
+/// Everything the label overlay is drawn from
+#[derive(Clone, Debug)]
+pub struct LabelOverlay {
+    pub map: Option<Arc<LabelMap>>,
+    pub table: LabelTable,
+    /// Scales every label's opacity, from 0 to hide the overlay to 1
+    pub blend: f32,
+}
+
+impl Default for LabelOverlay {
+    fn default() -> Self {
+        Self {
+            map: None,
+            table: LabelTable::default(),
+            blend: 0.5,
+        }
+    }
+}
+
+/// Parses an ITK-SNAP label description file if `filename` ends in `.txt`,
+/// otherwise a JSON array of label entries
+pub fn parse_label_descriptions(filename: &str, text: &str) -> Result<Vec<LabelEntry>> {
+    match filename.to_lowercase().ends_with(".txt") {
+        true => parse_itk_snap_labels(text),
+        false => serde_json::from_str(text).context("Failed to parse label descriptions JSON"),
+    }
+}
+
+/// Reads lines of `index red green blue alpha visible mesh-visible "name"`,
+/// skipping comments
+pub fn parse_itk_snap_labels(text: &str) -> Result<Vec<LabelEntry>> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad_line = || Error::Labels(format!("Bad label description on line {}", number + 1));
+        let (numbers, name) = line.split_at(line.find('"').unwrap_or(line.len()));
+        let mut fields = numbers.split_whitespace();
+        let mut number_field = || fields.next().ok_or_else(bad_line);
+        let value = number_field()?.parse().map_err(|_| bad_line())?;
+        let mut color = [0; 3];
+        for c in color.iter_mut() {
+            *c = number_field()?.parse().map_err(|_| bad_line())?;
+        }
+        let opacity = number_field()?.parse().map_err(|_| bad_line())?;
+        let visible = number_field()? != "0";
+        let _mesh_visible = number_field()?;
+        entries.push(LabelEntry {
+            value,
+            name: name.trim().trim_matches('"').to_string(),
+            color,
+            opacity,
+            visible,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse_hex_color, parse_itk_snap_labels, parse_label_descriptions, LabelMap, LabelTable,
+    };
+
+    #[test]
+    fn test_raw_label_widths() {
+        let map = LabelMap::from_raw([2, 1, 1], &[0, 3]).unwrap();
+        assert_eq!(map.labels_present(), vec![3]);
+        let map = LabelMap::from_raw([2, 1, 1], &[7, 0, 0, 0]).unwrap();
+        assert_eq!(map.data(), &[7, 0]);
+        assert!(LabelMap::from_raw([2, 1, 1], &[0, 1, 0, 0]).is_err());
+        assert!(LabelMap::from_raw([2, 1, 1], &[0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_label_at_is_nearest() {
+        let map = LabelMap::from_raw([2, 1, 1], &[1, 2]).unwrap();
+        assert_eq!(map.label_at([0.49, 0.5, 0.5]), Some(1));
+        assert_eq!(map.label_at([0.51, 0.5, 0.5]), Some(2));
+        assert_eq!(map.label_at([1.0, 0.5, 0.5]), Some(2));
+        assert_eq!(map.label_at([1.1, 0.5, 0.5]), None);
+    }
+
+    #[test]
+    fn test_itk_snap_descriptions() {
+        let text = "# ITK-SNAP Label Description File\n\
+                    0     0    0    0        0  0  0    \"Clear Label\"\n\
+                    1   255    0    0        1  1  1    \"Left kidney\"\n\
+                    2     0  255    0      0.5  0  1    \"Liver\"\n";
+        let entries = parse_itk_snap_labels(text).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].name, "Left kidney");
+        assert_eq!(entries[1].color, [255, 0, 0]);
+        assert_eq!(entries[2].opacity, 0.5);
+        assert!(!entries[2].visible);
+        assert!(parse_itk_snap_labels("1 255 0\n").is_err());
+    }
+
+    #[test]
+    fn test_descriptions_update_table() {
+        let mut table = LabelTable::default();
+        table.add_missing(&[1, 4]);
+        let json = r#"[{"value": 4, "name": "Tumour", "color": [10, 20, 30]}, {"label": 9}]"#;
+        table.apply_descriptions(parse_label_descriptions("labels.json", json).unwrap());
+        let values: Vec<u8> = table.entries.iter().map(|entry| entry.value).collect();
+        assert_eq!(values, vec![1, 4, 9]);
+        assert_eq!(table.entries[1].name, "Tumour");
+        assert_eq!(table.entries[1].hex_color(), "#0a141e");
+        assert_eq!(parse_hex_color("#0a141e"), Some([10, 20, 30]));
+        assert_eq!(parse_hex_color("0a141e"), None);
+
+        table.entry_mut(1).unwrap().visible = false;
+        let lookup = table.lookup_table();
+        assert_eq!(&lookup[4..8], &[0, 0, 0, 0]);
+        assert_eq!(&lookup[16..20], &[10, 20, 30, 128]);
+    }
+
+    #[test]
+    fn test_cell_occupancy_follows_table() {
+        let mut data = vec![0; 8];
+        data[7] = 2;
+        let map = LabelMap::from_raw([2, 2, 2], &data).unwrap();
+        let cells = map.cells(1);
+        let mut table = LabelTable::default();
+        table.add_missing(&map.labels_present());
+        assert_eq!(cells.occupancy(&table), vec![0, 0, 0, 0, 0, 0, 0, 255]);
+        table.entry_mut(2).unwrap().opacity = 0.0;
+        assert!(cells.occupancy(&table).iter().all(|o| *o == 0));
+    }
+}
@@ -1,9 +1,30 @@
+pub mod animation;
 pub mod app_state;
+pub mod backend;
+pub mod camera;
+pub mod capture;
+pub mod clipping;
+pub mod controls;
+pub mod cpu_render;
+pub mod fusion;
 pub mod gl_setup;
+pub mod image;
+pub mod keyboard;
+pub mod labels;
+pub mod macrocells;
 mod matrix;
+pub mod mesh;
+pub mod pointers;
+pub mod projection;
+pub mod reslice;
+pub mod sampling;
+pub mod slices;
+pub mod transfer_function;
 pub mod util;
 mod view;
+pub mod volume;
 mod volumetric_3d;
+pub mod zip;
 
 extern crate wasm_bindgen;
 
@@ -11,21 +32,51 @@ use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
 
 use anyhow::{Context, Result};
 
-use app_state::AppState;
-use gl_setup::{mouse_down_handler, mouse_move_handler, mouse_scroll_handler, mouse_up_handler};
+use animation::ExportSettings;
+use app_state::{cancel_frame_export, AppState};
+use backend::{render_frame, RenderBackend};
+use camera::ViewPreset;
+use capture::CaptureSettings;
+use clipping::MAX_CLIP_PLANES;
+use controls::{
+    adaptive_sampling_handler, add_bookmark_handler, add_keyframe_handler, bind_key_handler,
+    capture_handler, clear_meshes_handler, clip_plane_handler, crop_editing_handler,
+    dvr_shading_handler, ert_threshold_handler, export_frames_handler, export_reslice_handler,
+    fit_volume_handler, fusion_file_handler, fusion_handler, fusion_mode_handler, get_key_bindings,
+    go_to_bookmark_handler, gradient_source_handler, headlight_handler, iso_value_handler,
+    label_blend_handler, label_handler, light_azimuth_handler, light_elevation_handler,
+    load_label_descriptions_file, load_label_map_file, max_opacity_handler, mesh_file_handler,
+    opacity_window_high_handler, opacity_window_low_handler, picked_file, projection_handler,
+    projection_mode_handler, remove_bookmark_handler, remove_keyframe_handler, render_mode_handler,
+    render_scale_handler, reset_crop_handler, restore_bookmarks, sampling_rate_handler,
+    skip_empty_space_handler, slab_mode_handler, slab_thickness_handler, slice_planes_handler,
+    target_frame_time_handler, transition_duration_handler, turntable_axis_handler,
+    turntable_handler, turntable_speed_handler, view_preset_handler, ClipPlaneField, FusionField,
+    LabelField, ProjectionField, ResliceFormat,
+};
+use gl_setup::{
+    context_lost_handler, context_restored_handler, key_down_handler, mouse_scroll_handler,
+    observe_canvas_size, pointer_down_handler, pointer_move_handler, pointer_up_handler,
+    slice_mouse_down_handler, slice_mouse_move_handler, slice_mouse_up_handler,
+    slice_scroll_handler, CANVAS_CONTAINER_ID,
+};
+use keyboard::key_label;
+use labels::LabelEntry;
+use slices::SliceOrientation;
+use std::sync::Arc;
 use std::time;
 use sycamore::motion::create_raf_loop;
 use sycamore::prelude::*;
 use sycamore::suspense::Suspense;
 use util::LogErrWasm;
+use volume::Volume;
+use volumetric_3d::slice_view::slice_canvas_id;
 use volumetric_3d::*;
 use wasm_bindgen::{JsCast, JsValue};
+use wasm_timer::Instant;
 use web_sys::WebGl2RenderingContext as WebGl;
 use web_sys::*;
-const CUBE_STRIP: [u8; 42] = [
-    255, 255, 0, 0, 255, 0, 255, 255, 255, 0, 255, 255, 0, 0, 255, 0, 255, 0, 0, 0, 0, 255, 255, 0,
-    255, 0, 0, 255, 255, 255, 255, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0, 0,
-];
+const SKULL_FILE: &str = "skull_256x256x256_uint8.raw";
 
 const FPS_THROTTLE_MS: time::Duration = time::Duration::from_millis(33);
 pub type SharedMut<F> = std::sync::Arc<std::sync::Mutex<F>>;
@@ -45,6 +96,20 @@ pub enum Error {
     //    Mutex(PoisonError<MutexGuard<AppState>>)
     #[error("Poisoned mutex error")]
     Poisoned,
+    #[error("Volume with dimensions {dims:?} needs {expected} bytes, got {actual}")]
+    VolumeSize {
+        dims: [usize; 3],
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Invalid mesh: {0}")]
+    Mesh(String),
+    #[error("Invalid labels: {0}")]
+    Labels(String),
+    #[error("An export is already in progress")]
+    ExportInProgress,
+    #[error("Too large for a ZIP archive: {0}")]
+    ArchiveTooLarge(String),
     #[error("Failed request: {source}")]
     Http {
         #[from]
@@ -58,7 +123,7 @@ impl<T> From<PoisonError<MutexGuard<'_, T>>> for Error {
     }
 }
 
-pub struct GlDraw(WebGl, CanvasDims);
+pub struct GlDraw(WebGl);
 
 pub struct CanvasDims {
     width: f32,
@@ -93,15 +158,12 @@ pub fn gl_draw(event: Event, gl_draw: SharedMut<Option<GlDraw>>) -> Result<()> {
     // Clear the context with the newly set color. This is
     // the function call that actually does the drawing.
     gl.clear(WebGl::COLOR_BUFFER_BIT); //gl.COLOR_BUFFER_BIT);
-    let width = canvas.get_attribute("width").unwrap().parse().unwrap();
-    let height = canvas.get_attribute("height").unwrap().parse().unwrap();
-    let canvas_dims = CanvasDims { width, height };
     let mut gl_draw = gl_draw
         .lock()
         .map_err(Error::from)
         .context("failed to lock gl_draw mutex in gl_draw")?;
 
-    *gl_draw = Some(GlDraw(gl, canvas_dims));
+    *gl_draw = Some(GlDraw(gl));
     Ok(())
 }
 
@@ -109,50 +171,18 @@ impl GlDraw {
     pub fn setup_program(
         &self,
         app_state: &SharedMut<AppState>,
-        data_buffer: &[u8],
-    ) -> Result<ProgramReady> {
-        let GlDraw(gl, canvas_dims) = self;
-        let empty_state = volumetric_3d::new_empty_state(gl.clone());
-
-        let arr = js_sys::Float32Array::new_with_length(CUBE_STRIP.len() as u32);
-        arr.copy_from(
-            &CUBE_STRIP
-                .iter()
-                .map(|x| (*x as f32) / 255.0)
-                .collect::<Vec<_>>()[..],
-        );
-        let gl_state = empty_state.init(&arr).unwrap();
-
-        let mut gl_state = gl_state
-            .assemble_volumetric_3d_program(
-                &volumetric_3d::shaders::VERT_SHADER,
-                &volumetric_3d::shaders::FRAG_SHADER,
-            )
-            .unwrap();
-
-        gl_state.init();
-        let colormap_data: Vec<u8> = (0..256)
-            .map(|i| vec![i, i, 10, 50])
-            .flat_map(|x| x.into_iter())
-            .map(|x| x as u8)
-            .collect::<Vec<_>>();
-        let gl_state = gl_state
-            .build_textures(&colormap_data, &data_buffer)
-            .unwrap();
-        web_sys::console::log_1(&"Got here 2".into());
-
-        let program_ready = gl_state.set_volume_metadata();
-        web_sys::console::log_1(&"Got here 3".into());
-
-        let mut app_state_ref = app_state
+        volume: &Volume,
+    ) -> Result<WebGlBackend> {
+        let GlDraw(gl) = self;
+        let (generation, transfer_function) = app_state
             .lock()
             .map_err(Error::from)
-            .context("poisoned lock in gl_setup")?;
-        app_state_ref.update_canvas(canvas_dims.width, canvas_dims.height);
-        web_sys::console::log_1(&"Got here 4".into());
-        // program_ready.render_from_state(&app_state);
-        web_sys::console::log_1(&"Got here 5".into());
-        Ok(program_ready)
+            .context("poisoned lock in gl_setup")?
+            .get_transfer_function();
+        let mut backend = WebGlBackend::new(gl.clone());
+        backend.upload_transfer_function(generation, &transfer_function)?;
+        backend.upload_volume(volume)?;
+        Ok(backend)
     }
 }
 
@@ -169,8 +199,7 @@ pub fn App<G: Html>(ctx: Scope) -> View<G> {
 
     view! { ctx,
         div {
-            Suspense {
-                fallback: view! {ctx, "loading"},
+            Suspense(fallback=view! {ctx, "loading"}) {
                 VolumetricRenderer {}
             }
         }
@@ -178,7 +207,7 @@ pub fn App<G: Html>(ctx: Scope) -> View<G> {
 }
 
 async fn load_data_fut(app_state_signal: SharedMut<AppState>) -> Result<()> {
-    let data = reqwasm::http::Request::get("data/skull_256x256x256_uint8.raw")
+    let data = reqwasm::http::Request::get(&format!("data/{SKULL_FILE}"))
         //.header("Content-Type", "application/octet-stream")
         .send()
         .await
@@ -203,25 +232,51 @@ async fn fun(
     ctx: Scope<'_>,
     app_state: SharedMut<AppState>,
     gl_draw_signal: SharedMut<Option<GlDraw>>,
-    program_ready: SharedMut<Option<ProgramReady>>,
+    backend: SharedMut<Option<WebGlBackend>>,
 ) -> Result<()> {
-    let program_ready_clone = program_ready.clone();
+    let backend_clone = backend.clone();
     let app_state_clone = app_state.clone();
     let (_, start, _) = create_raf_loop(ctx, move || {
-        if let Some(pr) = &mut *program_ready_clone.clone().lock().expect("poisoned lock") {
-            pr.render_from_state(&app_state_clone);
+        let mut backend = backend_clone.lock().expect("poisoned lock");
+        // Nothing can be drawn until the context is restored
+        if let Some(backend) = backend
+            .as_mut()
+            .filter(|backend| !backend.is_context_lost())
+        {
+            let still = match backend.program_mut() {
+                Some(pr) => match pr.render_export_frame(&app_state_clone) {
+                    Ok(true) => Ok(true),
+                    Ok(false) => pr.render_capture(&app_state_clone).and_then(|captured| {
+                        Ok(captured || pr.render_screenshot(&app_state_clone)?)
+                    }),
+                    Err(err) => {
+                        cancel_frame_export(&app_state_clone).log_err();
+                        Err(err)
+                    }
+                },
+                None => Ok(false),
+            };
+            match still {
+                Ok(true) => (),
+                Ok(false) => render_frame(backend, &app_state_clone, Instant::now())
+                    .map(drop)
+                    .log_err(),
+                Err(err) => Err(err).log_err(),
+            }
+            backend.render_slices(&app_state_clone).log_err();
         }
         true
     });
     load_data_fut(app_state.clone()).await?;
 
-    let density_data = app_state
-        .clone()
-        .lock()
-        .map_err(Error::from)
-        .context("failed to lock app_state in load_data")?
-        .density_data
-        .clone();
+    // The volume keeps its own copy, so don't hold on to the download
+    let density_data = std::mem::take(
+        &mut app_state
+            .lock()
+            .map_err(Error::from)
+            .context("failed to lock app_state in load_data")?
+            .density_data,
+    );
     web_sys::console::log_1(&"about to instantiate Uint8Array".into());
     web_sys::console::log_1(&"instantiated".into());
     if let Some(gl_draw) = gl_draw_signal
@@ -230,13 +285,25 @@ async fn fun(
         .context("failed to lock gl_draw in load_data")?
         .as_ref()
     {
-        let mut pr = gl_draw.setup_program(&app_state, density_data.as_slice())?;
-        let mut pr_ref = program_ready
+        let volume = Arc::new(
+            Volume::from_raw(SKULL_FILE, density_data)
+                .context("Downloaded volume doesn't match the expected dimensions")?,
+        );
+        let mut gl_backend = gl_draw.setup_program(&app_state, &volume)?;
+        {
+            let mut app_state = app_state
+                .lock()
+                .map_err(Error::from)
+                .context("failed to lock app_state to store the volume")?;
+            app_state.set_volume(volume.clone());
+            app_state.set_dataset(SKULL_FILE);
+        }
+        let mut backend_ref = backend
             .lock()
             .map_err(Error::from)
-            .context("failed to lock program_ready mutex")?;
-        pr.render_from_state(&app_state)?;
-        *pr_ref = Some(pr);
+            .context("failed to lock backend mutex")?;
+        render_frame(&mut gl_backend, &app_state, Instant::now())?;
+        *backend_ref = Some(gl_backend);
         start();
     } else {
         web_sys::console::log_1(&"no web gl context set up, so cannot set up program".into())
@@ -248,7 +315,7 @@ fn load_data(
     ctx: Scope<'_>,
     app_state: SharedMut<AppState>,
     gl_draw_signal: SharedMut<Option<GlDraw>>,
-    program_ready: SharedMut<Option<ProgramReady>>,
+    backend: SharedMut<Option<WebGlBackend>>,
 ) -> Result<()> {
     let test = gl_draw_signal
         .lock()
@@ -257,9 +324,7 @@ fn load_data(
         .is_some();
     let message = if test {
         sycamore::futures::spawn_local_scoped(ctx, async move {
-            fun(ctx, app_state, gl_draw_signal, program_ready)
-                .await
-                .log_err()
+            fun(ctx, app_state, gl_draw_signal, backend).await.log_err()
         });
         "is Some".to_string()
     } else {
@@ -273,35 +338,799 @@ fn load_data(
 async fn VolumetricRenderer<G: Html>(ctx: Scope<'_>) -> View<G> {
     let app_state = AppState::new();
     let app_state = shared_mut(app_state);
-    let program_ready: SharedMut<Option<ProgramReady>> = shared_mut(None);
+    let backend: SharedMut<Option<WebGlBackend>> = shared_mut(None);
     let shared_gl_draw = shared_mut(None);
     let app_state_ref = create_ref(ctx, app_state.clone());
+    let backend_ref = create_ref(ctx, backend.clone());
     let gl_draw_signal_clone = shared_gl_draw.clone();
     let load = move |_: web_sys::Event| {
         load_data(
             ctx,
             app_state.clone(),
             gl_draw_signal_clone.clone(),
-            program_ready.clone(),
+            backend.clone(),
         )
         .log_err()
     };
+    on_mount(ctx, || observe_canvas_size(app_state_ref.clone()).log_err());
     view! { ctx,
-         canvas(
-             id = "volumetric-3d-canvas",
-             width = 800u16,
-             height = 800u16,
-             on:dblclick = move |event| gl_draw(event, shared_gl_draw.clone()).log_err(),
-             on:mousedown = |event| mouse_down_handler(event, app_state_ref).log_err(),
-             on:mouseup = |event| mouse_up_handler(event, app_state_ref).log_err(),
-             on:mousemove = |event| mouse_move_handler(event, app_state_ref).log_err(),
-             on:wheel = |event| mouse_scroll_handler(event, app_state_ref).log_err(),
+         // Drag the corner to resize the view
+         div(
+             id = CANVAS_CONTAINER_ID,
+             style = "width: 800px; height: 800px; max-width: 100%; resize: both; overflow: hidden",
          ) {
-             "Your browser does not seem to support
+             canvas(
+                 id = "volumetric-3d-canvas",
+                 width = 800u16,
+                 height = 800u16,
+                 on:dblclick = move |event| gl_draw(event, shared_gl_draw.clone()).log_err(),
+                 style = "width: 100%; height: 100%; display: block; touch-action: none",
+                 tabindex = "0",
+                 on:keydown = |event| key_down_handler(event, app_state_ref).log_err(),
+                 on:pointerdown = |event| pointer_down_handler(event, app_state_ref).log_err(),
+                 on:pointerup = |event| pointer_up_handler(event, app_state_ref).log_err(),
+                 on:pointercancel = |event| pointer_up_handler(event, app_state_ref).log_err(),
+                 on:pointermove = |event| pointer_move_handler(event, app_state_ref).log_err(),
+                 on:contextmenu = |event: web_sys::Event| event.prevent_default(),
+                 on:wheel = |event| mouse_scroll_handler(event, app_state_ref).log_err(),
+                 on:webglcontextlost = |event| context_lost_handler(event, backend_ref).log_err(),
+                 on:webglcontextrestored = |_| {
+                     context_restored_handler(app_state_ref, backend_ref).log_err()
+                 },
+             ) {
+                 "Your browser does not seem to support
     HTML5 canvas."
+             }
          }
+         (View::new_fragment(
+             SliceOrientation::ALL
+                 .iter()
+                 .map(|orientation| view! { ctx,
+                     SliceCanvas(app_state = app_state_ref, orientation = *orientation)
+                 })
+                 .collect(),
+         ))
          div(on:click = load) {
              "CLICK ME"
          }
+         div {
+             label { "Opacity window " }
+             input(
+                 type = "range",
+                 min = "0",
+                 max = "255",
+                 step = "1",
+                 value = "0",
+                 on:input = |event| opacity_window_low_handler(event, app_state_ref).log_err(),
+             )
+             input(
+                 type = "range",
+                 min = "0",
+                 max = "255",
+                 step = "1",
+                 value = "255",
+                 on:input = |event| opacity_window_high_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Max opacity " }
+             input(
+                 type = "range",
+                 min = "0",
+                 max = "1",
+                 step = "0.01",
+                 value = "0.2",
+                 on:input = |event| max_opacity_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Render mode " }
+             select(on:change = |event| render_mode_handler(event, app_state_ref).log_err()) {
+                 option(value = "dvr", selected = true) { "Volume" }
+                 option(value = "isosurface") { "Isosurface" }
+             }
+         }
+         div {
+             label { "Iso value " }
+             input(
+                 type = "range",
+                 min = "0",
+                 max = "255",
+                 step = "1",
+                 value = "100",
+                 on:input = |event| iso_value_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Headlight " }
+             input(
+                 type = "checkbox",
+                 checked = true,
+                 on:change = |event| headlight_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Light azimuth " }
+             input(
+                 type = "range",
+                 min = "-180",
+                 max = "180",
+                 step = "1",
+                 value = "45",
+                 on:input = |event| light_azimuth_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Light elevation " }
+             input(
+                 type = "range",
+                 min = "-90",
+                 max = "90",
+                 step = "1",
+                 value = "30",
+                 on:input = |event| light_elevation_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Shade volume " }
+             input(
+                 type = "checkbox",
+                 on:change = |event| dvr_shading_handler(event, app_state_ref).log_err(),
+             )
+             select(on:change = |event| gradient_source_handler(event, app_state_ref).log_err()) {
+                 option(value = "precomputed", selected = true) { "Precomputed gradients" }
+                 option(value = "on-the-fly") { "On the fly gradients" }
+             }
+         }
+         div {
+             label { "Sampling rate " }
+             input(
+                 type = "range",
+                 min = "0.25",
+                 max = "4",
+                 step = "0.05",
+                 value = "1",
+                 on:input = |event| sampling_rate_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Early ray termination " }
+             input(
+                 type = "range",
+                 min = "0.5",
+                 max = "1",
+                 step = "0.01",
+                 value = "0.95",
+                 on:input = |event| ert_threshold_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Adaptive sampling " }
+             input(
+                 type = "checkbox",
+                 checked = true,
+                 on:change = |event| adaptive_sampling_handler(event, app_state_ref).log_err(),
+             )
+             label { " target frame time (ms) " }
+             input(
+                 type = "range",
+                 min = "8",
+                 max = "100",
+                 step = "1",
+                 value = "33",
+                 on:input = |event| target_frame_time_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Skip empty space " }
+             input(
+                 type = "checkbox",
+                 checked = true,
+                 on:change = |event| skip_empty_space_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Show slice planes " }
+             input(
+                 type = "checkbox",
+                 checked = true,
+                 on:change = |event| slice_planes_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Oblique slab thickness " }
+             input(
+                 type = "range",
+                 min = "0",
+                 max = "64",
+                 step = "1",
+                 value = "0",
+                 on:input = |event| slab_thickness_handler(event, app_state_ref).log_err(),
+             )
+             select(on:change = |event| slab_mode_handler(event, app_state_ref).log_err()) {
+                 option(value = "mip", selected = true) { "MIP" }
+                 option(value = "average") { "Average" }
+             }
+             button(on:click = |_| {
+                 export_reslice_handler(app_state_ref, ResliceFormat::Png).log_err()
+             }) {
+                 "Export PNG"
+             }
+             button(on:click = |_| {
+                 export_reslice_handler(app_state_ref, ResliceFormat::Raw).log_err()
+             }) {
+                 "Export raw"
+             }
+         }
+         div {
+             label { "View " }
+             (View::new_fragment(
+                 ViewPreset::ALL
+                     .into_iter()
+                     .map(|preset| view! { ctx,
+                         button(on:click = move |_| view_preset_handler(app_state_ref, preset).log_err()) {
+                             (preset.name())
+                         }
+                     })
+                     .collect(),
+             ))
+             button(on:click = |_| fit_volume_handler(app_state_ref).log_err()) {
+                 "Fit volume"
+             }
+         }
+         div {
+             label { "Turntable " }
+             input(
+                 type = "checkbox",
+                 on:change = |event| turntable_handler(event, app_state_ref).log_err(),
+             )
+             select(on:change = |event| turntable_axis_handler(event, app_state_ref).log_err()) {
+                 option(value = "screen", selected = true) { "Screen vertical" }
+                 option(value = "screen-right") { "Screen horizontal" }
+                 option(value = "x") { "Volume x" }
+                 option(value = "y") { "Volume y" }
+                 option(value = "z") { "Volume z" }
+             }
+             label { " degrees per second " }
+             input(
+                 type = "range",
+                 min = "-180",
+                 max = "180",
+                 step = "1",
+                 value = "30",
+                 on:input = |event| turntable_speed_handler(event, app_state_ref).log_err(),
+             )
+         }
+         div {
+             label { "Render scale " }
+             input(
+                 type = "range",
+                 min = "0.25",
+                 max = "1",
+                 step = "0.05",
+                 value = "1",
+                 on:input = |event| render_scale_handler(event, app_state_ref).log_err(),
+             )
+         }
+         BookmarkControls(app_state = app_state_ref)
+         KeyBindingControls(app_state = app_state_ref)
+         AnimationControls(app_state = app_state_ref)
+         CaptureControls(app_state = app_state_ref)
+         div {
+             label { "Projection " }
+             select(on:change = |event| projection_mode_handler(event, app_state_ref).log_err()) {
+                 option(value = "perspective", selected = true) { "Perspective" }
+                 option(value = "orthographic") { "Orthographic" }
+             }
+             label { " field of view " }
+             input(
+                 type = "range",
+                 min = "10",
+                 max = "120",
+                 step = "1",
+                 value = "65",
+                 on:input = |event| {
+                     projection_handler(event, app_state_ref, ProjectionField::Fov).log_err()
+                 },
+             )
+             label { " near " }
+             input(
+                 type = "number",
+                 min = "0.01",
+                 step = "0.01",
+                 value = "1",
+                 on:change = |event| {
+                     projection_handler(event, app_state_ref, ProjectionField::Near).log_err()
+                 },
+             )
+             label { " far " }
+             input(
+                 type = "number",
+                 min = "1",
+                 step = "1",
+                 value = "200",
+                 on:change = |event| {
+                     projection_handler(event, app_state_ref, ProjectionField::Far).log_err()
+                 },
+             )
+         }
+         div {
+             label { "Edit crop box " }
+             input(
+                 type = "checkbox",
+                 on:change = |event| crop_editing_handler(event, app_state_ref).log_err(),
+             )
+             button(on:click = |_| reset_crop_handler(app_state_ref).log_err()) {
+                 "Reset crop"
+             }
+         }
+         div {
+             label { "Meshes (STL/OBJ in voxel coordinates) " }
+             input(
+                 type = "file",
+                 accept = ".stl,.obj",
+                 multiple = true,
+                 on:change = |event| mesh_file_handler(event, app_state_ref).log_err(),
+             )
+             button(on:click = |_| clear_meshes_handler(app_state_ref).log_err()) {
+                 "Clear meshes"
+             }
+         }
+         LabelControls(app_state = app_state_ref)
+         FusionControls(app_state = app_state_ref)
+         (View::new_fragment(
+             (0..MAX_CLIP_PLANES)
+                 .map(|index| view! { ctx, ClipPlaneControls(app_state = app_state_ref, index = index) })
+                 .collect(),
+         ))
+    }
+}
+
+#[derive(Prop)]
+struct SliceCanvasProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+    orientation: SliceOrientation,
+}
+
+/// 2D view of the slice through the cursor in `orientation`
+#[component]
+fn SliceCanvas<'a, G: Html>(ctx: Scope<'a>, props: SliceCanvasProps<'a>) -> View<G> {
+    let SliceCanvasProps {
+        app_state,
+        orientation,
+    } = props;
+    view! { ctx,
+         canvas(
+             id = slice_canvas_id(orientation),
+             class = "slice-view",
+             title = orientation.id(),
+             width = 256u16,
+             height = 256u16,
+             on:mousedown = move |event| slice_mouse_down_handler(event, app_state, orientation).log_err(),
+             on:mouseup = move |_| slice_mouse_up_handler(app_state).log_err(),
+             on:mouseleave = move |_| slice_mouse_up_handler(app_state).log_err(),
+             on:mousemove = move |event| slice_mouse_move_handler(event, app_state, orientation).log_err(),
+             on:wheel = move |event| slice_scroll_handler(event, app_state, orientation).log_err(),
+         )
+    }
+}
+
+#[derive(Prop)]
+struct LabelControlsProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+}
+
+/// Loading a label map and its descriptions, and a row for each label
+#[component]
+fn LabelControls<'a, G: Html>(ctx: Scope<'a>, props: LabelControlsProps<'a>) -> View<G> {
+    let app_state = props.app_state;
+    let entries = create_signal(ctx, Vec::<LabelEntry>::new());
+    let load_map = move |event| match picked_file(event) {
+        Ok(file) => sycamore::futures::spawn_local_scoped(ctx, async move {
+            match load_label_map_file(file, app_state).await {
+                Ok(loaded) => entries.set(loaded),
+                Err(err) => Err(err).log_err(),
+            }
+        }),
+        Err(err) => Err(err).log_err(),
+    };
+    let load_descriptions = move |event| match picked_file(event) {
+        Ok(file) => sycamore::futures::spawn_local_scoped(ctx, async move {
+            match load_label_descriptions_file(file, app_state).await {
+                Ok(loaded) => entries.set(loaded),
+                Err(err) => Err(err).log_err(),
+            }
+        }),
+        Err(err) => Err(err).log_err(),
+    };
+    view! { ctx,
+         div {
+             label { "Label map (raw, 8 or 16 bit) " }
+             input(type = "file", on:change = load_map)
+             label { " names (JSON or ITK-SNAP .txt) " }
+             input(type = "file", accept = ".json,.txt", on:change = load_descriptions)
+             label { " overlay " }
+             input(
+                 type = "range",
+                 min = "0",
+                 max = "1",
+                 step = "0.01",
+                 value = "0.5",
+                 on:input = |event| label_blend_handler(event, app_state).log_err(),
+             )
+         }
+         Indexed(
+             iterable = entries,
+             view = move |ctx, entry| view! { ctx, LabelRow(app_state = app_state, entry = entry) },
+         )
+    }
+}
+
+#[derive(Prop)]
+struct BookmarkControlsProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+}
+
+/// Saving the camera pose under a name and moving back to saved poses
+#[component]
+fn BookmarkControls<'a, G: Html>(ctx: Scope<'a>, props: BookmarkControlsProps<'a>) -> View<G> {
+    let app_state = props.app_state;
+    let restored = match restore_bookmarks(app_state) {
+        Ok(names) => names,
+        Err(err) => {
+            Err(err).log_err();
+            Vec::new()
+        }
+    };
+    let names = create_signal(ctx, restored);
+    let new_name = create_signal(ctx, String::new());
+    let update_names = move |result: Result<Vec<String>>| match result {
+        Ok(updated) => names.set(updated),
+        Err(err) => Err(err).log_err(),
+    };
+    let add = move |_| {
+        let name = new_name.get().trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+        update_names(add_bookmark_handler(app_state, name));
+        new_name.set(String::new());
+    };
+    view! { ctx,
+         div {
+             label { "Bookmark " }
+             input(type = "text", placeholder = "name", bind:value = new_name)
+             button(on:click = add) { "Save view" }
+             label { " transition (s) " }
+             input(
+                 type = "range",
+                 min = "0",
+                 max = "5",
+                 step = "0.1",
+                 value = "1",
+                 on:input = |event| transition_duration_handler(event, app_state).log_err(),
+             )
+         }
+         Indexed(
+             iterable = names,
+             view = move |ctx, name| {
+                 let label = name.clone();
+                 let go_name = name.clone();
+                 view! { ctx,
+                     div(class = "bookmark-row") {
+                         button(on:click = move |_| go_to_bookmark_handler(app_state, &go_name).log_err()) {
+                             (label.clone())
+                         }
+                         button(on:click = move |_| update_names(remove_bookmark_handler(app_state, &name))) {
+                             "Delete"
+                         }
+                     }
+                 }
+             },
+         )
+    }
+}
+
+#[derive(Prop)]
+struct KeyBindingControlsProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+}
+
+/// The keys for each action in the 3D view. Pressing a key in an action's
+/// box binds it.
+#[component]
+fn KeyBindingControls<'a, G: Html>(ctx: Scope<'a>, props: KeyBindingControlsProps<'a>) -> View<G> {
+    let app_state = props.app_state;
+    let initial = match get_key_bindings(app_state) {
+        Ok(bindings) => bindings,
+        Err(err) => {
+            Err(err).log_err();
+            Vec::new()
+        }
+    };
+    let bindings = create_signal(ctx, initial);
+    view! { ctx,
+         details {
+             summary { "Keyboard shortcuts" }
+             Indexed(
+                 iterable = bindings,
+                 view = move |ctx, (action, key)| {
+                     let name = action.name();
+                     let key = key.map_or_else(|| "none".to_string(), |key| key_label(&key));
+                     view! { ctx,
+                         div(class = "key-binding-row") {
+                             label { (name) " " }
+                             input(
+                                 type = "text",
+                                 readonly = true,
+                                 value = key,
+                                 on:keydown = move |event| match bind_key_handler(event, app_state, action) {
+                                     Ok(updated) => bindings.set(updated),
+                                     Err(err) => Err(err).log_err(),
+                                 },
+                             )
+                         }
+                     }
+                 },
+             )
+         }
+    }
+}
+
+#[derive(Prop)]
+struct AnimationControlsProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+}
+
+/// Keyframing the view over time and exporting the animation as frames
+#[component]
+fn AnimationControls<'a, G: Html>(ctx: Scope<'a>, props: AnimationControlsProps<'a>) -> View<G> {
+    let app_state = props.app_state;
+    let defaults = ExportSettings::default();
+    let time = create_signal(ctx, "0".to_string());
+    let width = create_signal(ctx, defaults.width.to_string());
+    let height = create_signal(ctx, defaults.height.to_string());
+    let fps = create_signal(ctx, defaults.fps.to_string());
+    let samples = create_signal(ctx, defaults.samples.to_string());
+    let keyframes = create_signal(ctx, Vec::<(usize, f32)>::new());
+    let update_keyframes = move |result: Result<Vec<f32>>| match result {
+        Ok(times) => keyframes.set(times.into_iter().enumerate().collect()),
+        Err(err) => Err(err).log_err(),
+    };
+    let add = move |_| {
+        let time = time.get().parse().unwrap_or(0.0);
+        update_keyframes(add_keyframe_handler(app_state, time))
+    };
+    let export = move |_| {
+        let settings = ExportSettings {
+            width: width.get().parse().unwrap_or(defaults.width).max(1),
+            height: height.get().parse().unwrap_or(defaults.height).max(1),
+            fps: fps.get().parse().unwrap_or(defaults.fps),
+            samples: samples.get().parse().unwrap_or(defaults.samples).max(1),
+        };
+        export_frames_handler(app_state, settings).log_err()
+    };
+    view! { ctx,
+         div {
+             label { "Keyframe at (s) " }
+             input(type = "number", min = "0", step = "0.5", bind:value = time)
+             button(on:click = add) { "Add keyframe" }
+         }
+         Indexed(
+             iterable = keyframes,
+             view = move |ctx, (index, time)| view! { ctx,
+                 div(class = "keyframe-row") {
+                     label { (format!("{time:.2} s ")) }
+                     button(on:click = move |_| update_keyframes(remove_keyframe_handler(app_state, index))) {
+                         "Delete"
+                     }
+                 }
+             },
+         )
+         div {
+             label { "Export " }
+             input(type = "number", min = "1", step = "1", bind:value = width)
+             label { " x " }
+             input(type = "number", min = "1", step = "1", bind:value = height)
+             label { " at fps " }
+             input(type = "number", min = "1", step = "1", bind:value = fps)
+             label { " samples per frame " }
+             input(type = "number", min = "1", max = "64", step = "1", bind:value = samples)
+             button(on:click = export) { "Export frames" }
+             button(on:click = |_| cancel_frame_export(app_state).log_err()) { "Cancel" }
+         }
+    }
+}
+
+#[derive(Prop)]
+struct CaptureControlsProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+}
+
+/// Rendering the current view at a size of its own, for posters and figures
+#[component]
+fn CaptureControls<'a, G: Html>(ctx: Scope<'a>, props: CaptureControlsProps<'a>) -> View<G> {
+    let app_state = props.app_state;
+    let defaults = CaptureSettings::default();
+    let width = create_signal(ctx, defaults.width.to_string());
+    let height = create_signal(ctx, defaults.height.to_string());
+    let samples = create_signal(ctx, defaults.samples.to_string());
+    let transparent = create_signal(ctx, defaults.transparent);
+    let capture = move |_| {
+        let settings = CaptureSettings {
+            width: width.get().parse().unwrap_or(defaults.width).max(1),
+            height: height.get().parse().unwrap_or(defaults.height).max(1),
+            samples: samples.get().parse().unwrap_or(defaults.samples).max(1),
+            transparent: *transparent.get(),
+        };
+        capture_handler(app_state, settings).log_err()
+    };
+    view! { ctx,
+         div {
+             label { "Capture " }
+             input(type = "number", min = "1", step = "1", bind:value = width)
+             label { " x " }
+             input(type = "number", min = "1", step = "1", bind:value = height)
+             label { " samples " }
+             input(type = "number", min = "1", max = "256", step = "1", bind:value = samples)
+             label { " transparent " }
+             input(type = "checkbox", bind:checked = transparent)
+             button(on:click = capture) { "Capture PNG" }
+         }
+    }
+}
+
+#[derive(Prop)]
+struct FusionControlsProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+}
+
+/// Loading a second volume, how it's combined with the first, its transfer
+/// function and its placement
+#[component]
+fn FusionControls<'a, G: Html>(ctx: Scope<'a>, props: FusionControlsProps<'a>) -> View<G> {
+    let app_state = props.app_state;
+    let handler = move |field| move |event| fusion_handler(event, app_state, field).log_err();
+    let axis_sliders = |name: &'static str,
+                        field: fn(usize) -> FusionField,
+                        range: &'static str,
+                        step: &'static str| {
+        View::new_fragment(
+            ["x", "y", "z"]
+                .into_iter()
+                .enumerate()
+                .map(|(axis, axis_name)| {
+                    let text = format!(" {name} {axis_name} ");
+                    let min = format!("-{range}");
+                    view! { ctx,
+                        label { (text) }
+                        input(
+                            type = "range",
+                            min = min,
+                            max = range,
+                            step = step,
+                            value = "0",
+                            on:input = handler(field(axis)),
+                        )
+                    }
+                })
+                .collect(),
+        )
+    };
+    let offsets = axis_sliders("offset", FusionField::Offset, "0.5", "0.01");
+    let rotations = axis_sliders("rotation", FusionField::Rotation, "180", "1");
+    view! { ctx,
+         div {
+             label { "Fusion volume (raw 8 bit, XxYxZ in the name) " }
+             input(
+                 type = "file",
+                 on:change = |event| fusion_file_handler(event, app_state).log_err(),
+             )
+             select(on:change = |event| fusion_mode_handler(event, app_state).log_err()) {
+                 option(value = "blend", selected = true) { "Alpha blend" }
+                 option(value = "additive") { "Additive" }
+                 option(value = "mask") { "Mask" }
+             }
+             label { " weight " }
+             input(type = "range", min = "0", max = "1", step = "0.01", value = "0.5", on:input = handler(FusionField::Weight))
+         }
+         div {
+             label { "Fusion window " }
+             input(type = "range", min = "0", max = "255", step = "1", value = "20", on:input = handler(FusionField::WindowLow))
+             input(type = "range", min = "0", max = "255", step = "1", value = "255", on:input = handler(FusionField::WindowHigh))
+             label { " max opacity " }
+             input(type = "range", min = "0", max = "1", step = "0.01", value = "0.3", on:input = handler(FusionField::MaxOpacity))
+         }
+         div {
+             label { "Fusion placement" }
+             (offsets)
+             (rotations)
+             label { " scale " }
+             input(type = "range", min = "0.25", max = "4", step = "0.01", value = "1", on:input = handler(FusionField::Scale))
+         }
+    }
+}
+
+#[derive(Prop)]
+struct LabelRowProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+    entry: LabelEntry,
+}
+
+#[component]
+fn LabelRow<'a, G: Html>(ctx: Scope<'a>, props: LabelRowProps<'a>) -> View<G> {
+    let LabelRowProps { app_state, entry } = props;
+    let value = entry.value;
+    let visible = entry.visible;
+    let color = entry.hex_color();
+    let description = format!(" {} {} ", entry.value, entry.name);
+    let opacity = entry.opacity.to_string();
+    let handler = move |field| move |event| label_handler(event, app_state, value, field).log_err();
+    view! { ctx,
+         div(class = "label-row") {
+             input(
+                 type = "checkbox",
+                 checked = visible,
+                 on:change = handler(LabelField::Visible),
+             )
+             input(type = "color", value = color, on:input = handler(LabelField::Color))
+             label { (description) }
+             input(
+                 type = "range",
+                 min = "0",
+                 max = "1",
+                 step = "0.01",
+                 value = opacity,
+                 on:input = handler(LabelField::Opacity),
+             )
+         }
+    }
+}
+
+#[derive(Prop)]
+struct ClipPlaneControlsProps<'a> {
+    app_state: &'a SharedMut<AppState>,
+    index: usize,
+}
+
+#[component]
+fn ClipPlaneControls<'a, G: Html>(ctx: Scope<'a>, props: ClipPlaneControlsProps<'a>) -> View<G> {
+    let ClipPlaneControlsProps { app_state, index } = props;
+    let handler =
+        move |field| move |event| clip_plane_handler(event, app_state, index, field).log_err();
+    view! { ctx,
+         div {
+             label { (format!("Clip plane {} ", index + 1)) }
+             input(type = "checkbox", on:change = handler(ClipPlaneField::Enabled))
+             label { " invert " }
+             input(type = "checkbox", on:change = handler(ClipPlaneField::Invert))
+             label { " azimuth " }
+             input(
+                 type = "range",
+                 min = "-180",
+                 max = "180",
+                 step = "1",
+                 value = "0",
+                 on:input = handler(ClipPlaneField::Azimuth),
+             )
+             label { " elevation " }
+             input(
+                 type = "range",
+                 min = "-90",
+                 max = "90",
+                 step = "1",
+                 value = "0",
+                 on:input = handler(ClipPlaneField::Elevation),
+             )
+             label { " offset " }
+             input(
+                 type = "range",
+                 min = "-0.87",
+                 max = "0.87",
+                 step = "0.01",
+                 value = "0",
+                 on:input = handler(ClipPlaneField::Offset),
+             )
+         }
     }
 }
@@ -0,0 +1,122 @@
+use crate::transfer_function::TransferFunction;
+use crate::volume::Volume;
+
+/// Edge length in voxels of the cells used to skip empty space
+pub const MACROCELL_SIZE: usize = 8;
+
+/// Coarse grid holding the smallest and largest value of each block of
+/// `cell_size`³ voxels, used to find regions a transfer function makes
+/// fully transparent
+#[derive(Clone, Debug)]
+pub struct MacrocellGrid {
+    dims: [usize; 3],
+    ranges: Vec<(u8, u8)>,
+}
+
+impl MacrocellGrid {
+    /// Each cell's range also covers the voxels bordering it, since trilinear
+    /// sampling anywhere inside the cell can blend in their values
+    pub fn new(volume: &Volume, cell_size: usize) -> Self {
+        let volume_dims = volume.dims();
+        let dims = volume_dims.map(|d| d.div_ceil(cell_size));
+        let mut ranges = Vec::with_capacity(dims.iter().product());
+        for cz in 0..dims[2] {
+            for cy in 0..dims[1] {
+                for cx in 0..dims[0] {
+                    let bounds = |c: usize, axis: usize| {
+                        let start = (c * cell_size).saturating_sub(1);
+                        let end = ((c + 1) * cell_size + 1).min(volume_dims[axis]);
+                        start..end
+                    };
+                    let mut range = (u8::MAX, u8::MIN);
+                    for z in bounds(cz, 2) {
+                        for y in bounds(cy, 1) {
+                            for x in bounds(cx, 0) {
+                                let value = volume.voxel(x, y, z);
+                                range = (range.0.min(value), range.1.max(value));
+                            }
+                        }
+                    }
+                    ranges.push(range);
+                }
+            }
+        }
+        Self { dims, ranges }
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    /// Smallest and largest value influencing the cell
+    pub fn range(&self, x: usize, y: usize, z: usize) -> (u8, u8) {
+        self.ranges[x + self.dims[0] * (y + self.dims[1] * z)]
+    }
+
+    /// One byte per cell, 255 where the transfer function gives any of the
+    /// cell's values some opacity and 0 where the whole cell can be skipped
+    pub fn occupancy(&self, transfer_function: &TransferFunction) -> Vec<u8> {
+        self.ranges
+            .iter()
+            .map(
+                |(low, high)| match transfer_function.is_visible(*low, *high) {
+                    true => u8::MAX,
+                    false => 0,
+                },
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MacrocellGrid;
+    use crate::transfer_function::TransferFunction;
+    use crate::volume::Volume;
+
+    /// 10³ volume of zeros with a single bright voxel at (9, 0, 0)
+    fn single_voxel() -> Volume {
+        let mut data = vec![0; 1000];
+        data[9] = 200;
+        Volume::new([10, 10, 10], data).unwrap()
+    }
+
+    #[test]
+    fn test_grid_dims_round_up() {
+        let grid = MacrocellGrid::new(&single_voxel(), 4);
+        assert_eq!(grid.dims(), [3, 3, 3]);
+    }
+
+    #[test]
+    fn test_ranges_include_border() {
+        let grid = MacrocellGrid::new(&single_voxel(), 4);
+        assert_eq!(grid.range(2, 0, 0), (0, 200));
+        assert_eq!(grid.range(1, 0, 0), (0, 0));
+        assert_eq!(grid.range(2, 1, 0), (0, 0));
+
+        // The voxel at x = 8 borders the cell covering 4..8
+        let mut data = vec![0; 1000];
+        data[8] = 200;
+        let grid = MacrocellGrid::new(&Volume::new([10, 10, 10], data).unwrap(), 4);
+        assert_eq!(grid.range(1, 0, 0), (0, 200));
+        assert_eq!(grid.range(0, 0, 0), (0, 0));
+    }
+
+    #[test]
+    fn test_occupancy_follows_transfer_function() {
+        let grid = MacrocellGrid::new(&single_voxel(), 4);
+        let transfer_function = TransferFunction {
+            window: (100.0, 255.0),
+            ..TransferFunction::default()
+        };
+        let occupancy = grid.occupancy(&transfer_function);
+        assert_eq!(occupancy.iter().filter(|o| **o > 0).count(), 1);
+        assert_eq!(occupancy[2], 255);
+
+        let transparent = TransferFunction {
+            window: (250.0, 255.0),
+            ..TransferFunction::default()
+        };
+        assert!(grid.occupancy(&transparent).iter().all(|o| *o == 0));
+    }
+}
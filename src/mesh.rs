@@ -0,0 +1,268 @@
+use anyhow::{Context, Result};
+use cgmath::{InnerSpace, Matrix4, Vector3};
+use std::sync::Arc;
+
+use crate::Error;
+
+/// Colours given to meshes in the order they're loaded
+const MESH_COLORS: [[f32; 3]; 4] = [
+    [0.85, 0.8, 0.7],
+    [0.3, 0.6, 0.9],
+    [0.9, 0.5, 0.2],
+    [0.5, 0.85, 0.4],
+];
+/// Size of a binary STL header plus its triangle count
+const STL_HEADER_SIZE: usize = 84;
+/// Normal, three vertices and the attribute byte count
+const STL_TRIANGLE_SIZE: usize = 50;
+
+/// Triangle soup, shaded with flat normals when drawn
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mesh {
+    pub triangles: Vec<[[f32; 3]; 3]>,
+}
+
+impl Mesh {
+    /// Parses `bytes` as STL if `filename` ends in `.stl`, otherwise as OBJ
+    pub fn from_file(filename: &str, bytes: &[u8]) -> Result<Self> {
+        match filename.to_lowercase().ends_with(".stl") {
+            true => Self::from_stl(bytes),
+            false => {
+                Self::from_obj(std::str::from_utf8(bytes).context("OBJ file is not valid UTF-8")?)
+            }
+        }
+    }
+
+    /// Reads binary or ASCII STL. Binary files are recognised by their size
+    /// matching the triangle count in the header, as ASCII files may also
+    /// start with "solid".
+    pub fn from_stl(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() >= STL_HEADER_SIZE {
+            let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+            // The count is untrusted, and usize is 32 bits on wasm
+            let size = count
+                .checked_mul(STL_TRIANGLE_SIZE)
+                .and_then(|size| size.checked_add(STL_HEADER_SIZE));
+            if size == Some(bytes.len()) {
+                return Ok(Self::from_binary_stl(&bytes[STL_HEADER_SIZE..]));
+            }
+        }
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| Error::Mesh("STL is neither binary nor ASCII".into()))?;
+        Self::from_ascii_stl(text)
+    }
+
+    fn from_binary_stl(records: &[u8]) -> Self {
+        let float = |bytes: &[u8]| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let triangles = records
+            .chunks_exact(STL_TRIANGLE_SIZE)
+            .map(|record| {
+                // Skip the stored normal, it's recomputed from the winding
+                let vertex = |i: usize| {
+                    let start = 12 + 12 * i;
+                    [0, 4, 8].map(|offset| float(&record[start + offset..]))
+                };
+                [vertex(0), vertex(1), vertex(2)]
+            })
+            .collect();
+        Self { triangles }
+    }
+
+    fn from_ascii_stl(text: &str) -> Result<Self> {
+        let mut vertices = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("vertex") {
+                continue;
+            }
+            vertices.push(parse_vector(words, number + 1)?);
+        }
+        if vertices.len() % 3 != 0 {
+            return Err(Error::Mesh(format!(
+                "STL has {} vertices, which isn't a whole number of triangles",
+                vertices.len()
+            ))
+            .into());
+        }
+        let triangles = vertices
+            .chunks_exact(3)
+            .map(|v| [v[0], v[1], v[2]])
+            .collect();
+        Ok(Self { triangles })
+    }
+
+    /// Reads the vertices and faces of an OBJ file, splitting polygons into
+    /// fans of triangles. Texture coordinates, normals and everything else
+    /// are ignored.
+    pub fn from_obj(text: &str) -> Result<Self> {
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("v") => vertices.push(parse_vector(words, number)?),
+                Some("f") => {
+                    let corners = words
+                        .map(|word| obj_index(word, vertices.len(), number))
+                        .collect::<Result<Vec<_>>>()?;
+                    if corners.len() < 3 {
+                        return Err(Error::Mesh(format!(
+                            "Face on line {number} has too few corners"
+                        ))
+                        .into());
+                    }
+                    for i in 1..corners.len() - 1 {
+                        triangles.push([
+                            vertices[corners[0]],
+                            vertices[corners[i]],
+                            vertices[corners[i + 1]],
+                        ]);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(Self { triangles })
+    }
+
+    /// Smallest and largest corner of the axis aligned box around the mesh
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        let mut vertices = self.triangles.iter().flatten();
+        let first = *vertices.next()?;
+        Some(vertices.fold((first, first), |(min, max), v| {
+            (
+                [0, 1, 2].map(|i| min[i].min(v[i])),
+                [0, 1, 2].map(|i| max[i].max(v[i])),
+            )
+        }))
+    }
+
+    /// Position and flat normal of every vertex, six floats each
+    pub fn interleaved_vertices(&self) -> Vec<f32> {
+        self.triangles
+            .iter()
+            .flat_map(|triangle| {
+                let [a, b, c] = triangle.map(Vector3::from);
+                let normal = (b - a).cross(c - a);
+                let normal = match normal.magnitude2() > 0.0 {
+                    true => normal.normalize(),
+                    false => normal,
+                };
+                triangle
+                    .iter()
+                    .flat_map(move |v| [v[0], v[1], v[2], normal.x, normal.y, normal.z])
+            })
+            .collect()
+    }
+}
+
+/// A mesh placed in the volume
+#[derive(Clone, Debug)]
+pub struct SceneMesh {
+    pub name: String,
+    pub mesh: Arc<Mesh>,
+    pub color: [f32; 3],
+    /// Maps the mesh's coordinates to volume coordinates
+    pub model: Matrix4<f32>,
+}
+
+impl SceneMesh {
+    /// Meshes are expected in voxel coordinates, as produced by running
+    /// marching cubes over a segmentation of the volume
+    pub fn in_voxel_space(name: String, mesh: Mesh, volume_dims: [usize; 3], index: usize) -> Self {
+        let [x, y, z] = volume_dims.map(|d| 1.0 / d.max(1) as f32);
+        Self {
+            name,
+            mesh: Arc::new(mesh),
+            color: MESH_COLORS[index % MESH_COLORS.len()],
+            model: Matrix4::from_nonuniform_scale(x, y, z),
+        }
+    }
+}
+
+fn parse_vector<'a>(mut words: impl Iterator<Item = &'a str>, line: usize) -> Result<[f32; 3]> {
+    let mut vector = [0.0; 3];
+    for c in vector.iter_mut() {
+        let word = words
+            .next()
+            .ok_or_else(|| Error::Mesh(format!("Missing coordinate on line {line}")))?;
+        *c = word
+            .parse()
+            .map_err(|_| Error::Mesh(format!("Bad coordinate {word} on line {line}")))?;
+    }
+    Ok(vector)
+}
+
+/// Zero based vertex index of an OBJ face corner like `3`, `3/1/2` or `-1`
+fn obj_index(word: &str, vertex_count: usize, line: usize) -> Result<usize> {
+    let index = word.split('/').next().unwrap_or_default();
+    let index: i64 = index
+        .parse()
+        .map_err(|_| Error::Mesh(format!("Bad face index {word} on line {line}")))?;
+    let resolved = match index {
+        i if i > 0 => i - 1,
+        i => vertex_count as i64 + i,
+    };
+    match (0..vertex_count as i64).contains(&resolved) {
+        true => Ok(resolved as usize),
+        false => Err(Error::Mesh(format!("Face index {word} out of range on line {line}")).into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Mesh;
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    #[test]
+    fn test_ascii_stl() {
+        let stl = "solid t\n facet normal 0 0 1\n  outer loop\n   vertex 0 0 0\n   \
+                   vertex 1 0 0\n   vertex 0 1 0\n  endloop\n endfacet\nendsolid t\n";
+        let mesh = Mesh::from_stl(stl.as_bytes()).unwrap();
+        assert_eq!(mesh.triangles, vec![TRIANGLE]);
+    }
+
+    #[test]
+    fn test_binary_stl() {
+        // Headers of binary files often start with "solid" too
+        let mut stl = b"solid but binary".to_vec();
+        stl.resize(80, 0);
+        stl.extend(1u32.to_le_bytes());
+        stl.extend([0.0f32, 0.0, 1.0].iter().flat_map(|f| f.to_le_bytes()));
+        for vertex in TRIANGLE {
+            stl.extend(vertex.iter().flat_map(|f| f.to_le_bytes()));
+        }
+        stl.extend([0, 0]);
+        let mesh = Mesh::from_stl(&stl).unwrap();
+        assert_eq!(mesh.triangles, vec![TRIANGLE]);
+        assert!(Mesh::from_stl(&stl[..stl.len() - 1]).is_err());
+
+        // A triangle count too large for the file is read as ASCII
+        stl[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Mesh::from_stl(&stl).is_err());
+    }
+
+    #[test]
+    fn test_obj_quads_and_indices() {
+        let obj = "# square\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\n\
+                   f 1/1/1 2/2/1 3/3/1 4/4/1\nf -4 -3 -1\n";
+        let mesh = Mesh::from_obj(obj).unwrap();
+        assert_eq!(mesh.triangles.len(), 3);
+        assert_eq!(mesh.triangles[1][2], [0.0, 1.0, 0.0]);
+        assert_eq!(mesh.triangles[2], TRIANGLE);
+        assert_eq!(mesh.bounds(), Some(([0.0; 3], [1.0, 1.0, 0.0])));
+        assert!(Mesh::from_obj("v 0 0 0\nf 1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn test_flat_normals() {
+        let mesh = Mesh {
+            triangles: vec![TRIANGLE],
+        };
+        let vertices = mesh.interleaved_vertices();
+        assert_eq!(vertices.len(), 18);
+        assert_eq!(&vertices[6..12], &[1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+}
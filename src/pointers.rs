@@ -0,0 +1,102 @@
+use cgmath::{InnerSpace, Vector2};
+
+/// Pointers pressed on the 3D view, in the order they went down. Only the
+/// first two take part in gestures.
+#[derive(Clone, Debug, Default)]
+pub struct Pointers {
+    active: Vec<(i32, Vector2<f32>)>,
+}
+
+/// Camera motion from the two gesture pointers moving
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PinchMotion {
+    /// Movement of the midpoint between the pointers, in pixels
+    pub pan: Vector2<f32>,
+    /// Change of the distance between the pointers, in pixels
+    pub spread: f32,
+}
+
+impl Pointers {
+    pub fn len(&self) -> usize {
+        self.active.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.is_empty()
+    }
+
+    pub fn first(&self) -> Option<Vector2<f32>> {
+        self.active.first().map(|(_, position)| *position)
+    }
+
+    /// Adds a pointer, or moves it if it was already down
+    pub fn press(&mut self, id: i32, position: Vector2<f32>) {
+        match self.active.iter_mut().find(|(active, _)| *active == id) {
+            Some((_, existing)) => *existing = position,
+            None => self.active.push((id, position)),
+        }
+    }
+
+    pub fn release(&mut self, id: i32) {
+        self.active.retain(|(active, _)| *active != id);
+    }
+
+    /// Moves a pointer that is down, and gives the pinch it makes with the
+    /// other gesture pointer if it is one of the two
+    pub fn update(&mut self, id: i32, position: Vector2<f32>) -> Option<PinchMotion> {
+        let before = self.pinch();
+        let index = self.active.iter().position(|(active, _)| *active == id)?;
+        self.active[index].1 = position;
+        match (before, self.pinch()) {
+            (Some((mid_before, spread_before)), Some((mid, spread))) if index < 2 => {
+                Some(PinchMotion {
+                    pan: mid - mid_before,
+                    spread: spread - spread_before,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Midpoint of and distance between the first two pointers
+    fn pinch(&self) -> Option<(Vector2<f32>, f32)> {
+        match self.active.as_slice() {
+            [(_, a), (_, b), ..] => Some(((a + b) / 2.0, (b - a).magnitude())),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PinchMotion, Pointers};
+    use cgmath::Vector2;
+
+    #[test]
+    fn test_pinch() {
+        let mut pointers = Pointers::default();
+        pointers.press(7, Vector2::new(0.0, 0.0));
+        assert_eq!(pointers.update(7, Vector2::new(10.0, 0.0)), None);
+        pointers.press(3, Vector2::new(30.0, 0.0));
+        assert_eq!(pointers.len(), 2);
+
+        // Spreading one finger pans by half as much as it moves
+        assert_eq!(
+            pointers.update(3, Vector2::new(50.0, 0.0)),
+            Some(PinchMotion {
+                pan: Vector2::new(10.0, 0.0),
+                spread: 20.0,
+            })
+        );
+        // Neither a third finger nor unknown ids take part
+        pointers.press(9, Vector2::new(5.0, 5.0));
+        assert_eq!(pointers.update(9, Vector2::new(6.0, 6.0)), None);
+        assert_eq!(pointers.update(1, Vector2::new(6.0, 6.0)), None);
+
+        pointers.release(7);
+        assert_eq!(pointers.first(), Some(Vector2::new(50.0, 0.0)));
+        pointers.release(3);
+        pointers.release(9);
+        assert!(pointers.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use cgmath::{Deg, Matrix4};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectionMode {
+    /// Pinhole camera, rays spread out from the eye
+    Perspective,
+    /// Parallel rays, so sizes on screen don't depend on depth
+    Orthographic,
+}
+
+/// How the view is projected onto the canvas
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projection {
+    pub mode: ProjectionMode,
+    /// Vertical field of view in degrees. Orthographic views show what a
+    /// perspective one would at the depth of the volume centre, so switching
+    /// keeps the framing.
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self {
+            mode: ProjectionMode::Perspective,
+            fov: 65.0,
+            near: 1.0,
+            far: 200.0,
+        }
+    }
+}
+
+impl Projection {
+    /// Distance from the eye at which a sphere of `radius` fills as much of
+    /// the view as fits, without the near plane cutting into it
+    pub fn fit_distance(&self, radius: f32, aspect: f32) -> f32 {
+        let half_fov = (self.fov.clamp(1.0, 170.0).to_radians() / 2.0).tan();
+        // The narrower of the vertical and horizontal half angles
+        let half_angle = half_fov.min(half_fov * aspect).atan();
+        let distance = match self.mode {
+            ProjectionMode::Perspective => radius / half_angle.sin(),
+            ProjectionMode::Orthographic => radius / half_angle.tan(),
+        };
+        distance.max(self.near.max(1e-3) + radius)
+    }
+
+    /// `focus_distance` is the depth of the volume centre in view space
+    pub fn matrix(&self, aspect: f32, focus_distance: f32) -> Matrix4<f32> {
+        let fov = self.fov.clamp(1.0, 170.0);
+        let near = self.near.max(1e-3);
+        let far = self.far.max(near + 1e-3);
+        match self.mode {
+            ProjectionMode::Perspective => cgmath::perspective(Deg(fov), aspect, near, far),
+            ProjectionMode::Orthographic => {
+                let half_height = focus_distance.abs().max(1e-3) * (fov.to_radians() / 2.0).tan();
+                let half_width = half_height * aspect;
+                cgmath::ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Projection, ProjectionMode};
+    use cgmath::{assert_abs_diff_eq, Vector4};
+
+    #[test]
+    fn test_fit_distance() {
+        let perspective = Projection {
+            fov: 90.0,
+            near: 0.1,
+            ..Projection::default()
+        };
+        assert_abs_diff_eq!(
+            perspective.fit_distance(1.0, 1.0),
+            2f32.sqrt(),
+            epsilon = 1e-5
+        );
+        // A tall canvas is limited by its width
+        assert_abs_diff_eq!(
+            perspective.fit_distance(1.0, 0.5),
+            1.0 / 0.5f32.atan().sin(),
+            epsilon = 1e-5
+        );
+        // The sphere stays behind the near plane
+        let near = Projection {
+            near: 5.0,
+            ..perspective
+        };
+        assert_abs_diff_eq!(near.fit_distance(1.0, 1.0), 6.0);
+
+        // An orthographic view at the fitted distance is as tall as the sphere
+        let orthographic = Projection {
+            mode: ProjectionMode::Orthographic,
+            fov: 60.0,
+            ..perspective
+        };
+        let distance = orthographic.fit_distance(1.0, 2.0);
+        let top = orthographic.matrix(2.0, distance) * Vector4::new(0.0, 1.0, -distance, 1.0);
+        assert_abs_diff_eq!(top.y / top.w, 1.0, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn test_orthographic_ignores_depth() {
+        let projection = Projection {
+            mode: ProjectionMode::Orthographic,
+            ..Projection::default()
+        };
+        let matrix = projection.matrix(1.5, 2.0);
+        let near = matrix * Vector4::new(0.3, -0.2, -1.5, 1.0);
+        let far = matrix * Vector4::new(0.3, -0.2, -20.0, 1.0);
+        assert_abs_diff_eq!(near.w, 1.0);
+        assert_abs_diff_eq!(near.x, far.x, epsilon = 1e-6);
+        assert_abs_diff_eq!(near.y, far.y, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_orthographic_matches_perspective_at_focus() {
+        let perspective = Projection::default();
+        let orthographic = Projection {
+            mode: ProjectionMode::Orthographic,
+            ..perspective
+        };
+        let point = Vector4::new(0.4, 0.25, -3.0, 1.0);
+        let expected = perspective.matrix(1.0, 3.0) * point;
+        let actual = orthographic.matrix(1.0, 3.0) * point;
+        assert_abs_diff_eq!(expected.x / expected.w, actual.x / actual.w, epsilon = 1e-5);
+        assert_abs_diff_eq!(expected.y / expected.w, actual.y / actual.w, epsilon = 1e-5);
+    }
+}
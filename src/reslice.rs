@@ -0,0 +1,235 @@
+use anyhow::{Context, Result};
+use cgmath::{Deg, InnerSpace, Quaternion, Rotation, Rotation3, Vector2, Vector3};
+
+use crate::volume::Volume;
+
+/// Edge length of the oblique plane in volume units, enough to cover the
+/// volume through its centre at any orientation
+pub const OBLIQUE_PLANE_EXTENT: f32 = 1.733;
+/// Width and height in pixels of exported reslices
+pub const RESLICE_EXPORT_SIZE: usize = 512;
+/// Degrees the oblique plane turns for a drag across the whole view
+const DEGREES_PER_VIEW: f32 = 180.0;
+
+/// How the samples across a slab's thickness are combined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlabMode {
+    /// Maximum intensity projection
+    Mip,
+    Average,
+}
+
+/// Rectangle through the volume to resample, plus the slab around it. Points
+/// on it are `origin + uv.x * u + uv.y * v` for `uv` in `[0, 1]²`, with (0, 0)
+/// at the bottom left of the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SliceGeometry {
+    pub origin: [f32; 3],
+    pub u: [f32; 3],
+    pub v: [f32; 3],
+    /// Offset between neighbouring samples across the slab
+    pub slab_step: [f32; 3],
+    pub slab_samples: u32,
+    pub slab_mode: SlabMode,
+}
+
+impl SliceGeometry {
+    /// A single sample thick slice
+    pub fn thin(origin: [f32; 3], u: [f32; 3], v: [f32; 3]) -> Self {
+        Self {
+            origin,
+            u,
+            v,
+            slab_step: [0.0; 3],
+            slab_samples: 1,
+            slab_mode: SlabMode::Mip,
+        }
+    }
+
+    /// Point `sample` steps through the slab at `uv`, the slab being centred on
+    /// the plane
+    pub fn point(&self, uv: [f32; 2], sample: u32) -> [f32; 3] {
+        let offset = sample as f32 - (self.slab_samples as f32 - 1.0) * 0.5;
+        let mut point = [0.0; 3];
+        for (axis, p) in point.iter_mut().enumerate() {
+            *p = self.origin[axis]
+                + uv[0] * self.u[axis]
+                + uv[1] * self.v[axis]
+                + offset * self.slab_step[axis];
+        }
+        point
+    }
+
+    /// Value at `uv` in raw data units, combining the slab's samples. Samples
+    /// outside the volume count as zero.
+    pub fn value(&self, volume: &Volume, uv: [f32; 2]) -> f32 {
+        let samples = (0..self.slab_samples.max(1))
+            .map(|sample| volume.sample(self.point(uv, sample)).unwrap_or(0.0));
+        match self.slab_mode {
+            SlabMode::Mip => samples.fold(0.0, f32::max),
+            SlabMode::Average => samples.sum::<f32>() / self.slab_samples.max(1) as f32,
+        }
+    }
+
+    /// Resamples the slice on the CPU in raw data units. The samples match the
+    /// slice views', but without their window, colormap or crosshair.
+    pub fn resample(&self, volume: &Volume, width: usize, height: usize) -> ResliceImage {
+        let data = (0..height)
+            .flat_map(|row| {
+                (0..width).map(move |column| {
+                    let uv = [
+                        (column as f32 + 0.5) / width as f32,
+                        1.0 - (row as f32 + 0.5) / height as f32,
+                    ];
+                    self.value(volume, uv).round().clamp(0.0, 255.0) as u8
+                })
+            })
+            .collect();
+        ResliceImage {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// Arbitrarily oriented slice through the slice cursor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObliquePlane {
+    /// Rotation from the axial orientation
+    pub orientation: Quaternion<f32>,
+    /// Thickness of the slab in voxels, below one only the plane is sampled
+    pub slab_thickness: f32,
+    pub slab_mode: SlabMode,
+}
+
+impl Default for ObliquePlane {
+    fn default() -> Self {
+        Self {
+            orientation: Quaternion::from_angle_x(Deg(30.0)),
+            slab_thickness: 0.0,
+            slab_mode: SlabMode::Mip,
+        }
+    }
+}
+
+impl ObliquePlane {
+    /// Unit vectors along the image's right and up directions and the normal
+    pub fn axes(&self) -> [Vector3<f32>; 3] {
+        [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]
+            .map(|axis| self.orientation.rotate_vector(axis))
+    }
+
+    /// Turns the plane about its own up and right axes as if the image were
+    /// dragged by `delta`, a fraction of the view's size with y pointing down
+    pub fn rotate(&mut self, delta: Vector2<f32>) {
+        let [u, v, _] = self.axes();
+        let about_v = Quaternion::from_axis_angle(v, Deg(delta.x * DEGREES_PER_VIEW));
+        let about_u = Quaternion::from_axis_angle(u, Deg(delta.y * DEGREES_PER_VIEW));
+        self.orientation = (about_u * about_v * self.orientation).normalize();
+    }
+
+    /// Slice through `center` sampled with one step per voxel across the slab
+    pub fn geometry(&self, center: [f32; 3], volume_dims: [usize; 3]) -> SliceGeometry {
+        let [u, v, normal] = self.axes();
+        let center = Vector3::from(center);
+        let origin = center - (u + v) * (OBLIQUE_PLANE_EXTENT * 0.5);
+        let voxel_size = 1.0 / volume_dims.iter().copied().max().unwrap_or(1).max(1) as f32;
+        let slab_samples = self.slab_thickness.round().max(1.0) as u32;
+        SliceGeometry {
+            origin: origin.into(),
+            u: (u * OBLIQUE_PLANE_EXTENT).into(),
+            v: (v * OBLIQUE_PLANE_EXTENT).into(),
+            slab_step: (normal * voxel_size).into(),
+            slab_samples,
+            slab_mode: self.slab_mode,
+        }
+    }
+}
+
+/// 8 bit greyscale image in raw data units, stored top row first
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResliceImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl ResliceImage {
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .context("Failed to write PNG header")?
+            .write_image_data(&self.data)
+            .context("Failed to write PNG data")?;
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ObliquePlane, SlabMode, SliceGeometry};
+    use crate::volume::Volume;
+    use cgmath::{assert_abs_diff_eq, Quaternion, Vector2};
+
+    /// 4³ volume whose value is 10 * z
+    fn ramp_z() -> Volume {
+        let data = (0..64).map(|i| (i / 16 * 10) as u8).collect();
+        Volume::new([4, 4, 4], data).unwrap()
+    }
+
+    #[test]
+    fn test_thin_axial_slice() {
+        let geometry = SliceGeometry::thin([0.0, 0.0, 0.625], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let image = geometry.resample(&ramp_z(), 2, 3);
+        assert_eq!(image.data, vec![20; 6]);
+    }
+
+    #[test]
+    fn test_slab_modes() {
+        // Axial slab through the centre spanning the middle two voxels in z
+        let mut geometry = SliceGeometry {
+            origin: [0.5, 0.5, 0.5],
+            u: [0.0; 3],
+            v: [0.0; 3],
+            slab_step: [0.0, 0.0, 0.25],
+            slab_samples: 2,
+            slab_mode: SlabMode::Mip,
+        };
+        assert_eq!(geometry.value(&ramp_z(), [0.0, 0.0]), 20.0);
+        geometry.slab_mode = SlabMode::Average;
+        assert_eq!(geometry.value(&ramp_z(), [0.0, 0.0]), 15.0);
+        // Samples past the volume's edge count as empty
+        geometry.slab_step = [0.0, 0.0, 2.0];
+        assert_eq!(geometry.value(&ramp_z(), [0.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_rotate_oblique_plane() {
+        let mut plane = ObliquePlane {
+            orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            ..ObliquePlane::default()
+        };
+        // Dragging down half the view tips the top of the image away
+        plane.rotate(Vector2::new(0.0, 0.5));
+        let [u, v, normal] = plane.axes();
+        assert_abs_diff_eq!(u, cgmath::Vector3::unit_x(), epsilon = 1e-6);
+        assert_abs_diff_eq!(v, cgmath::Vector3::unit_z(), epsilon = 1e-6);
+        assert_abs_diff_eq!(normal, -cgmath::Vector3::unit_y(), epsilon = 1e-6);
+
+        let geometry = plane.geometry([0.5; 3], [4, 4, 4]);
+        let center = geometry.point([0.5, 0.5], 0);
+        assert_abs_diff_eq!(center[1], 0.5, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_png_export() {
+        let geometry = SliceGeometry::thin([0.0, 0.0, 0.625], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        let png = geometry.resample(&ramp_z(), 4, 4).to_png().unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+    }
+}
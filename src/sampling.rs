@@ -0,0 +1,105 @@
+use std::time::Duration;
+use wasm_timer::Instant;
+
+/// How long after the last change the view counts as settled
+const SETTLE_TIME: Duration = Duration::from_millis(150);
+/// Upper bound on how much the step size is stretched while interacting
+const MAX_COARSENING: f32 = 8.0;
+
+/// Stretches the ray marching step while the view is changing to keep frame
+/// times near `target_frame_time`, and asks for one full quality frame once
+/// the view has settled.
+pub struct AdaptiveSampling {
+    pub enabled: bool,
+    pub target_frame_time: Duration,
+    coarsening: f32,
+    last_change: Option<Instant>,
+    refined: bool,
+}
+
+impl AdaptiveSampling {
+    pub fn new(target_frame_time: Duration) -> Self {
+        Self {
+            enabled: true,
+            target_frame_time,
+            coarsening: 1.0,
+            last_change: None,
+            refined: true,
+        }
+    }
+
+    pub fn record_change(&mut self, now: Instant) {
+        self.last_change = Some(now);
+    }
+
+    fn is_interacting(&self, now: Instant) -> bool {
+        self.last_change
+            .map(|last_change| now - last_change < SETTLE_TIME)
+            .unwrap_or(false)
+    }
+
+    /// Multiplier for the step size of a frame drawn at `now`
+    pub fn step_scale(&self, now: Instant) -> f32 {
+        match self.enabled && self.is_interacting(now) {
+            true => self.coarsening,
+            false => 1.0,
+        }
+    }
+
+    /// Whether the last frame was drawn coarser than full quality and the view
+    /// has since settled
+    pub fn needs_refinement(&self, now: Instant) -> bool {
+        !self.refined && !self.is_interacting(now)
+    }
+
+    /// Whether the last frame was full quality and the view has since settled,
+    /// so further frames can be averaged into it
+    pub fn is_settled(&self, now: Instant) -> bool {
+        self.refined && !self.is_interacting(now)
+    }
+
+    /// Feeds back the time taken by a frame drawn with `step_scale`
+    pub fn record_frame(&mut self, frame_time: Duration, step_scale: f32) {
+        self.refined = step_scale <= 1.0;
+        if !self.enabled || self.target_frame_time.is_zero() {
+            return;
+        }
+        // The cost of a frame is roughly proportional to the number of samples
+        let ratio = frame_time.as_secs_f32() / self.target_frame_time.as_secs_f32();
+        self.coarsening = (step_scale * ratio).clamp(1.0, MAX_COARSENING);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AdaptiveSampling;
+    use std::time::Duration;
+    use wasm_timer::Instant;
+
+    #[test]
+    fn test_coarsens_while_interacting() {
+        let mut sampling = AdaptiveSampling::new(Duration::from_millis(20));
+        let now = Instant::now();
+        sampling.record_change(now);
+        assert_eq!(sampling.step_scale(now), 1.0);
+        sampling.record_frame(Duration::from_millis(60), 1.0);
+        assert_eq!(sampling.step_scale(now), 3.0);
+        assert!(!sampling.needs_refinement(now));
+    }
+
+    #[test]
+    fn test_refines_once_settled() {
+        let mut sampling = AdaptiveSampling::new(Duration::from_millis(20));
+        let now = Instant::now();
+        sampling.record_change(now);
+        sampling.record_frame(Duration::from_millis(60), 1.0);
+        sampling.record_frame(Duration::from_millis(20), 3.0);
+        let later = now + Duration::from_secs(1);
+        assert!(sampling.needs_refinement(later));
+        assert!(!sampling.is_settled(later));
+        assert_eq!(sampling.step_scale(later), 1.0);
+        sampling.record_frame(Duration::from_millis(60), 1.0);
+        assert!(!sampling.needs_refinement(later));
+        assert!(sampling.is_settled(later));
+    }
+}
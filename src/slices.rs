@@ -0,0 +1,210 @@
+use cgmath::Vector3;
+
+use crate::reslice::{ObliquePlane, SliceGeometry};
+
+/// Orientation of an orthogonal slice through the volume. The volume's x axis
+/// runs left to right, y front to back and z from the feet up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceAxis {
+    /// Perpendicular to z
+    Axial,
+    /// Perpendicular to y
+    Coronal,
+    /// Perpendicular to x
+    Sagittal,
+}
+
+impl SliceAxis {
+    pub const ALL: [SliceAxis; 3] = [SliceAxis::Axial, SliceAxis::Coronal, SliceAxis::Sagittal];
+
+    /// Volume axis the slice is perpendicular to
+    pub fn normal_axis(self) -> usize {
+        match self {
+            SliceAxis::Axial => 2,
+            SliceAxis::Coronal => 1,
+            SliceAxis::Sagittal => 0,
+        }
+    }
+
+    /// Volume axes shown left to right and bottom to top
+    pub fn plane_axes(self) -> [usize; 2] {
+        match self {
+            SliceAxis::Axial => [0, 1],
+            SliceAxis::Coronal => [0, 2],
+            SliceAxis::Sagittal => [1, 2],
+        }
+    }
+
+    /// Slice that lies in the plane perpendicular to `axis`
+    pub fn for_normal_axis(axis: usize) -> Self {
+        match axis {
+            0 => SliceAxis::Sagittal,
+            1 => SliceAxis::Coronal,
+            _ => SliceAxis::Axial,
+        }
+    }
+
+    /// Colour this slice's plane and crosshair are drawn in
+    pub fn color(self) -> [f32; 3] {
+        match self {
+            SliceAxis::Axial => [0.2, 0.4, 1.0],
+            SliceAxis::Coronal => [0.2, 0.9, 0.3],
+            SliceAxis::Sagittal => [1.0, 0.25, 0.2],
+        }
+    }
+
+    pub fn id(self) -> &'static str {
+        match self {
+            SliceAxis::Axial => "axial",
+            SliceAxis::Coronal => "coronal",
+            SliceAxis::Sagittal => "sagittal",
+        }
+    }
+
+    /// Point on the slice through `cursor` at `uv`, where (0, 0) is the bottom
+    /// left of the view and (1, 1) the top right
+    pub fn volume_point(self, cursor: [f32; 3], uv: [f32; 2]) -> [f32; 3] {
+        let [u, v] = self.plane_axes();
+        let mut point = cursor;
+        point[u] = uv[0];
+        point[v] = uv[1];
+        point
+    }
+
+    /// Where `point` lands in the view, inverse of `volume_point`
+    pub fn view_uv(self, point: [f32; 3]) -> [f32; 2] {
+        let [u, v] = self.plane_axes();
+        [point[u], point[v]]
+    }
+
+    /// The whole slice through `cursor`
+    pub fn geometry(self, cursor: [f32; 3]) -> SliceGeometry {
+        let [u, v] = self.plane_axes();
+        let unit = |axis: usize| {
+            let mut direction = [0.0; 3];
+            direction[axis] = 1.0;
+            direction
+        };
+        SliceGeometry::thin(self.volume_point(cursor, [0.0, 0.0]), unit(u), unit(v))
+    }
+}
+
+/// One of the 2D views
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceOrientation {
+    Orthogonal(SliceAxis),
+    /// The freely rotated plane of [`ObliquePlane`]
+    Oblique,
+}
+
+impl SliceOrientation {
+    pub const ALL: [SliceOrientation; 4] = [
+        SliceOrientation::Orthogonal(SliceAxis::Axial),
+        SliceOrientation::Orthogonal(SliceAxis::Coronal),
+        SliceOrientation::Orthogonal(SliceAxis::Sagittal),
+        SliceOrientation::Oblique,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            SliceOrientation::Orthogonal(axis) => axis.id(),
+            SliceOrientation::Oblique => "oblique",
+        }
+    }
+}
+
+/// Everything the slice views are drawn from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SliceData {
+    pub cursor: SliceCursor,
+    pub oblique: ObliquePlane,
+    pub volume_dims: [usize; 3],
+}
+
+impl SliceData {
+    pub fn geometry(&self, orientation: SliceOrientation) -> SliceGeometry {
+        match orientation {
+            SliceOrientation::Orthogonal(axis) => axis.geometry(self.cursor.position),
+            SliceOrientation::Oblique => self
+                .oblique
+                .geometry(self.cursor.position, self.volume_dims),
+        }
+    }
+}
+
+/// Point shared by all slice views. Each view shows the slice through it and
+/// crosshairs where the other two slices cut its plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SliceCursor {
+    /// Volume coordinates in [0, 1]
+    pub position: [f32; 3],
+}
+
+impl Default for SliceCursor {
+    fn default() -> Self {
+        Self { position: [0.5; 3] }
+    }
+}
+
+impl SliceCursor {
+    /// Moves the cursor to what's under `uv` in the view of `axis`, keeping
+    /// that view's slice where it is
+    pub fn point_at(&mut self, axis: SliceAxis, uv: [f32; 2]) {
+        let uv = uv.map(|c| c.clamp(0.0, 1.0));
+        self.position = axis.volume_point(self.position, uv);
+    }
+
+    /// Moves the slice shown in the view of `axis` by `steps` voxels, landing
+    /// on voxel centres so slices don't blend neighbouring voxels
+    pub fn step_slice(&mut self, axis: SliceAxis, steps: i32, dims: [usize; 3]) {
+        let normal = axis.normal_axis();
+        let size = dims[normal].max(1) as f32;
+        let voxel = (self.position[normal] * size - 0.5).round() + steps as f32;
+        self.position[normal] = (voxel.clamp(0.0, size - 1.0) + 0.5) / size;
+    }
+
+    /// Moves the cursor by `distance` along `direction`, staying in the volume
+    pub fn step_along(&mut self, direction: Vector3<f32>, distance: f32) {
+        let position: [f32; 3] = (Vector3::from(self.position) + direction * distance).into();
+        self.position = position.map(|c| c.clamp(0.0, 1.0));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SliceAxis, SliceCursor};
+
+    #[test]
+    fn test_view_uv_inverts_volume_point() {
+        let cursor = [0.1, 0.2, 0.3];
+        for axis in SliceAxis::ALL {
+            let point = axis.volume_point(cursor, [0.7, 0.9]);
+            assert_eq!(axis.view_uv(point), [0.7, 0.9]);
+            assert_eq!(point[axis.normal_axis()], cursor[axis.normal_axis()]);
+            assert_eq!(SliceAxis::for_normal_axis(axis.normal_axis()), axis);
+            let geometry = axis.geometry(cursor);
+            assert_eq!(geometry.point([0.7, 0.9], 0), point);
+        }
+    }
+
+    #[test]
+    fn test_cursor_links_views() {
+        let mut cursor = SliceCursor::default();
+        cursor.point_at(SliceAxis::Axial, [0.25, 0.75]);
+        assert_eq!(cursor.position, [0.25, 0.75, 0.5]);
+        // Clicking in the coronal view keeps the coronal slice, which is at y
+        cursor.point_at(SliceAxis::Coronal, [0.5, 1.5]);
+        assert_eq!(cursor.position, [0.5, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn test_step_slice_snaps_to_voxels() {
+        let mut cursor = SliceCursor::default();
+        cursor.step_slice(SliceAxis::Sagittal, 1, [4, 4, 4]);
+        assert_eq!(cursor.position[0], 0.875);
+        cursor.step_slice(SliceAxis::Sagittal, 10, [4, 4, 4]);
+        assert_eq!(cursor.position[0], 0.875);
+        cursor.step_slice(SliceAxis::Sagittal, -10, [4, 4, 4]);
+        assert_eq!(cursor.position[0], 0.125);
+    }
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Number of entries in the colour and opacity lookup table
+pub const TRANSFER_FUNCTION_SIZE: usize = 256;
+
+/// Maps raw volume values to colour and opacity. Opacity ramps linearly from
+/// zero at `window.0` to `max_opacity` at `window.1`, both in raw data units.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferFunction {
+    #[serde(deserialize_with = "deserialize_colors")]
+    pub colors: Vec<[u8; 3]>,
+    pub window: (f32, f32),
+    pub max_opacity: f32,
+}
+
+/// The colours of a transfer function, which needs at least one to look up
+fn deserialize_colors<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<[u8; 3]>, D::Error> {
+    let colors = Vec::<[u8; 3]>::deserialize(deserializer)?;
+    if colors.is_empty() {
+        return Err(serde::de::Error::invalid_length(0, &"at least one colour"));
+    }
+    Ok(colors)
+}
+
+impl Default for TransferFunction {
+    fn default() -> Self {
+        Self {
+            colors: (0..TRANSFER_FUNCTION_SIZE)
+                .map(|i| [(i / 5) as u8, (i / 5) as u8, 10])
+                .collect(),
+            window: (0.0, 255.0),
+            max_opacity: 0.2,
+        }
+    }
+}
+
+impl TransferFunction {
+    /// Black through red and yellow to white, as often used for PET
+    pub fn hot() -> Self {
+        let ramp = |i: usize, start: usize| ((i.saturating_sub(start)) * 3).min(255) as u8;
+        Self {
+            colors: (0..TRANSFER_FUNCTION_SIZE)
+                .map(|i| [ramp(i, 0), ramp(i, 85), ramp(i, 170)])
+                .collect(),
+            window: (20.0, 255.0),
+            max_opacity: 0.3,
+        }
+    }
+
+    pub fn opacity(&self, index: usize) -> f32 {
+        let (low, high) = self.window;
+        let ramp = match high > low {
+            true => (index as f32 - low) / (high - low),
+            false => (index as f32 >= low) as u8 as f32,
+        };
+        ramp.clamp(0.0, 1.0) * self.max_opacity.clamp(0.0, 1.0)
+    }
+
+    /// RGBA8 lookup table with `TRANSFER_FUNCTION_SIZE` entries
+    pub fn lookup_table(&self) -> Vec<u8> {
+        (0..TRANSFER_FUNCTION_SIZE)
+            .flat_map(|i| {
+                let [r, g, b] = self.colors[i * self.colors.len() / TRANSFER_FUNCTION_SIZE];
+                [r, g, b, (self.opacity(i) * 255.0).round() as u8]
+            })
+            .collect()
+    }
+
+    /// Factor that brightens the colours so the brightest channel of any entry
+    /// is full intensity, for showing the colormap without opacity
+    pub fn color_scale(&self) -> f32 {
+        match self.colors.iter().flatten().max() {
+            Some(&max) if max > 0 => 255.0 / max as f32,
+            _ => 1.0,
+        }
+    }
+
+    /// Whether any entry between `low` and `high` inclusive is at all opaque.
+    /// The range is widened by one entry on either side as the table is
+    /// sampled with linear filtering.
+    pub fn is_visible(&self, low: u8, high: u8) -> bool {
+        let low = (low as usize).saturating_sub(1);
+        let high = (high as usize + 1).min(TRANSFER_FUNCTION_SIZE - 1);
+        (low..=high).any(|i| (self.opacity(i) * 255.0).round() > 0.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TransferFunction;
+
+    #[test]
+    fn test_color_scale_brightens_dim_colormap() {
+        let transfer_function = TransferFunction::default();
+        assert_eq!(transfer_function.color_scale(), 5.0);
+        let black = TransferFunction {
+            colors: vec![[0, 0, 0]],
+            ..TransferFunction::default()
+        };
+        assert_eq!(black.color_scale(), 1.0);
+    }
+
+    #[test]
+    fn test_rejects_empty_colors() {
+        let json = r#"{"colors": [], "window": [0.0, 255.0], "max_opacity": 0.2}"#;
+        assert!(serde_json::from_str::<TransferFunction>(json).is_err());
+        let json = r#"{"colors": [[255, 0, 0]], "window": [0.0, 255.0], "max_opacity": 0.2}"#;
+        let transfer_function: TransferFunction = serde_json::from_str(json).unwrap();
+        assert_eq!(transfer_function.lookup_table()[..4], [255, 0, 0, 0]);
+    }
+}
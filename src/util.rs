@@ -1,4 +1,15 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use cgmath::Vector3;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+
+use crate::Error;
+
+/// How long a download's URL is kept after clicking its link. Some browsers
+/// start reading the blob only after the click returns, and cancel the
+/// download if its URL was revoked by then.
+const DOWNLOAD_URL_LIFETIME_MS: i32 = 10_000;
+
 pub trait LogErrWasm {
     fn log_err(self);
 }
@@ -11,3 +22,84 @@ impl LogErrWasm for Result<()> {
         }
     }
 }
+
+/// Unit vector for angles in degrees, with zero azimuth and elevation along +z
+/// and positive elevation towards +y
+pub fn spherical_direction(azimuth: f32, elevation: f32) -> Vector3<f32> {
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+    Vector3::new(
+        elevation.cos() * azimuth.sin(),
+        elevation.sin(),
+        elevation.cos() * azimuth.cos(),
+    )
+}
+
+/// Has the browser save `bytes` as a file called `filename`
+pub fn download_bytes(filename: &str, mime_type: &str, bytes: &[u8]) -> Result<()> {
+    let array = js_sys::Uint8Array::from(bytes);
+    let parts = js_sys::Array::of1(&array);
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime_type);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(|_| Error::JsCast)
+        .context("Failed to create blob to download")?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(|_| Error::JsCast)
+        .context("Failed to create URL for download")?;
+    let window = web_sys::window()
+        .ok_or(Error::MissingItem)
+        .context("No window to download from")?;
+    let anchor = window
+        .document()
+        .ok_or(Error::MissingItem)
+        .context("No document to download from")?
+        .create_element("a")
+        .map_err(|_| Error::MissingItem)
+        .context("Failed to create download link")?
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .map_err(|_| Error::JsCast)
+        .context("Download link is not an anchor")?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+    let revoke = Closure::once_into_js(move || {
+        if web_sys::Url::revoke_object_url(&url).is_err() {
+            web_sys::console::error_1(&"Failed to release download URL".into());
+        }
+    });
+    window
+        .set_timeout_with_callback_and_timeout_and_arguments_0(
+            revoke.unchecked_ref(),
+            DOWNLOAD_URL_LIFETIME_MS,
+        )
+        .map_err(|_| Error::JsCast)
+        .context("Failed to schedule releasing the download URL")?;
+    Ok(())
+}
+
+fn local_storage() -> Result<web_sys::Storage> {
+    web_sys::window()
+        .ok_or(Error::MissingItem)
+        .context("No window to store settings in")?
+        .local_storage()
+        .map_err(|_| Error::JsCast)
+        .context("Local storage is not accessible")?
+        .ok_or(Error::MissingItem)
+        .context("Local storage is not available")
+}
+
+/// Value kept under `key` from an earlier session, if any
+pub fn load_from_storage(key: &str) -> Result<Option<String>> {
+    local_storage()?
+        .get_item(key)
+        .map_err(|_| Error::JsCast)
+        .with_context(|| format!("Failed to read {key} from local storage"))
+}
+
+/// Keeps `value` under `key` for later sessions
+pub fn save_to_storage(key: &str, value: &str) -> Result<()> {
+    local_storage()?
+        .set_item(key, value)
+        .map_err(|_| Error::JsCast)
+        .with_context(|| format!("Failed to write {key} to local storage"))
+}
@@ -0,0 +1,314 @@
+use cgmath::{InnerSpace, Matrix3, Matrix4, SquareMatrix, Vector3};
+
+use crate::fusion::{parse_raw_dims, parse_raw_orientation, parse_raw_spacing};
+use crate::Error;
+
+/// Directions of the volume axes in patient coordinates, with x towards the
+/// patient's left, y posterior and z superior (LPS, as in DICOM)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PatientOrientation {
+    /// Columns are the patient directions of the volume's x, y and z axes
+    pub axes: Matrix3<f32>,
+}
+
+impl Default for PatientOrientation {
+    /// Volume axes already aligned with LPS
+    fn default() -> Self {
+        Self {
+            axes: Matrix3::identity(),
+        }
+    }
+}
+
+impl PatientOrientation {
+    /// Orientation from a code such as `LPS` or `RAS` giving the patient
+    /// direction each of the volume's x, y and z axes points towards
+    pub fn from_code(code: &str) -> Option<Self> {
+        let directions = code
+            .chars()
+            .map(|letter| match letter {
+                'L' => Some(Vector3::unit_x()),
+                'R' => Some(-Vector3::unit_x()),
+                'P' => Some(Vector3::unit_y()),
+                'A' => Some(-Vector3::unit_y()),
+                'S' => Some(Vector3::unit_z()),
+                'I' => Some(-Vector3::unit_z()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let [x, y, z]: [Vector3<f32>; 3] = directions.try_into().ok()?;
+        let axes = Matrix3::from_cols(x, y, z);
+        // Each patient axis has to be used once
+        (axes.determinant() != 0.0).then_some(Self { axes })
+    }
+
+    /// Unit direction in volume coordinates of the patient direction `dir`
+    pub fn to_volume(&self, dir: Vector3<f32>) -> Vector3<f32> {
+        self.axes
+            .invert()
+            .map(|inverse| inverse * dir)
+            .unwrap_or(dir)
+            .normalize()
+    }
+}
+
+/// A scalar volume of 8 bit densities, stored with x varying fastest
+#[derive(Clone, Debug)]
+pub struct Volume {
+    dims: [usize; 3],
+    data: Vec<u8>,
+    /// Size of a voxel along each axis in mm
+    spacing: [f32; 3],
+    /// Patient coordinates in mm of the corner at texture coordinate zero, if
+    /// known
+    origin: Option<[f32; 3]>,
+    /// Known only for formats that record it
+    orientation: Option<PatientOrientation>,
+}
+
+impl Volume {
+    pub fn new(dims: [usize; 3], data: Vec<u8>) -> Result<Self, Error> {
+        let expected = dims.iter().product::<usize>();
+        if data.len() != expected {
+            return Err(Error::VolumeSize {
+                dims,
+                expected,
+                actual: data.len(),
+            });
+        }
+        Ok(Self {
+            dims,
+            data,
+            spacing: [1.0; 3],
+            origin: None,
+            orientation: None,
+        })
+    }
+
+    /// Raw 8 bit data whose dimensions, voxel size in mm and orientation are
+    /// read from `filename`, as in `pet_128x128x64_2x2x2.5mm_LPS_uint8.raw`.
+    /// Without dimensions the volume is taken to be a cube, and without a
+    /// voxel size the voxels are taken to be 1 mm.
+    pub fn from_raw(filename: &str, data: Vec<u8>) -> Result<Self, Error> {
+        let dims = parse_raw_dims(filename).unwrap_or_else(|| {
+            let side = (data.len() as f64).cbrt().round() as usize;
+            [side; 3]
+        });
+        let volume =
+            Self::new(dims, data)?.with_spacing(parse_raw_spacing(filename).unwrap_or([1.0; 3]));
+        Ok(match parse_raw_orientation(filename) {
+            Some(orientation) => volume.with_orientation(orientation),
+            None => volume,
+        })
+    }
+
+    pub fn with_spacing(self, spacing: [f32; 3]) -> Self {
+        Self { spacing, ..self }
+    }
+
+    pub fn with_origin(self, origin: [f32; 3]) -> Self {
+        Self {
+            origin: Some(origin),
+            ..self
+        }
+    }
+
+    pub fn with_orientation(self, orientation: PatientOrientation) -> Self {
+        Self {
+            orientation: Some(orientation),
+            ..self
+        }
+    }
+
+    pub fn orientation(&self) -> Option<PatientOrientation> {
+        self.orientation
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    pub fn spacing(&self) -> [f32; 3] {
+        self.spacing
+    }
+
+    /// Size of the whole volume along each axis in mm
+    pub fn extent(&self) -> [f32; 3] {
+        [0, 1, 2].map(|axis| self.dims[axis] as f32 * self.spacing[axis])
+    }
+
+    /// Maps texture coordinates to patient coordinates in mm. Without a known
+    /// origin the volume is centred on the patient origin, and without a known
+    /// orientation its axes are taken to be aligned with the patient's.
+    pub fn model(&self) -> Matrix4<f32> {
+        let axes = self.orientation.unwrap_or_default().axes;
+        let extent = Vector3::from(self.extent());
+        let origin = match self.origin {
+            Some(origin) => Vector3::from(origin),
+            None => -(axes * extent) * 0.5,
+        };
+        Matrix4::from_translation(origin)
+            * Matrix4::from(axes)
+            * Matrix4::from_nonuniform_scale(extent.x, extent.y, extent.z)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.dims[0] * (y + self.dims[1] * z)
+    }
+
+    pub fn voxel(&self, x: usize, y: usize, z: usize) -> u8 {
+        self.data[self.index(x, y, z)]
+    }
+
+    /// Trilinearly interpolated value at `point` in texture coordinates, where
+    /// voxel centres sit at `(i + 0.5) / dims` and edges are clamped like the
+    /// GPU's `CLAMP_TO_EDGE`. Returns `None` outside `[0, 1]³`.
+    pub fn sample(&self, point: [f32; 3]) -> Option<f32> {
+        if point.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return None;
+        }
+        let mut lo = [0; 3];
+        let mut hi = [0; 3];
+        let mut t = [0.0; 3];
+        for axis in 0..3 {
+            let size = self.dims[axis];
+            let x = (point[axis] * size as f32 - 0.5).clamp(0.0, (size - 1) as f32);
+            lo[axis] = x.floor() as usize;
+            hi[axis] = (lo[axis] + 1).min(size - 1);
+            t[axis] = x - lo[axis] as f32;
+        }
+        let at = |x: [usize; 3]| self.voxel(x[0], x[1], x[2]) as f32;
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let along_x = |y: usize, z: usize| lerp(at([lo[0], y, z]), at([hi[0], y, z]), t[0]);
+        let near = lerp(along_x(lo[1], lo[2]), along_x(hi[1], lo[2]), t[1]);
+        let far = lerp(along_x(lo[1], hi[2]), along_x(hi[1], hi[2]), t[1]);
+        Some(lerp(near, far, t[2]))
+    }
+
+    /// Central difference gradient in raw data units per voxel. One sided
+    /// differences are used on the boundary.
+    pub fn gradient(&self, x: usize, y: usize, z: usize) -> [f32; 3] {
+        let pos = [x, y, z];
+        let mut grad = [0.0; 3];
+        for (axis, g) in grad.iter_mut().enumerate() {
+            let lo = pos[axis].saturating_sub(1);
+            let hi = (pos[axis] + 1).min(self.dims[axis] - 1);
+            if hi == lo {
+                continue;
+            }
+            let mut lo_pos = pos;
+            let mut hi_pos = pos;
+            lo_pos[axis] = lo;
+            hi_pos[axis] = hi;
+            let lo_val = self.voxel(lo_pos[0], lo_pos[1], lo_pos[2]) as f32;
+            let hi_val = self.voxel(hi_pos[0], hi_pos[1], hi_pos[2]) as f32;
+            *g = (hi_val - lo_val) / (hi - lo) as f32;
+        }
+        grad
+    }
+
+    /// Largest gradient magnitude in the volume, used to normalise gradients
+    /// computed on the GPU to the same range as [`Volume::gradient_texture`]
+    pub fn max_gradient_magnitude(&self) -> f32 {
+        self.gradients().map(|g| magnitude(&g)).fold(0.0, f32::max)
+    }
+
+    /// RGB gradient texture with each gradient scaled by `max_magnitude`, see
+    /// [`Volume::max_gradient_magnitude`], and mapped from `-1.0..=1.0` to `0..=255`
+    pub fn gradient_texture(&self, max_magnitude: f32) -> Vec<u8> {
+        let scale = if max_magnitude > 0.0 {
+            1.0 / max_magnitude
+        } else {
+            0.0
+        };
+        self.gradients()
+            .flat_map(|g| g.map(|c| ((c * scale * 0.5 + 0.5) * 255.0).round() as u8))
+            .collect()
+    }
+
+    fn gradients(&self) -> impl Iterator<Item = [f32; 3]> + '_ {
+        let [nx, ny, nz] = self.dims;
+        (0..nz).flat_map(move |z| {
+            (0..ny).flat_map(move |y| (0..nx).map(move |x| self.gradient(x, y, z)))
+        })
+    }
+}
+
+fn magnitude(v: &[f32; 3]) -> f32 {
+    v.iter().map(|c| c * c).sum::<f32>().sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PatientOrientation, Volume};
+    use cgmath::{Matrix3, Vector3, Vector4};
+
+    fn ramp_x() -> Volume {
+        let data = (0..4 * 3 * 2).map(|i| ((i % 4) * 10) as u8).collect();
+        Volume::new([4, 3, 2], data).unwrap()
+    }
+
+    #[test]
+    fn test_wrong_size() {
+        assert!(Volume::new([4, 4, 4], vec![0; 63]).is_err());
+    }
+
+    #[test]
+    fn test_gradient() {
+        let volume = ramp_x();
+        assert_eq!(volume.gradient(1, 1, 1), [10.0, 0.0, 0.0]);
+        assert_eq!(volume.gradient(0, 0, 0), [10.0, 0.0, 0.0]);
+        assert_eq!(volume.max_gradient_magnitude(), 10.0);
+    }
+
+    #[test]
+    fn test_gradient_texture() {
+        let volume = ramp_x();
+        let texture = volume.gradient_texture(volume.max_gradient_magnitude());
+        assert_eq!(texture.len(), 4 * 3 * 2 * 3);
+        assert_eq!(&texture[..3], &[255, 128, 128]);
+    }
+
+    #[test]
+    fn test_model_places_voxels_in_mm() {
+        let volume = ramp_x().with_spacing([0.5, 1.0, 2.0]);
+        assert_eq!(volume.extent(), [2.0, 3.0, 4.0]);
+        let centered = volume.model() * Vector4::new(1.0, 1.0, 1.0, 1.0);
+        assert_eq!(centered, Vector4::new(1.0, 1.5, 2.0, 1.0));
+        let placed =
+            volume.with_origin([10.0, 0.0, 0.0]).model() * Vector4::new(0.5, 0.0, 0.0, 1.0);
+        assert_eq!(placed, Vector4::new(11.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_from_raw_name() {
+        let volume = Volume::from_raw("ct_4x3x2_0.5x0.5x1mm_RAS_uint8.raw", vec![0; 24]).unwrap();
+        assert_eq!(volume.dims(), [4, 3, 2]);
+        assert_eq!(volume.spacing(), [0.5, 0.5, 1.0]);
+        let orientation = volume.orientation().unwrap();
+        assert_eq!(
+            orientation.axes,
+            Matrix3::from_cols(-Vector3::unit_x(), -Vector3::unit_y(), Vector3::unit_z())
+        );
+        assert_eq!(PatientOrientation::from_code("LLS"), None);
+
+        let cube = Volume::from_raw("skull.raw", vec![0; 27]).unwrap();
+        assert_eq!(cube.dims(), [3; 3]);
+        assert_eq!(cube.orientation(), None);
+    }
+
+    #[test]
+    fn test_sample() {
+        let volume = ramp_x();
+        // Voxel centres along x are at 0.125, 0.375, 0.625 and 0.875
+        assert_eq!(volume.sample([0.375, 0.5, 0.5]), Some(10.0));
+        assert_eq!(volume.sample([0.5, 0.1, 0.9]), Some(15.0));
+        assert_eq!(volume.sample([0.0, 0.5, 0.5]), Some(0.0));
+        assert_eq!(volume.sample([1.0, 0.5, 0.5]), Some(30.0));
+        assert_eq!(volume.sample([1.1, 0.5, 0.5]), None);
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{Context, Result};
+use web_sys::WebGl2RenderingContext as WebGl;
+use web_sys::*;
+
+use super::gl_utils::GlUtils;
+use super::shaders::{PRESENT_FRAG_SHADER, PRESENT_VERT_SHADER};
+use super::Error;
+use crate::backend::MAX_ACCUMULATED_FRAMES;
+
+/// Texture unit of the accumulation buffer, kept clear of the units sampled
+/// by the ray marcher so the framebuffer never feeds back into itself
+const ACCUMULATION_TEXTURE_UNIT: u32 = WebGl::TEXTURE7;
+
+/// Floating point framebuffer holding the running average of jittered frames
+pub(crate) struct Accumulator {
+    framebuffer: WebGlFramebuffer,
+    texture: WebGlTexture,
+    program: WebGlProgram,
+    width: i32,
+    height: i32,
+    frame_count: u32,
+}
+
+impl Accumulator {
+    /// Fails if the context can't render to floating point textures, without
+    /// leaving behind anything it created
+    pub(crate) fn new(gl: &WebGl, width: i32, height: i32) -> Result<Self> {
+        gl.get_extension("EXT_color_buffer_float")
+            .map_err(|_| Error::Message("Js".into()))
+            .context("Failed to query EXT_color_buffer_float")?
+            .ok_or(Error::Missing)
+            .context("EXT_color_buffer_float is not supported")?;
+        let program = gl.link_program_from(&PRESENT_VERT_SHADER, &PRESENT_FRAG_SHADER)?;
+        let accumulation = gl.get_unif_loc(&program, "accumulation")?;
+        gl.use_program(Some(&program));
+        gl.uniform1i(
+            Some(&accumulation),
+            (ACCUMULATION_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+        );
+
+        let Some(texture) = gl.create_texture() else {
+            gl.delete_program(Some(&program));
+            return Err(Error::Missing).context("Unable to create accumulation texture");
+        };
+        gl.active_texture(ACCUMULATION_TEXTURE_UNIT);
+        gl.bind_texture(WebGl::TEXTURE_2D, Some(&texture));
+        gl.tex_storage_2d(WebGl::TEXTURE_2D, 1, WebGl::RGBA16F, width, height);
+        for parameter in [WebGl::TEXTURE_MIN_FILTER, WebGl::TEXTURE_MAG_FILTER] {
+            gl.tex_parameteri(WebGl::TEXTURE_2D, parameter, WebGl::NEAREST as i32);
+        }
+
+        let Some(framebuffer) = gl.create_framebuffer() else {
+            gl.delete_texture(Some(&texture));
+            gl.delete_program(Some(&program));
+            return Err(Error::Missing).context("Unable to create accumulation framebuffer");
+        };
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, Some(&framebuffer));
+        gl.framebuffer_texture_2d(
+            WebGl::FRAMEBUFFER,
+            WebGl::COLOR_ATTACHMENT0,
+            WebGl::TEXTURE_2D,
+            Some(&texture),
+            0,
+        );
+        let status = gl.check_framebuffer_status(WebGl::FRAMEBUFFER);
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, None);
+        let accumulator = Self {
+            framebuffer,
+            texture,
+            program,
+            width,
+            height,
+            frame_count: 0,
+        };
+        if status != WebGl::FRAMEBUFFER_COMPLETE {
+            accumulator.delete(gl);
+            return Err(
+                Error::Message(format!("Accumulation framebuffer incomplete: {status}")).into(),
+            );
+        }
+        Ok(accumulator)
+    }
+
+    pub(crate) fn has_size(&self, width: i32, height: i32) -> bool {
+        self.width == width && self.height == height
+    }
+
+    pub(crate) fn is_converged(&self) -> bool {
+        self.frame_count >= MAX_ACCUMULATED_FRAMES
+    }
+
+    /// Binds the framebuffer and blending so the next draw is averaged into the
+    /// accumulation, discarding it first if `restart` is set. Returns the seed
+    /// for the frame's jitter.
+    pub(crate) fn begin_frame(&mut self, gl: &WebGl, restart: bool) -> i32 {
+        if restart {
+            self.frame_count = 0;
+        }
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, Some(&self.framebuffer));
+        if self.frame_count == 0 {
+            gl.clear_color(0.0, 0.0, 0.0, 0.0);
+            gl.clear(WebGl::COLOR_BUFFER_BIT);
+        }
+        // accumulation = frame / (n + 1) + accumulation * n / (n + 1)
+        gl.blend_color(0.0, 0.0, 0.0, 1.0 / (self.frame_count + 1) as f32);
+        gl.blend_func(WebGl::CONSTANT_ALPHA, WebGl::ONE_MINUS_CONSTANT_ALPHA);
+        self.frame_count += 1;
+        self.frame_count as i32 - 1
+    }
+
+    /// Draws the accumulated image over `background` into `framebuffer`, the
+    /// canvas if `None`
+    pub(crate) fn present(
+        &mut self,
+        gl: &WebGl,
+        framebuffer: Option<&WebGlFramebuffer>,
+        background: [f32; 4],
+    ) {
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, framebuffer);
+        let [r, g, b, a] = background;
+        gl.clear_color(r, g, b, a);
+        gl.clear(WebGl::COLOR_BUFFER_BIT);
+        gl.blend_func(WebGl::ONE, WebGl::ONE_MINUS_SRC_ALPHA);
+        gl.use_program(Some(&self.program));
+        gl.active_texture(ACCUMULATION_TEXTURE_UNIT);
+        gl.bind_texture(WebGl::TEXTURE_2D, Some(&self.texture));
+        gl.disable(WebGl::CULL_FACE);
+        gl.draw_arrays(WebGl::TRIANGLES, 0, 3);
+        gl.enable(WebGl::CULL_FACE);
+    }
+
+    pub(crate) fn delete(self, gl: &WebGl) {
+        gl.delete_framebuffer(Some(&self.framebuffer));
+        gl.delete_texture(Some(&self.texture));
+        gl.delete_program(Some(&self.program));
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::{Context, Result};
+use web_sys::WebGl2RenderingContext as WebGl;
+use web_sys::*;
+
+use super::Error;
+
+/// Offscreen 8 bit RGBA framebuffer that capture tiles are rendered into and
+/// read back from
+pub(crate) struct CaptureTarget {
+    framebuffer: WebGlFramebuffer,
+    renderbuffer: WebGlRenderbuffer,
+}
+
+impl CaptureTarget {
+    /// Largest side of a renderbuffer, and so of a capture tile
+    pub(crate) fn max_size(gl: &WebGl) -> Result<u32> {
+        let max_renderbuffer = gl
+            .get_parameter(WebGl::MAX_RENDERBUFFER_SIZE)
+            .map_err(|_| Error::Message("Js".into()))
+            .context("Failed to query the maximum renderbuffer size")?
+            .as_f64()
+            .ok_or(Error::Missing)
+            .context("Maximum renderbuffer size is not a number")?;
+        Ok(max_renderbuffer as u32)
+    }
+
+    pub(crate) fn new(gl: &WebGl, width: i32, height: i32) -> Result<Self> {
+        let renderbuffer = gl
+            .create_renderbuffer()
+            .ok_or(Error::Missing)
+            .context("Unable to create capture renderbuffer")?;
+        gl.bind_renderbuffer(WebGl::RENDERBUFFER, Some(&renderbuffer));
+        gl.renderbuffer_storage(WebGl::RENDERBUFFER, WebGl::RGBA8, width, height);
+        gl.bind_renderbuffer(WebGl::RENDERBUFFER, None);
+
+        let framebuffer = gl
+            .create_framebuffer()
+            .ok_or(Error::Missing)
+            .context("Unable to create capture framebuffer")?;
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, Some(&framebuffer));
+        gl.framebuffer_renderbuffer(
+            WebGl::FRAMEBUFFER,
+            WebGl::COLOR_ATTACHMENT0,
+            WebGl::RENDERBUFFER,
+            Some(&renderbuffer),
+        );
+        let status = gl.check_framebuffer_status(WebGl::FRAMEBUFFER);
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, None);
+        let target = Self {
+            framebuffer,
+            renderbuffer,
+        };
+        if status != WebGl::FRAMEBUFFER_COMPLETE {
+            target.delete(gl);
+            return Err(Error::Message(format!("Capture framebuffer incomplete: {status}")).into());
+        }
+        Ok(target)
+    }
+
+    pub(crate) fn framebuffer(&self) -> &WebGlFramebuffer {
+        &self.framebuffer
+    }
+
+    pub(crate) fn delete(self, gl: &WebGl) {
+        gl.delete_framebuffer(Some(&self.framebuffer));
+        gl.delete_renderbuffer(Some(&self.renderbuffer));
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use web_sys::WebGl2RenderingContext as WebGl;
+use web_sys::*;
+
+use super::gl_utils::GlUtils;
+use super::shaders::{HANDLE_FRAG_SHADER, HANDLE_VERT_SHADER};
+use crate::clipping::{CropBox, CropHandle};
+
+/// Draws the grabbable face centres of the crop box over the rendered volume
+pub(crate) struct CropHandleOverlay {
+    program: WebGlProgram,
+    proj_view: WebGlUniformLocation,
+    handles: WebGlUniformLocation,
+    active_handle: WebGlUniformLocation,
+}
+
+impl CropHandleOverlay {
+    pub(crate) fn new(gl: &WebGl) -> Result<Self> {
+        let program = gl.link_program_from(&HANDLE_VERT_SHADER, &HANDLE_FRAG_SHADER)?;
+        let proj_view = gl.get_unif_loc(&program, "proj_view")?;
+        let handles = gl.get_unif_loc(&program, "handles")?;
+        let active_handle = gl.get_unif_loc(&program, "active_handle")?;
+        Ok(Self {
+            program,
+            proj_view,
+            handles,
+            active_handle,
+        })
+    }
+
+    /// Draws onto whatever framebuffer is bound, highlighting `active`
+    pub(crate) fn draw(
+        &self,
+        gl: &WebGl,
+        proj_view: &[f32; 16],
+        crop_box: &CropBox,
+        active: Option<CropHandle>,
+    ) {
+        let positions: Vec<f32> = CropHandle::ALL
+            .iter()
+            .flat_map(|handle| {
+                let position = crop_box.handle_position(*handle);
+                [position.x, position.y, position.z]
+            })
+            .collect();
+        let active = active
+            .and_then(|active| CropHandle::ALL.iter().position(|handle| *handle == active))
+            .map(|index| index as i32)
+            .unwrap_or(-1);
+        gl.use_program(Some(&self.program));
+        gl.uniform_matrix4fv_with_f32_array(Some(&self.proj_view), false, proj_view);
+        gl.uniform3fv_with_f32_array(Some(&self.handles), &positions);
+        gl.uniform1i(Some(&self.active_handle), active);
+        gl.blend_func(WebGl::SRC_ALPHA, WebGl::ONE_MINUS_SRC_ALPHA);
+        gl.draw_arrays(WebGl::POINTS, 0, CropHandle::ALL.len() as i32);
+        gl.blend_func(WebGl::ONE, WebGl::ONE_MINUS_SRC_ALPHA);
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use web_sys::WebGl2RenderingContext as WebGl;
+use web_sys::*;
+
+use super::gl_utils::{GlUtils, TextureFormat};
+use super::Error;
+use crate::fusion::Fusion;
+use crate::transfer_function::{TransferFunction, TRANSFER_FUNCTION_SIZE};
+use crate::volume::Volume;
+
+/// Texture units of the second volume and its colormap
+pub(crate) const SECONDARY_TEXTURE_UNIT: u32 = WebGl::TEXTURE9;
+pub(crate) const SECONDARY_COLORMAP_TEXTURE_UNIT: u32 = WebGl::TEXTURE10;
+
+/// The second volume of a fusion and the lookup table of its transfer function
+pub(crate) struct FusionTextures {
+    volume: Arc<Volume>,
+    texture: WebGlTexture,
+    colormap: WebGlTexture,
+}
+
+impl FusionTextures {
+    fn new(gl: &WebGl, volume: Arc<Volume>) -> Result<Self> {
+        let texture = gl.create_texture_3d(
+            SECONDARY_TEXTURE_UNIT,
+            &TextureFormat {
+                internal_format: WebGl::R8,
+                format: WebGl::RED,
+                filter: WebGl::LINEAR,
+            },
+            volume.dims(),
+            volume.data(),
+        )?;
+        let colormap = gl
+            .create_texture()
+            .ok_or(Error::Missing)
+            .context("Unable to create fusion colormap texture")?;
+        gl.active_texture(SECONDARY_COLORMAP_TEXTURE_UNIT);
+        gl.bind_texture(WebGl::TEXTURE_2D, Some(&colormap));
+        gl.tex_storage_2d(
+            WebGl::TEXTURE_2D,
+            1,
+            WebGl::RGBA8,
+            TRANSFER_FUNCTION_SIZE as i32,
+            1,
+        );
+        for (parameter, value) in [
+            (WebGl::TEXTURE_MIN_FILTER, WebGl::LINEAR),
+            (WebGl::TEXTURE_MAG_FILTER, WebGl::LINEAR),
+            (WebGl::TEXTURE_WRAP_S, WebGl::CLAMP_TO_EDGE),
+            (WebGl::TEXTURE_WRAP_T, WebGl::CLAMP_TO_EDGE),
+        ] {
+            gl.tex_parameteri(WebGl::TEXTURE_2D, parameter, value as i32);
+        }
+        Ok(Self {
+            volume,
+            texture,
+            colormap,
+        })
+    }
+
+    /// Brings `current` in line with `fusion`, uploading the second volume
+    /// only if it was replaced
+    pub(crate) fn sync(
+        current: &mut Option<FusionTextures>,
+        gl: &WebGl,
+        fusion: &Fusion,
+    ) -> Result<()> {
+        let replaced = match (current.as_ref(), &fusion.secondary) {
+            (Some(textures), Some(secondary)) => !Arc::ptr_eq(&textures.volume, &secondary.volume),
+            (None, None) => false,
+            _ => true,
+        };
+        if replaced {
+            if let Some(textures) = current.take() {
+                gl.delete_texture(Some(&textures.texture));
+                gl.delete_texture(Some(&textures.colormap));
+            }
+            if let Some(secondary) = &fusion.secondary {
+                *current = Some(Self::new(gl, secondary.volume.clone())?);
+            }
+        }
+        if let (Some(textures), Some(secondary)) = (current, &fusion.secondary) {
+            textures.upload_colormap(gl, &secondary.transfer_function)?;
+        }
+        Ok(())
+    }
+
+    fn upload_colormap(&self, gl: &WebGl, transfer_function: &TransferFunction) -> Result<()> {
+        gl.active_texture(SECONDARY_COLORMAP_TEXTURE_UNIT);
+        gl.bind_texture(WebGl::TEXTURE_2D, Some(&self.colormap));
+        gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+            WebGl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            TRANSFER_FUNCTION_SIZE as i32,
+            1,
+            WebGl::RGBA,
+            WebGl::UNSIGNED_BYTE,
+            Some(&transfer_function.lookup_table()),
+        )
+        .map_err(|_| Error::Message("Js".into()))
+        .context("Failed to upload fusion transfer function")?;
+        Ok(())
+    }
+}
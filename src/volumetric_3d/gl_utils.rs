@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use web_sys::WebGl2RenderingContext as WebGl;
 use web_sys::*;
 
+use super::shaders::{FragmentShader, Shader, VertexShader};
 use super::Error;
 pub(crate) trait GlUtils {
     fn get_unif_loc(
@@ -9,6 +10,30 @@ pub(crate) trait GlUtils {
         program: &WebGlProgram,
         location_name: &str,
     ) -> Result<WebGlUniformLocation>;
+
+    fn compile_shader<'b, T: Shader<'b>>(&self, shader: &T) -> Result<WebGlShader>;
+
+    /// Compiles both shaders and links them into a program
+    fn link_program_from(
+        &self,
+        vertex_shader: &VertexShader,
+        fragment_shader: &FragmentShader,
+    ) -> Result<WebGlProgram>;
+
+    /// Creates a clamped 3D texture on `texture_unit` and fills it with `data`
+    fn create_texture_3d(
+        &self,
+        texture_unit: u32,
+        texture_format: &TextureFormat,
+        dims: [usize; 3],
+        data: &[u8],
+    ) -> Result<WebGlTexture>;
+}
+
+pub(crate) struct TextureFormat {
+    pub(crate) internal_format: u32,
+    pub(crate) format: u32,
+    pub(crate) filter: u32,
 }
 
 impl GlUtils for WebGl {
@@ -25,4 +50,106 @@ impl GlUtils for WebGl {
             ))
             .into()
     }
+
+    fn compile_shader<'b, T: Shader<'b>>(&self, shader: &T) -> Result<WebGlShader> {
+        let to_compile_shader = self
+            .create_shader(shader.code())
+            .ok_or(Error::Missing)
+            .context("Error creating shader")?;
+        self.shader_source(&to_compile_shader, shader.source());
+        self.compile_shader(&to_compile_shader);
+        let compiled_shader = to_compile_shader;
+        let status = self
+            .get_shader_parameter(&compiled_shader, WebGl::COMPILE_STATUS)
+            .as_bool()
+            .ok_or(Error::Missing)
+            .context("Compile failed. Unable to get params")?;
+        match status {
+            false => {
+                let error_message = self
+                    .get_shader_info_log(&compiled_shader)
+                    .unwrap_or_else(|| String::from("No compiler log"));
+                Err(Error::Message(error_message).into())
+            }
+            true => Ok(compiled_shader),
+        }
+    }
+
+    fn link_program_from(
+        &self,
+        vertex_shader: &VertexShader,
+        fragment_shader: &FragmentShader,
+    ) -> Result<WebGlProgram> {
+        let compiled_vertex_shader = GlUtils::compile_shader(self, vertex_shader)?;
+        let compiled_fragment_shader = GlUtils::compile_shader(self, fragment_shader)?;
+
+        let program = self
+            .create_program()
+            .ok_or(Error::Missing)
+            .context("Unable to create program")?;
+        self.attach_shader(&program, &compiled_vertex_shader);
+        self.attach_shader(&program, &compiled_fragment_shader);
+        self.link_program(&program);
+
+        let program_status = self
+            .get_program_parameter(&program, WebGl::LINK_STATUS)
+            .as_bool()
+            .ok_or(Error::Missing)
+            .context("Failed to get program status")?;
+        if !program_status {
+            return Err(Error::Message("Failed to attach shaders to program".to_string()).into());
+        };
+        Ok(program)
+    }
+
+    fn create_texture_3d(
+        &self,
+        texture_unit: u32,
+        texture_format: &TextureFormat,
+        dims: [usize; 3],
+        data: &[u8],
+    ) -> Result<WebGlTexture> {
+        let [x, y, z] = dims.map(|d| d as i32);
+        let texture = self
+            .create_texture()
+            .ok_or(Error::Missing)
+            .context("Couldn't create 3D texture")?;
+        self.active_texture(texture_unit);
+        self.bind_texture(WebGl::TEXTURE_3D, Some(&texture));
+        self.tex_storage_3d(
+            WebGl::TEXTURE_3D,
+            1,
+            texture_format.internal_format,
+            x,
+            y,
+            z,
+        );
+        for (parameter, value) in [
+            (WebGl::TEXTURE_MIN_FILTER, texture_format.filter),
+            (WebGl::TEXTURE_MAG_FILTER, texture_format.filter),
+            (WebGl::TEXTURE_WRAP_R, WebGl::CLAMP_TO_EDGE),
+            (WebGl::TEXTURE_WRAP_S, WebGl::CLAMP_TO_EDGE),
+            (WebGl::TEXTURE_WRAP_T, WebGl::CLAMP_TO_EDGE),
+        ] {
+            self.tex_parameteri(WebGl::TEXTURE_3D, parameter, value as i32);
+        }
+        // Rows of RGB or single channel data are not necessarily 4 byte aligned
+        self.pixel_storei(WebGl::UNPACK_ALIGNMENT, 1);
+        self.tex_sub_image_3d_with_opt_u8_array(
+            WebGl::TEXTURE_3D,
+            0,
+            0,
+            0,
+            0,
+            x,
+            y,
+            z,
+            texture_format.format,
+            WebGl::UNSIGNED_BYTE,
+            Some(data),
+        )
+        .map_err(|_| Error::Message("Js".into()))
+        .context("failed tex sub image 3d")?;
+        Ok(texture)
+    }
 }
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use web_sys::WebGl2RenderingContext as WebGl;
+use web_sys::*;
+
+use super::gl_utils::{GlUtils, TextureFormat};
+use super::Error;
+use crate::labels::{LabelMap, LabelOverlay, MAX_LABELS};
+
+/// The label map as an integer texture, sampled with nearest filtering, and
+/// the colour and opacity of each label
+pub(crate) struct LabelTextures {
+    map: Arc<LabelMap>,
+    map_texture: WebGlTexture,
+    lookup: WebGlTexture,
+    lookup_unit: u32,
+}
+
+impl LabelTextures {
+    fn new(gl: &WebGl, map: Arc<LabelMap>, map_unit: u32, lookup_unit: u32) -> Result<Self> {
+        let map_texture = gl.create_texture_3d(
+            map_unit,
+            &TextureFormat {
+                internal_format: WebGl::R8UI,
+                format: WebGl::RED_INTEGER,
+                filter: WebGl::NEAREST,
+            },
+            map.dims(),
+            map.data(),
+        )?;
+        let lookup = gl
+            .create_texture()
+            .ok_or(Error::Missing)
+            .context("Unable to create label colour texture")?;
+        gl.active_texture(lookup_unit);
+        gl.bind_texture(WebGl::TEXTURE_2D, Some(&lookup));
+        gl.tex_storage_2d(WebGl::TEXTURE_2D, 1, WebGl::RGBA8, MAX_LABELS as i32, 1);
+        for parameter in [WebGl::TEXTURE_MIN_FILTER, WebGl::TEXTURE_MAG_FILTER] {
+            gl.tex_parameteri(WebGl::TEXTURE_2D, parameter, WebGl::NEAREST as i32);
+        }
+        Ok(Self {
+            map,
+            map_texture,
+            lookup,
+            lookup_unit,
+        })
+    }
+
+    /// Brings `current` in line with `overlay`, uploading the map only if it
+    /// was replaced. Returns whether the map was replaced.
+    pub(crate) fn sync(
+        current: &mut Option<LabelTextures>,
+        gl: &WebGl,
+        overlay: &LabelOverlay,
+        map_unit: u32,
+        lookup_unit: u32,
+    ) -> Result<bool> {
+        let replaced = match (current.as_ref(), &overlay.map) {
+            (Some(textures), Some(map)) => !Arc::ptr_eq(&textures.map, map),
+            (None, None) => false,
+            _ => true,
+        };
+        if replaced {
+            if let Some(textures) = current.take() {
+                gl.delete_texture(Some(&textures.map_texture));
+                gl.delete_texture(Some(&textures.lookup));
+            }
+            if let Some(map) = &overlay.map {
+                *current = Some(Self::new(gl, map.clone(), map_unit, lookup_unit)?);
+            }
+        }
+        if let Some(textures) = current {
+            textures.upload_lookup(gl, &overlay.table.lookup_table())?;
+        }
+        Ok(replaced)
+    }
+
+    fn upload_lookup(&self, gl: &WebGl, lookup_table: &[u8]) -> Result<()> {
+        gl.active_texture(self.lookup_unit);
+        gl.bind_texture(WebGl::TEXTURE_2D, Some(&self.lookup));
+        gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+            WebGl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            MAX_LABELS as i32,
+            1,
+            WebGl::RGBA,
+            WebGl::UNSIGNED_BYTE,
+            Some(lookup_table),
+        )
+        .map_err(|_| Error::Message("Js".into()))
+        .context("Failed to upload label colours")?;
+        Ok(())
+    }
+
+    pub(crate) fn map(&self) -> &LabelMap {
+        &self.map
+    }
+}
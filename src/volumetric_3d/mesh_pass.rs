@@ -0,0 +1,224 @@
+use anyhow::{Context, Result};
+use cgmath::Vector3;
+use wasm_bindgen::JsCast;
+use web_sys::WebGl2RenderingContext as WebGl;
+use web_sys::*;
+
+use super::gl_utils::GlUtils;
+use super::shaders::{MESH_FRAG_SHADER, MESH_VERT_SHADER};
+use super::Error;
+use crate::mesh::SceneMesh;
+
+/// Texture units the ray marcher reads the mesh colour and depth from
+pub(crate) const MESH_COLOR_TEXTURE_UNIT: u32 = WebGl::TEXTURE4;
+pub(crate) const MESH_DEPTH_TEXTURE_UNIT: u32 = WebGl::TEXTURE5;
+
+/// Draws the opaque meshes into an offscreen colour and depth buffer, which
+/// the ray marcher reads to stop rays at the mesh surface
+pub(crate) struct MeshPass {
+    program: WebGlProgram,
+    proj_view: WebGlUniformLocation,
+    model: WebGlUniformLocation,
+    eye_pos: WebGlUniformLocation,
+    mesh_color: WebGlUniformLocation,
+    targets: Option<MeshTargets>,
+    meshes: Vec<GpuMesh>,
+    /// Generation of the meshes in `meshes`
+    generation: u64,
+}
+
+struct MeshTargets {
+    framebuffer: WebGlFramebuffer,
+    color: WebGlTexture,
+    depth: WebGlTexture,
+    width: i32,
+    height: i32,
+}
+
+struct GpuMesh {
+    vertex_array: WebGlVertexArrayObject,
+    buffer: WebGlBuffer,
+    vertex_count: i32,
+    color: [f32; 3],
+    model: [f32; 16],
+}
+
+impl MeshPass {
+    pub(crate) fn new(gl: &WebGl) -> Result<Self> {
+        let program = gl.link_program_from(&MESH_VERT_SHADER, &MESH_FRAG_SHADER)?;
+        Ok(Self {
+            proj_view: gl.get_unif_loc(&program, "proj_view")?,
+            model: gl.get_unif_loc(&program, "model")?,
+            eye_pos: gl.get_unif_loc(&program, "eye_pos")?,
+            mesh_color: gl.get_unif_loc(&program, "mesh_color")?,
+            program,
+            targets: None,
+            meshes: Vec::new(),
+            generation: 0,
+        })
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub(crate) fn has_meshes(&self) -> bool {
+        !self.meshes.is_empty()
+    }
+
+    /// Replaces the uploaded meshes with `meshes`
+    pub(crate) fn update_meshes(
+        &mut self,
+        gl: &WebGl,
+        generation: u64,
+        meshes: &[SceneMesh],
+    ) -> Result<()> {
+        for mesh in self.meshes.drain(..) {
+            gl.delete_vertex_array(Some(&mesh.vertex_array));
+            gl.delete_buffer(Some(&mesh.buffer));
+        }
+        let previous_vertex_array = bound_vertex_array(gl);
+        for mesh in meshes {
+            let vertices = mesh.mesh.interleaved_vertices();
+            let vertex_array = gl
+                .create_vertex_array()
+                .ok_or(Error::Missing)
+                .context("Unable to create mesh vertex array")?;
+            let buffer = gl
+                .create_buffer()
+                .ok_or(Error::Missing)
+                .context("Unable to create mesh buffer")?;
+            gl.bind_vertex_array(Some(&vertex_array));
+            gl.bind_buffer(WebGl::ARRAY_BUFFER, Some(&buffer));
+            gl.buffer_data_with_array_buffer_view(
+                WebGl::ARRAY_BUFFER,
+                &js_sys::Float32Array::from(vertices.as_slice()),
+                WebGl::STATIC_DRAW,
+            );
+            // Position then normal, three floats each
+            for (attribute, offset) in [(0, 0), (1, 12)] {
+                gl.enable_vertex_attrib_array(attribute);
+                gl.vertex_attrib_pointer_with_i32(attribute, 3, WebGl::FLOAT, false, 24, offset);
+            }
+            let model: &[f32; 16] = mesh.model.as_ref();
+            self.meshes.push(GpuMesh {
+                vertex_array,
+                buffer,
+                vertex_count: (vertices.len() / 6) as i32,
+                color: mesh.color,
+                model: *model,
+            });
+        }
+        gl.bind_vertex_array(previous_vertex_array.as_ref());
+        self.generation = generation;
+        Ok(())
+    }
+
+    /// Keeps the offscreen buffers the size of the canvas
+    pub(crate) fn resize(&mut self, gl: &WebGl, width: i32, height: i32) -> Result<()> {
+        if let Some(targets) = &self.targets {
+            if targets.width == width && targets.height == height {
+                return Ok(());
+            }
+        }
+        if let Some(targets) = self.targets.take() {
+            gl.delete_framebuffer(Some(&targets.framebuffer));
+            gl.delete_texture(Some(&targets.color));
+            gl.delete_texture(Some(&targets.depth));
+        }
+        self.targets = Some(MeshTargets::new(gl, width, height)?);
+        Ok(())
+    }
+
+    /// Draws the meshes offscreen, leaving the canvas bound afterwards.
+    /// Returns whether there was anything to draw.
+    pub(crate) fn draw(&self, gl: &WebGl, proj_view: &[f32; 16], eye_pos: Vector3<f32>) -> bool {
+        let Some(targets) = &self.targets else {
+            return false;
+        };
+        if self.meshes.is_empty() {
+            return false;
+        }
+        let previous_vertex_array = bound_vertex_array(gl);
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, Some(&targets.framebuffer));
+        gl.clear_color(0.0, 0.0, 0.0, 0.0);
+        gl.clear_depth(1.0);
+        gl.clear(WebGl::COLOR_BUFFER_BIT | WebGl::DEPTH_BUFFER_BIT);
+        gl.enable(WebGl::DEPTH_TEST);
+        gl.disable(WebGl::BLEND);
+        gl.disable(WebGl::CULL_FACE);
+        gl.use_program(Some(&self.program));
+        gl.uniform_matrix4fv_with_f32_array(Some(&self.proj_view), false, proj_view);
+        gl.uniform3f(Some(&self.eye_pos), eye_pos.x, eye_pos.y, eye_pos.z);
+        for mesh in &self.meshes {
+            gl.uniform_matrix4fv_with_f32_array(Some(&self.model), false, &mesh.model);
+            gl.uniform3fv_with_f32_array(Some(&self.mesh_color), &mesh.color);
+            gl.bind_vertex_array(Some(&mesh.vertex_array));
+            gl.draw_arrays(WebGl::TRIANGLES, 0, mesh.vertex_count);
+        }
+        gl.bind_vertex_array(previous_vertex_array.as_ref());
+        gl.disable(WebGl::DEPTH_TEST);
+        gl.enable(WebGl::BLEND);
+        gl.enable(WebGl::CULL_FACE);
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, None);
+        true
+    }
+}
+
+impl MeshTargets {
+    fn new(gl: &WebGl, width: i32, height: i32) -> Result<Self> {
+        let create_texture = |unit, internal_format| -> Result<WebGlTexture> {
+            let texture = gl
+                .create_texture()
+                .ok_or(Error::Missing)
+                .context("Unable to create mesh texture")?;
+            gl.active_texture(unit);
+            gl.bind_texture(WebGl::TEXTURE_2D, Some(&texture));
+            gl.tex_storage_2d(WebGl::TEXTURE_2D, 1, internal_format, width, height);
+            for parameter in [WebGl::TEXTURE_MIN_FILTER, WebGl::TEXTURE_MAG_FILTER] {
+                gl.tex_parameteri(WebGl::TEXTURE_2D, parameter, WebGl::NEAREST as i32);
+            }
+            Ok(texture)
+        };
+        let color = create_texture(MESH_COLOR_TEXTURE_UNIT, WebGl::RGBA8)?;
+        let depth = create_texture(MESH_DEPTH_TEXTURE_UNIT, WebGl::DEPTH_COMPONENT24)?;
+
+        let framebuffer = gl
+            .create_framebuffer()
+            .ok_or(Error::Missing)
+            .context("Unable to create mesh framebuffer")?;
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, Some(&framebuffer));
+        for (attachment, texture) in [
+            (WebGl::COLOR_ATTACHMENT0, &color),
+            (WebGl::DEPTH_ATTACHMENT, &depth),
+        ] {
+            gl.framebuffer_texture_2d(
+                WebGl::FRAMEBUFFER,
+                attachment,
+                WebGl::TEXTURE_2D,
+                Some(texture),
+                0,
+            );
+        }
+        let status = gl.check_framebuffer_status(WebGl::FRAMEBUFFER);
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, None);
+        if status != WebGl::FRAMEBUFFER_COMPLETE {
+            return Err(Error::Message(format!("Mesh framebuffer incomplete: {status}")).into());
+        }
+        Ok(Self {
+            framebuffer,
+            color,
+            depth,
+            width,
+            height,
+        })
+    }
+}
+
+/// The cube the ray marcher draws is left bound, so it's restored after
+/// drawing meshes
+fn bound_vertex_array(gl: &WebGl) -> Option<WebGlVertexArrayObject> {
+    gl.get_parameter(WebGl::VERTEX_ARRAY_BINDING)
+        .ok()
+        .and_then(|binding| binding.dyn_into().ok())
+}
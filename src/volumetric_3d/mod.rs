@@ -1,25 +1,69 @@
+mod accumulation;
+mod capture_target;
+mod crop_handles;
+mod fusion_textures;
 mod gl_utils;
+mod label_textures;
+mod mesh_pass;
 pub(crate) mod shaders;
+mod slice_planes;
+pub(crate) mod slice_view;
 
 extern crate wasm_bindgen;
+use accumulation::Accumulator;
 use anyhow::{Context, Result};
-use cgmath::Matrix4;
-use gl_utils::GlUtils;
+use capture_target::CaptureTarget;
+use cgmath::{Matrix4, SquareMatrix};
+use crop_handles::CropHandleOverlay;
+use fusion_textures::{FusionTextures, SECONDARY_COLORMAP_TEXTURE_UNIT, SECONDARY_TEXTURE_UNIT};
+use gl_utils::{GlUtils, TextureFormat};
+use label_textures::LabelTextures;
+use mesh_pass::{MeshPass, MESH_COLOR_TEXTURE_UNIT, MESH_DEPTH_TEXTURE_UNIT};
+use slice_planes::SlicePlaneOverlay;
+use slice_view::SliceViews;
 use wasm_bindgen::prelude::*;
 use web_sys::WebGl2RenderingContext as WebGl;
 use web_sys::*;
 
 use crate::{
     app_state::{
-        get_arcball_data, get_canvas_dims, set_arcball_changed_to_false_after_draw, should_i_draw,
-        AppState, DrawData,
+        finish_export_frame, get_arcball_data, get_canvas_dims, get_capture_data,
+        get_fusion_update, get_label_update, get_mesh_update, get_render_settings,
+        get_transfer_function_update, next_export_frame, take_capture_request,
+        take_screenshot_request, AppState, DrawData, FramePlan, GradientSource, Light, RenderMode,
+        RenderSettings,
     },
+    backend::{FrameUniforms, RenderBackend},
+    capture::{self, Tile},
+    clipping::MAX_CLIP_PLANES,
+    fusion::{Fusion, FusionMode},
+    image::{PngText, RgbaImage},
+    labels::{LabelCells, LabelOverlay},
+    macrocells::{MacrocellGrid, MACROCELL_SIZE},
+    mesh::SceneMesh,
+    transfer_function::{TransferFunction, TRANSFER_FUNCTION_SIZE},
+    util::download_bytes,
+    volume::Volume,
     CanvasDims, SharedMut,
 };
 
-const VOLUME_X: i32 = 256;
-const VOLUME_Y: i32 = 256;
-const VOLUME_Z: i32 = 256;
+/// Framebuffer a frame ends up in and the background it's drawn over
+pub(crate) struct RenderTarget<'a> {
+    framebuffer: Option<&'a WebGlFramebuffer>,
+    background: [f32; 4],
+}
+
+impl RenderTarget<'_> {
+    const CANVAS: RenderTarget<'static> = RenderTarget {
+        framebuffer: None,
+        background: [1.0, 1.0, 1.0, 1.0],
+    };
+}
+
+/// Texture units of the label map and the label colours
+const LABELS_TEXTURE_UNIT: u32 = WebGl::TEXTURE6;
+const LABEL_COLORS_TEXTURE_UNIT: u32 = WebGl::TEXTURE8;
+
 const CUBE_STRIP: [u8; 42] = [
     255, 255, 0, 0, 255, 0, 255, 255, 255, 0, 255, 255, 0, 0, 255, 0, 255, 0, 0, 0, 0, 255, 255, 0,
     255, 0, 0, 255, 255, 255, 255, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0, 0,
@@ -86,11 +130,46 @@ impl GlState<EmptyState> {
 pub(crate) struct Volumetric3DLocations {
     proj_view: WebGlUniformLocation,
     camera_pos: WebGlUniformLocation,
+    orthographic: WebGlUniformLocation,
+    view_dir: WebGlUniformLocation,
     colormap: WebGlUniformLocation,
     vol_dims: WebGlUniformLocation,
     volume: WebGlUniformLocation,
     vol_scale: WebGlUniformLocation,
     dt_scale: WebGlUniformLocation,
+    ert_threshold: WebGlUniformLocation,
+    frame_seed: WebGlUniformLocation,
+    canvas_width: WebGlUniformLocation,
+    output_srgb: WebGlUniformLocation,
+    render_mode: WebGlUniformLocation,
+    iso_value: WebGlUniformLocation,
+    headlight: WebGlUniformLocation,
+    light_dir: WebGlUniformLocation,
+    dvr_shading: WebGlUniformLocation,
+    precomputed_gradients: WebGlUniformLocation,
+    gradients: WebGlUniformLocation,
+    gradient_scale: WebGlUniformLocation,
+    occupancy: WebGlUniformLocation,
+    macrocell_size: WebGlUniformLocation,
+    skip_empty_space: WebGlUniformLocation,
+    crop_min: WebGlUniformLocation,
+    crop_max: WebGlUniformLocation,
+    clip_planes: WebGlUniformLocation,
+    num_clip_planes: WebGlUniformLocation,
+    has_mesh: WebGlUniformLocation,
+    mesh_color: WebGlUniformLocation,
+    mesh_depth: WebGlUniformLocation,
+    inv_proj_view: WebGlUniformLocation,
+    has_labels: WebGlUniformLocation,
+    labels: WebGlUniformLocation,
+    label_colors: WebGlUniformLocation,
+    label_blend: WebGlUniformLocation,
+    has_secondary: WebGlUniformLocation,
+    secondary: WebGlUniformLocation,
+    secondary_colormap: WebGlUniformLocation,
+    secondary_inv_model: WebGlUniformLocation,
+    fusion_mode: WebGlUniformLocation,
+    fusion_weight: WebGlUniformLocation,
 }
 
 impl Volumetric3DLocations {
@@ -107,6 +186,19 @@ impl Volumetric3DLocations {
         gl.uniform1f(Some(&self.dt_scale), scale);
     }
 
+    fn assign_ert_threshold(&mut self, gl: &WebGl, threshold: f32) {
+        gl.uniform1f(Some(&self.ert_threshold), threshold);
+    }
+
+    fn assign_jitter(&mut self, gl: &WebGl, frame_seed: i32, canvas_width: i32) {
+        gl.uniform1i(Some(&self.frame_seed), frame_seed);
+        gl.uniform1i(Some(&self.canvas_width), canvas_width);
+    }
+
+    fn assign_output_srgb(&mut self, gl: &WebGl, output_srgb: bool) {
+        gl.uniform1i(Some(&self.output_srgb), output_srgb as i32);
+    }
+
     fn assign_vol_dims(&mut self, gl: &WebGl, dimensions: &[i32; 3]) {
         gl.uniform3iv_with_i32_array(Some(&self.vol_dims), dimensions);
     }
@@ -119,9 +211,126 @@ impl Volumetric3DLocations {
         gl.uniform3fv_with_f32_array(Some(&self.camera_pos), camera_pos);
     }
 
+    fn assign_projection(&mut self, gl: &WebGl, orthographic: bool, view_dir: &[f32; 3]) {
+        gl.uniform1i(Some(&self.orthographic), orthographic as i32);
+        gl.uniform3fv_with_f32_array(Some(&self.view_dir), view_dir);
+    }
+
     fn assign_proj_view(&mut self, gl: &WebGl, proj_view_data: &[f32; 16]) {
         gl.uniform_matrix4fv_with_f32_array(Some(&self.proj_view), false, proj_view_data);
     }
+
+    fn assign_render_mode(&mut self, gl: &WebGl, mode: RenderMode) {
+        let mode = match mode {
+            RenderMode::Dvr => 0,
+            RenderMode::Isosurface => 1,
+        };
+        gl.uniform1i(Some(&self.render_mode), mode);
+    }
+
+    /// `iso_value` is in raw data units and is rescaled to match the uploaded texture
+    fn assign_iso_value(&mut self, gl: &WebGl, iso_value: f32) {
+        gl.uniform1f(Some(&self.iso_value), iso_value / 255.0);
+    }
+
+    fn assign_light(&mut self, gl: &WebGl, light: &Light) {
+        let dir = light.direction();
+        gl.uniform1i(Some(&self.headlight), light.headlight as i32);
+        gl.uniform3fv_with_f32_array(Some(&self.light_dir), &[dir.x, dir.y, dir.z]);
+    }
+
+    fn assign_gradients(&mut self, gl: &WebGl, location: i32) {
+        gl.uniform1i(Some(&self.gradients), location);
+    }
+
+    fn assign_occupancy(&mut self, gl: &WebGl, location: i32) {
+        gl.uniform1i(Some(&self.occupancy), location);
+    }
+
+    fn assign_macrocell_size(&mut self, gl: &WebGl, size: &[f32; 3]) {
+        gl.uniform3fv_with_f32_array(Some(&self.macrocell_size), size);
+    }
+
+    fn assign_skip_empty_space(&mut self, gl: &WebGl, skip_empty_space: bool) {
+        gl.uniform1i(Some(&self.skip_empty_space), skip_empty_space as i32);
+    }
+
+    /// Only enabled clipping planes are uploaded, packed at the front
+    fn assign_clipping(&mut self, gl: &WebGl, render_settings: &RenderSettings) {
+        let crop_box = &render_settings.crop_box;
+        gl.uniform3fv_with_f32_array(Some(&self.crop_min), &crop_box.min);
+        gl.uniform3fv_with_f32_array(Some(&self.crop_max), &crop_box.max);
+        let mut planes = [0.0; 4 * MAX_CLIP_PLANES];
+        let mut num_planes = 0;
+        for plane in render_settings.clip_planes.iter().filter(|p| p.enabled) {
+            planes[4 * num_planes..4 * (num_planes + 1)].copy_from_slice(&plane.equation());
+            num_planes += 1;
+        }
+        gl.uniform4fv_with_f32_array(Some(&self.clip_planes), &planes);
+        gl.uniform1i(Some(&self.num_clip_planes), num_planes as i32);
+    }
+
+    fn assign_mesh_textures(&mut self, gl: &WebGl, color_location: i32, depth_location: i32) {
+        gl.uniform1i(Some(&self.mesh_color), color_location);
+        gl.uniform1i(Some(&self.mesh_depth), depth_location);
+    }
+
+    /// `inv_proj_view` is only read where `has_mesh` is set
+    fn assign_mesh(&mut self, gl: &WebGl, has_mesh: bool, inv_proj_view: &[f32; 16]) {
+        gl.uniform1i(Some(&self.has_mesh), has_mesh as i32);
+        gl.uniform_matrix4fv_with_f32_array(Some(&self.inv_proj_view), false, inv_proj_view);
+    }
+
+    fn assign_label_textures(&mut self, gl: &WebGl, labels_location: i32, colors_location: i32) {
+        gl.uniform1i(Some(&self.labels), labels_location);
+        gl.uniform1i(Some(&self.label_colors), colors_location);
+    }
+
+    fn assign_labels(&mut self, gl: &WebGl, has_labels: bool, label_blend: f32) {
+        gl.uniform1i(Some(&self.has_labels), has_labels as i32);
+        gl.uniform1f(Some(&self.label_blend), label_blend);
+    }
+
+    fn assign_secondary_textures(
+        &mut self,
+        gl: &WebGl,
+        volume_location: i32,
+        colormap_location: i32,
+    ) {
+        gl.uniform1i(Some(&self.secondary), volume_location);
+        gl.uniform1i(Some(&self.secondary_colormap), colormap_location);
+    }
+
+    fn assign_fusion(
+        &mut self,
+        gl: &WebGl,
+        has_secondary: bool,
+        fusion: &Fusion,
+        primary_model: &Matrix4<f32>,
+    ) {
+        let mode = match fusion.mode {
+            FusionMode::AlphaBlend => 0,
+            FusionMode::Additive => 1,
+            FusionMode::Mask => 2,
+        };
+        let inv_model: [f32; 16] = *fusion.secondary_inv_model(primary_model).as_ref();
+        gl.uniform1i(Some(&self.has_secondary), has_secondary as i32);
+        gl.uniform1i(Some(&self.fusion_mode), mode);
+        gl.uniform1f(Some(&self.fusion_weight), fusion.weight);
+        gl.uniform_matrix4fv_with_f32_array(Some(&self.secondary_inv_model), false, &inv_model);
+    }
+
+    fn assign_gradient_scale(&mut self, gl: &WebGl, scale: f32) {
+        gl.uniform1f(Some(&self.gradient_scale), scale);
+    }
+
+    fn assign_shading(&mut self, gl: &WebGl, dvr_shading: bool, gradient_source: GradientSource) {
+        gl.uniform1i(Some(&self.dvr_shading), dvr_shading as i32);
+        gl.uniform1i(
+            Some(&self.precomputed_gradients),
+            (gradient_source == GradientSource::Precomputed) as i32,
+        );
+    }
 }
 
 pub(crate) struct ProgramCompiled<UniformLocations> {
@@ -133,14 +342,39 @@ impl ProgramCompiled<Volumetric3DLocations> {
     fn init(&mut self, gl: &WebGl) {
         self.locations.assign_vol_loc(gl, 0);
         self.locations.assign_colormap(gl, 1);
-        self.locations.assign_dt_scale(gl, 1.0);
+        self.locations.assign_gradients(gl, 2);
+        self.locations.assign_occupancy(gl, 3);
+        self.locations.assign_mesh_textures(
+            gl,
+            (MESH_COLOR_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+            (MESH_DEPTH_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+        );
+        self.locations.assign_label_textures(
+            gl,
+            (LABELS_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+            (LABEL_COLORS_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+        );
+        self.locations.assign_secondary_textures(
+            gl,
+            (SECONDARY_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+            (SECONDARY_COLORMAP_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+        );
     }
 }
 
 impl ProgramCompiledWithTextures<Volumetric3DLocations, Volumetric3DTextures> {
-    fn set_volume_metadata(&mut self, gl: &WebGl, volume_dims: &[i32; 3], volume_scale: &[f32; 3]) {
+    fn set_volume_metadata(
+        &mut self,
+        gl: &WebGl,
+        volume_dims: &[i32; 3],
+        volume_scale: &[f32; 3],
+        gradient_scale: f32,
+    ) {
         self.locations.assign_vol_scale(gl, volume_scale);
         self.locations.assign_vol_dims(gl, volume_dims);
+        self.locations.assign_gradient_scale(gl, gradient_scale);
+        let macrocell_size = volume_dims.map(|d| MACROCELL_SIZE as f32 / d as f32);
+        self.locations.assign_macrocell_size(gl, &macrocell_size);
     }
 }
 
@@ -153,63 +387,841 @@ pub(crate) struct ProgramCompiledWithTextures<Locations, Textures> {
 pub struct ProgramReady(
     WebGl,
     ProgramCompiledWithTextures<Volumetric3DLocations, Volumetric3DTextures>,
+    Option<Accumulator>,
+    Overlays,
+    Option<MeshPass>,
+    /// Size an accumulation buffer last failed to be created at, so it's only
+    /// tried again once the size changes
+    Option<(i32, i32)>,
 );
 
+/// Programs drawing editing aids over the rendered volume, created the first
+/// time they're shown
+#[derive(Default)]
+pub(crate) struct Overlays {
+    crop_handles: Option<CropHandleOverlay>,
+    slice_planes: Option<SlicePlaneOverlay>,
+}
+
+impl Overlays {
+    fn prepare(&mut self, gl: &WebGl, draw_data: &DrawData) -> Result<()> {
+        if draw_data.crop_handles.is_some() && self.crop_handles.is_none() {
+            self.crop_handles = Some(CropHandleOverlay::new(gl)?);
+        }
+        if draw_data.slice_planes.is_some() && self.slice_planes.is_none() {
+            self.slice_planes = Some(SlicePlaneOverlay::new(gl)?);
+        }
+        Ok(())
+    }
+
+    fn draw(&self, gl: &WebGl, proj_view: &[f32; 16], draw_data: &DrawData) {
+        if let (Some(overlay), Some(cursor)) = (&self.slice_planes, &draw_data.slice_planes) {
+            overlay.draw(gl, proj_view, cursor);
+        }
+        if let (Some(overlay), Some((crop_box, active))) =
+            (&self.crop_handles, draw_data.crop_handles)
+        {
+            overlay.draw(gl, proj_view, &crop_box, active);
+        }
+    }
+}
+
 pub(crate) struct Volumetric3DTextures {
     colormap: WebGlTexture,
-    volumetric: WebGlTexture,
+    /// Only kept to hold on to the volume and gradient textures, which stay
+    /// bound to their texture units
+    _volumetric: WebGlTexture,
+    _gradients: WebGlTexture,
+    occupancy: WebGlTexture,
+    macrocells: MacrocellGrid,
+    /// Generation of the transfer function in `colormap` and `occupancy`
+    transfer_function_generation: u64,
+    /// Window and colormap brightening of that transfer function, which the
+    /// slice views apply themselves
+    window: (f32, f32),
+    color_scale: f32,
+    /// Macrocells the transfer function gives some opacity
+    transfer_occupancy: Vec<u8>,
+    labels: Option<LabelTextures>,
+    label_cells: Option<LabelCells>,
+    /// Macrocells holding a shown label, if a label map is loaded
+    label_occupancy: Option<Vec<u8>>,
+    label_blend: f32,
+    /// Generation of the label overlay in `labels`
+    labels_generation: u64,
+    fusion_textures: Option<FusionTextures>,
+    fusion: Fusion,
+    /// Generation of `fusion`
+    fusion_generation: u64,
+    /// Model matrix of the volume, which the fused volume is resampled through
+    volume_model: Matrix4<f32>,
+}
+
+impl Volumetric3DTextures {
+    /// Uploads a changed transfer function and rebuilds the macrocell
+    /// occupancy to match it
+    fn update_transfer_function(
+        &mut self,
+        gl: &WebGl,
+        generation: u64,
+        transfer_function: &TransferFunction,
+    ) -> Result<()> {
+        gl.active_texture(WebGl::TEXTURE1);
+        gl.bind_texture(WebGl::TEXTURE_2D, Some(&self.colormap));
+        gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+            WebGl::TEXTURE_2D,
+            0,
+            0,
+            0,
+            TRANSFER_FUNCTION_SIZE as i32,
+            1,
+            WebGl::RGBA,
+            WebGl::UNSIGNED_BYTE,
+            Some(&transfer_function.lookup_table()),
+        )
+        .map_err(|_| Error::Message("Js".into()))
+        .context("Failed to upload transfer function")?;
+        self.transfer_occupancy = self.macrocells.occupancy(transfer_function);
+        self.transfer_function_generation = generation;
+        self.window = transfer_function.window;
+        self.color_scale = transfer_function.color_scale();
+        self.upload_occupancy(gl)
+    }
+
+    /// Uploads a changed label map or label table, and the occupancy to match
+    fn update_labels(&mut self, gl: &WebGl, generation: u64, overlay: &LabelOverlay) -> Result<()> {
+        let replaced = LabelTextures::sync(
+            &mut self.labels,
+            gl,
+            overlay,
+            LABELS_TEXTURE_UNIT,
+            LABEL_COLORS_TEXTURE_UNIT,
+        )?;
+        if replaced {
+            self.label_cells = self
+                .labels
+                .as_ref()
+                .map(|labels| labels.map().cells(MACROCELL_SIZE));
+        }
+        self.label_occupancy = self
+            .label_cells
+            .as_ref()
+            .map(|cells| cells.occupancy(&overlay.table));
+        self.label_blend = overlay.blend;
+        self.labels_generation = generation;
+        self.upload_occupancy(gl)
+    }
+
+    fn update_fusion(&mut self, gl: &WebGl, generation: u64, fusion: Fusion) -> Result<()> {
+        FusionTextures::sync(&mut self.fusion_textures, gl, &fusion)?;
+        self.fusion = fusion;
+        self.fusion_generation = generation;
+        Ok(())
+    }
+
+    /// The macrocells only cover the first volume, so space can't be skipped
+    /// where the second volume may add opacity
+    fn fusion_adds_opacity(&self) -> bool {
+        self.fusion_textures.is_some() && self.fusion.mode != FusionMode::Mask
+    }
+
+    /// Cells are skipped only if neither the transfer function nor a shown
+    /// label gives them any opacity
+    fn upload_occupancy(&mut self, gl: &WebGl) -> Result<()> {
+        let occupancy: Vec<u8> = match &self.label_occupancy {
+            Some(label_occupancy) if self.label_blend > 0.0 => self
+                .transfer_occupancy
+                .iter()
+                .zip(label_occupancy)
+                .map(|(transfer, label)| *transfer.max(label))
+                .collect(),
+            _ => self.transfer_occupancy.clone(),
+        };
+        let [x, y, z] = self.macrocells.dims().map(|d| d as i32);
+        gl.active_texture(WebGl::TEXTURE3);
+        gl.bind_texture(WebGl::TEXTURE_3D, Some(&self.occupancy));
+        gl.pixel_storei(WebGl::UNPACK_ALIGNMENT, 1);
+        gl.tex_sub_image_3d_with_opt_u8_array(
+            WebGl::TEXTURE_3D,
+            0,
+            0,
+            0,
+            0,
+            x,
+            y,
+            z,
+            WebGl::RED,
+            WebGl::UNSIGNED_BYTE,
+            Some(&occupancy),
+        )
+        .map_err(|_| Error::Message("Js".into()))
+        .context("Failed to upload macrocell occupancy")?;
+        Ok(())
+    }
 }
 
 impl ProgramReady {
-    pub(crate) fn render(&mut self, camera_pos: &[f32; 3], proj_view: &[f32; 16]) {
+    /// Draws a frame, averaging it into the previous ones unless `plan` asks
+    /// for a restart. Without float framebuffers frames are drawn straight to
+    /// the canvas. Meshes are composited behind the volume by the ray marcher,
+    /// so they only show where they're inside the volume's bounding box.
+    pub(crate) fn render(
+        &mut self,
+        draw_data: &DrawData,
+        proj_view: &[f32; 16],
+        render_settings: &RenderSettings,
+        plan: FramePlan,
+        canvas_dims: &CanvasDims,
+        target: &RenderTarget,
+    ) {
         let ProgramReady(
             gl,
             ProgramCompiledWithTextures {
-                program: _,
+                program,
                 locations,
-                ..
+                textures,
             },
+            accumulator,
+            overlays,
+            mesh_pass,
+            _,
         ) = self;
-        gl.clear_color(1.0, 1.0, 1.0, 1.0);
-        gl.clear(WebGl::COLOR_BUFFER_BIT);
+        let eye_pos = draw_data.eye_pos;
+        let has_mesh = mesh_pass
+            .as_ref()
+            .map(|mesh_pass| mesh_pass.draw(gl, proj_view, eye_pos))
+            .unwrap_or(false);
+        let inv_proj_view = draw_data
+            .proj_view
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+        let (step_scale, restart) = match plan {
+            FramePlan::Restart { step_scale } => (step_scale, true),
+            FramePlan::Accumulate => (1.0, false),
+        };
+        let frame_seed = match accumulator {
+            Some(accumulator) => accumulator.begin_frame(gl, restart),
+            None => {
+                gl.bind_framebuffer(WebGl::FRAMEBUFFER, target.framebuffer);
+                let [r, g, b, a] = target.background;
+                gl.clear_color(r, g, b, a);
+                gl.clear(WebGl::COLOR_BUFFER_BIT);
+                0
+            }
+        };
+        gl.use_program(Some(program));
         locations.assign_proj_view(gl, proj_view);
-        locations.assign_camera(gl, camera_pos);
+        locations.assign_camera(gl, &[eye_pos.x, eye_pos.y, eye_pos.z]);
+        locations.assign_projection(gl, draw_data.orthographic, draw_data.view_dir.as_ref());
+        locations.assign_render_mode(gl, render_settings.mode);
+        locations.assign_iso_value(gl, render_settings.iso_value);
+        locations.assign_light(gl, &render_settings.light);
+        locations.assign_shading(
+            gl,
+            render_settings.dvr_shading,
+            render_settings.gradient_source,
+        );
+        locations.assign_dt_scale(gl, step_scale / render_settings.sampling_rate);
+        locations.assign_ert_threshold(gl, render_settings.ert_threshold);
+        locations.assign_skip_empty_space(
+            gl,
+            render_settings.skip_empty_space && !textures.fusion_adds_opacity(),
+        );
+        locations.assign_clipping(gl, render_settings);
+        locations.assign_labels(gl, textures.labels.is_some(), textures.label_blend);
+        locations.assign_fusion(
+            gl,
+            textures.fusion_textures.is_some(),
+            &textures.fusion,
+            &textures.volume_model,
+        );
+        locations.assign_jitter(gl, frame_seed, canvas_dims.width as i32);
+        locations.assign_output_srgb(gl, accumulator.is_none());
+        // World and volume coordinates coincide while the volume scale is one
+        locations.assign_mesh(gl, has_mesh, inv_proj_view.as_ref());
         gl.draw_arrays(WebGl::TRIANGLE_STRIP, 0, 14);
+        if let Some(accumulator) = accumulator {
+            accumulator.present(gl, target.framebuffer, target.background);
+        }
+        overlays.draw(gl, proj_view, draw_data);
         gl.finish();
     }
 
-    pub fn render_from_state(&mut self, app_state: &SharedMut<AppState>) -> Result<()> {
-        let CanvasDims { width, height } = get_canvas_dims(app_state)?;
-        let persp_proj = cgmath::perspective(cgmath::Deg(65.0), width / height, 1.0, 200.0);
+    /// Keeps the accumulation buffer the size of the canvas, falling back to
+    /// drawing straight to the canvas if it can't be created
+    fn resize_accumulator(&mut self, width: i32, height: i32) {
+        let ProgramReady(gl, _, accumulator, _, _, failed_size) = self;
+        if let Some(current) = accumulator {
+            if current.has_size(width, height) {
+                return;
+            }
+        }
+        if *failed_size == Some((width, height)) {
+            return;
+        }
+        if let Some(current) = accumulator.take() {
+            current.delete(gl);
+        }
+        match Accumulator::new(gl, width, height) {
+            Ok(new_accumulator) => {
+                *accumulator = Some(new_accumulator);
+                *failed_size = None;
+            }
+            Err(err) => {
+                *failed_size = Some((width, height));
+                web_sys::console::log_1(&format!("Progressive refinement disabled: {err:?}").into())
+            }
+        }
+    }
 
-        if should_i_draw(app_state) {
-            let DrawData { proj_view, eye_pos } = get_arcball_data(app_state);
-            let proj_view = persp_proj * proj_view;
-            let camera_pos: [f32; 3] = [eye_pos.x, eye_pos.y, eye_pos.z];
-            let mut i = 0;
-            let mut arr: [f32; 16] = [0.0; 16];
-            [proj_view.x, proj_view.y, proj_view.z, proj_view.w]
-                .iter()
-                .flat_map(|x| vec![x.x, x.y, x.z, x.w])
-                .for_each(|x| {
-                    arr[i] = x;
-                    i += 1
-                });
+    /// Uploads whatever changed in `app_state` since the last frame
+    fn sync_with_state(
+        &mut self,
+        app_state: &SharedMut<AppState>,
+        draw_data: &DrawData,
+        width: i32,
+        height: i32,
+    ) -> Result<()> {
+        let textures = &self.1.textures;
+        let transfer_function_update =
+            get_transfer_function_update(app_state, textures.transfer_function_generation)?;
+        let label_update = get_label_update(app_state, textures.labels_generation)?;
+        let fusion_update = get_fusion_update(app_state, textures.fusion_generation)?;
+        let mesh_update = get_mesh_update(app_state, self.meshes_generation())?;
+        if let Some((generation, transfer_function)) = transfer_function_update {
+            self.1
+                .textures
+                .update_transfer_function(&self.0, generation, &transfer_function)?;
+        }
+        if let Some((generation, overlay)) = label_update {
+            self.upload_labels(generation, &overlay)?;
+        }
+        if let Some((generation, fusion)) = fusion_update {
+            self.upload_fusion(generation, fusion)?;
+        }
+        if let Some((generation, meshes)) = mesh_update {
+            self.upload_meshes(generation, &meshes)?;
+        }
+        self.prepare_frame(draw_data, width, height)
+    }
 
-            self.render(&camera_pos, &arr);
-            set_arcball_changed_to_false_after_draw(app_state);
+    fn upload_labels(&mut self, generation: u64, overlay: &LabelOverlay) -> Result<()> {
+        self.1.textures.update_labels(&self.0, generation, overlay)
+    }
+
+    fn upload_fusion(&mut self, generation: u64, fusion: Fusion) -> Result<()> {
+        self.1.textures.update_fusion(&self.0, generation, fusion)
+    }
+
+    /// Replaces the meshes drawn, setting up the mesh pass for the first ones
+    fn upload_meshes(&mut self, generation: u64, meshes: &[SceneMesh]) -> Result<()> {
+        let ProgramReady(gl, _, _, _, mesh_pass, _) = self;
+        if mesh_pass.is_none() {
+            *mesh_pass = Some(MeshPass::new(gl)?);
+        }
+        if let Some(mesh_pass) = mesh_pass {
+            mesh_pass.update_meshes(gl, generation, meshes)?;
+        }
+        Ok(())
+    }
+
+    fn meshes_generation(&self) -> u64 {
+        self.4.as_ref().map(MeshPass::generation).unwrap_or(0)
+    }
+
+    /// Fits the overlays and the mesh buffers to a `width` by `height` frame
+    /// drawn with `draw_data`
+    fn prepare_frame(&mut self, draw_data: &DrawData, width: i32, height: i32) -> Result<()> {
+        let ProgramReady(gl, _, _, overlays, mesh_pass, _) = self;
+        overlays.prepare(gl, draw_data)?;
+        if let Some(mesh_pass) = mesh_pass.as_mut().filter(|pass| pass.has_meshes()) {
+            mesh_pass.resize(gl, width, height)?;
         }
         Ok(())
     }
+
+    /// Renders the next frame of an animation export, if one is in progress,
+    /// at the export's size and full sampling quality. Returns whether it
+    /// did, in which case the canvas holds the exported frame.
+    pub fn render_export_frame(&mut self, app_state: &SharedMut<AppState>) -> Result<bool> {
+        let Some(settings) = next_export_frame(app_state)? else {
+            return Ok(false);
+        };
+        let (width, height) = (settings.width as i32, settings.height as i32);
+        let canvas = self.canvas()?;
+        let (canvas_width, canvas_height) = (canvas.width(), canvas.height());
+        canvas.set_width(settings.width);
+        canvas.set_height(settings.height);
+        self.0.viewport(0, 0, width, height);
+        self.resize_accumulator(width, height);
+
+        // Editing aids stay out of the exported frames
+        let draw_data = DrawData {
+            crop_handles: None,
+            slice_planes: None,
+            ..get_arcball_data(app_state)
+        };
+        let render_settings = get_render_settings(app_state)?;
+        self.sync_with_state(app_state, &draw_data, width, height)?;
+        let proj_view: [f32; 16] = *draw_data.proj_view.as_ref();
+        let canvas_dims = CanvasDims {
+            width: width as f32,
+            height: height as f32,
+        };
+        for sample in 0..settings.samples.max(1) {
+            let plan = match sample {
+                0 => FramePlan::Restart { step_scale: 1.0 },
+                _ => FramePlan::Accumulate,
+            };
+            let target = &RenderTarget::CANVAS;
+            self.render(
+                &draw_data,
+                &proj_view,
+                &render_settings,
+                plan,
+                &canvas_dims,
+                target,
+            );
+        }
+        let image = self
+            .read_pixels(width, height)
+            .context("Failed to read back export frame")?;
+
+        canvas.set_width(canvas_width);
+        canvas.set_height(canvas_height);
+        self.0
+            .viewport(0, 0, canvas_width as i32, canvas_height as i32);
+        let png = image.to_png()?;
+        if let Some(archive) = finish_export_frame(app_state, &png)? {
+            download_bytes("frames.zip", "application/zip", &archive)?;
+        }
+        Ok(true)
+    }
+
+    fn canvas(&self) -> Result<HtmlCanvasElement> {
+        self.0
+            .canvas()
+            .ok_or(Error::Missing)
+            .context("WebGL context has no canvas")?
+            .dyn_into::<HtmlCanvasElement>()
+            .map_err(|_| Error::Message("Js".into()))
+            .context("WebGL context is not drawing to a canvas element")
+    }
+
+    /// Sizes the drawing buffer to `width` by `height` pixels and draws to
+    /// all of it
+    fn fit_canvas(&self, width: i32, height: i32) -> Result<()> {
+        let canvas = self.canvas()?;
+        if canvas.width() != width as u32 || canvas.height() != height as u32 {
+            canvas.set_width(width as u32);
+            canvas.set_height(height as u32);
+        }
+        self.0.viewport(0, 0, width, height);
+        Ok(())
+    }
+
+    /// Downloads the canvas as a PNG once a screenshot was asked for and the
+    /// view's accumulation converged over the frames since, which spreads
+    /// the full quality samples across animation frames. Returns whether it
+    /// did.
+    pub fn render_screenshot(&mut self, app_state: &SharedMut<AppState>) -> Result<bool> {
+        let canvas_dims = get_canvas_dims(app_state)?;
+        let (width, height) = (canvas_dims.width as i32, canvas_dims.height as i32);
+        let converged = match &self.2 {
+            Some(accumulator) => accumulator.has_size(width, height) && accumulator.is_converged(),
+            // Frames drawn straight to the canvas can't be refined
+            None => true,
+        };
+        if !take_screenshot_request(app_state, converged)? {
+            return Ok(false);
+        }
+        let draw_data = get_arcball_data(app_state);
+        let proj_view: [f32; 16] = *draw_data.proj_view.as_ref();
+        // The canvas isn't preserved after it's shown, so the accumulated
+        // image is drawn to it again without adding a sample
+        match self {
+            ProgramReady(gl, _, Some(accumulator), overlays, ..) => {
+                accumulator.present(gl, None, RenderTarget::CANVAS.background);
+                overlays.draw(gl, &proj_view, &draw_data);
+            }
+            _ => {
+                let render_settings = get_render_settings(app_state)?;
+                self.render(
+                    &draw_data,
+                    &proj_view,
+                    &render_settings,
+                    FramePlan::Restart { step_scale: 1.0 },
+                    &canvas_dims,
+                    &RenderTarget::CANVAS,
+                );
+            }
+        }
+        let png = self
+            .read_pixels(width, height)
+            .context("Failed to read back screenshot")?
+            .to_png()?;
+        download_bytes("screenshot.png", "image/png", &png)?;
+        Ok(true)
+    }
+
+    /// Renders the capture asked for, if any, into an offscreen framebuffer
+    /// one tile at a time, and downloads the stitched image as a PNG with
+    /// the dataset, camera and transfer function in its text chunks. Returns
+    /// whether it did.
+    pub fn render_capture(&mut self, app_state: &SharedMut<AppState>) -> Result<bool> {
+        let Some(settings) = take_capture_request(app_state)? else {
+            return Ok(false);
+        };
+        let (width, height) = (settings.width.max(1), settings.height.max(1));
+        let gl = self.0.clone();
+        let tiles = capture::tiles(width, height, CaptureTarget::max_size(&gl)?);
+        let tile_size = |size: fn(&Tile) -> u32| tiles.iter().map(size).max().unwrap_or(1) as i32;
+        let target = CaptureTarget::new(
+            &gl,
+            tile_size(|tile| tile.width),
+            tile_size(|tile| tile.height),
+        )?;
+        let result = self.render_tiles(app_state, &settings, &tiles, &target);
+        target.delete(&gl);
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, None);
+        let (mut pixels, metadata) = result?;
+        if settings.transparent {
+            capture::unpremultiply(&mut pixels);
+        }
+        let png = RgbaImage::from_bottom_up(width as usize, height as usize, pixels)
+            .to_png_with_text(&metadata)?;
+        download_bytes("capture.png", "image/png", &png)?;
+        Ok(true)
+    }
+
+    /// Renders each of `tiles` of a capture into `target`, returning the
+    /// pixels of the whole capture bottom row first and its metadata
+    fn render_tiles(
+        &mut self,
+        app_state: &SharedMut<AppState>,
+        settings: &capture::CaptureSettings,
+        tiles: &[Tile],
+        target: &CaptureTarget,
+    ) -> Result<(Vec<u8>, PngText)> {
+        let (width, height) = (settings.width.max(1), settings.height.max(1));
+        let (draw_data, metadata) = get_capture_data(app_state, width, height)?;
+        let render_settings = get_render_settings(app_state)?;
+        let render_target = RenderTarget {
+            framebuffer: Some(target.framebuffer()),
+            background: match settings.transparent {
+                true => [0.0; 4],
+                false => RenderTarget::CANVAS.background,
+            },
+        };
+        let mut image = vec![0; (width * height * 4) as usize];
+        for tile in tiles {
+            let (tile_width, tile_height) = (tile.width as i32, tile.height as i32);
+            let tile_data = DrawData {
+                proj_view: tile.projection(width, height) * draw_data.proj_view,
+                ..draw_data.clone()
+            };
+            // The accumulation and mesh buffers are read across their whole
+            // size, so they follow the tile
+            self.resize_accumulator(tile_width, tile_height);
+            self.sync_with_state(app_state, &tile_data, tile_width, tile_height)?;
+            self.0.viewport(0, 0, tile_width, tile_height);
+            let proj_view: [f32; 16] = *tile_data.proj_view.as_ref();
+            let tile_dims = CanvasDims {
+                width: tile.width as f32,
+                height: tile.height as f32,
+            };
+            for sample in 0..settings.samples.max(1) {
+                let plan = match sample {
+                    0 => FramePlan::Restart { step_scale: 1.0 },
+                    _ => FramePlan::Accumulate,
+                };
+                self.render(
+                    &tile_data,
+                    &proj_view,
+                    &render_settings,
+                    plan,
+                    &tile_dims,
+                    &render_target,
+                );
+            }
+            self.0
+                .bind_framebuffer(WebGl::FRAMEBUFFER, Some(target.framebuffer()));
+            let pixels = self
+                .read_rows(tile_width, tile_height)
+                .context("Failed to read back capture tile")?;
+            capture::paste_tile(&mut image, width, tile, &pixels);
+        }
+        Ok((image, metadata))
+    }
+
+    /// What was last drawn to the bound framebuffer
+    fn read_pixels(&self, width: i32, height: i32) -> Result<RgbaImage> {
+        let pixels = self.read_rows(width, height)?;
+        Ok(RgbaImage::from_bottom_up(
+            width as usize,
+            height as usize,
+            pixels,
+        ))
+    }
+
+    /// RGBA pixels of the bound framebuffer, bottom row first
+    fn read_rows(&self, width: i32, height: i32) -> Result<Vec<u8>> {
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        self.0
+            .read_pixels_with_opt_u8_array(
+                0,
+                0,
+                width,
+                height,
+                WebGl::RGBA,
+                WebGl::UNSIGNED_BYTE,
+                Some(&mut pixels),
+            )
+            .map_err(|_| Error::Message("Js".into()))
+            .context("Failed to read pixels")?;
+        Ok(pixels)
+    }
+}
+
+/// The WebGL [`RenderBackend`]. Uploading a volume runs the typestate
+/// pipeline from `GlState<EmptyState>` to `ProgramReady`, which draws the
+/// frames from then on.
+pub struct WebGlBackend {
+    gl: WebGl,
+    /// Transfer function the next volume's textures are built with
+    transfer_function: (u64, TransferFunction),
+    program: Option<ProgramReady>,
+    /// Drawn with the program's textures, so they're rebuilt along with it
+    slice_views: Option<SliceViews>,
+    uniforms: Option<FrameUniforms>,
+    /// Set while the context is lost, when there's nothing to draw with
+    context_lost: bool,
+}
+
+impl WebGlBackend {
+    pub fn new(gl: WebGl) -> Self {
+        Self {
+            gl,
+            transfer_function: (0, TransferFunction::default()),
+            program: None,
+            slice_views: None,
+            uniforms: None,
+            context_lost: false,
+        }
+    }
+
+    /// The program drawing the volume, once one is uploaded
+    pub fn program_mut(&mut self) -> Option<&mut ProgramReady> {
+        self.program.as_mut()
+    }
+
+    /// Drops the program, whose textures, buffers and shaders went with the
+    /// context
+    pub fn lose_context(&mut self) {
+        self.program = None;
+        self.slice_views = None;
+        self.context_lost = true;
+    }
+
+    pub fn is_context_lost(&self) -> bool {
+        self.context_lost
+    }
+
+    /// Redraws the slice views if the slices or what they show changed
+    pub fn render_slices(&mut self, app_state: &SharedMut<AppState>) -> Result<()> {
+        match (&self.program, &mut self.slice_views) {
+            (Some(ProgramReady(gl, program, ..)), Some(views)) => {
+                views.render_from_state(gl, app_state, &program.textures)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the whole typestate pipeline again on the restored context, with
+    /// `volume` and the last transfer function uploaded. Labels, fused volumes
+    /// and meshes follow on the next frame as their generations start over.
+    pub fn restore_context(&mut self, volume: &Volume) -> Result<()> {
+        self.context_lost = false;
+        self.upload_volume(volume)
+            .context("Failed to rebuild the program on the restored context")
+    }
+}
+
+impl RenderBackend for WebGlBackend {
+    fn upload_volume(&mut self, volume: &Volume) -> Result<()> {
+        let vertices = js_sys::Float32Array::new_with_length(CUBE_STRIP.len() as u32);
+        vertices.copy_from(&CUBE_STRIP.map(|x| x as f32 / 255.0));
+        let mut gl_state = new_empty_state(self.gl.clone())
+            .init(&vertices)
+            .map_err(Error::Message)
+            .context("Failed to upload the volume box")?
+            .assemble_volumetric_3d_program(&shaders::VERT_SHADER, &shaders::FRAG_SHADER)?;
+        gl_state.init();
+        let max_gradient_magnitude = volume.max_gradient_magnitude();
+        let (generation, transfer_function) = &self.transfer_function;
+        let program = gl_state
+            .build_textures(
+                *generation,
+                transfer_function,
+                volume,
+                max_gradient_magnitude,
+            )?
+            .set_volume_metadata(volume, max_gradient_magnitude);
+        self.program = Some(program);
+        self.slice_views = Some(SliceViews::new(&self.gl)?);
+        Ok(())
+    }
+
+    fn upload_transfer_function(
+        &mut self,
+        generation: u64,
+        transfer_function: &TransferFunction,
+    ) -> Result<()> {
+        self.transfer_function = (generation, transfer_function.clone());
+        if let Some(ProgramReady(gl, program, ..)) = &mut self.program {
+            program
+                .textures
+                .update_transfer_function(gl, generation, transfer_function)?;
+        }
+        Ok(())
+    }
+
+    fn transfer_function_generation(&self) -> u64 {
+        match &self.program {
+            Some(program) => program.1.textures.transfer_function_generation,
+            None => self.transfer_function.0,
+        }
+    }
+
+    fn upload_labels(&mut self, generation: u64, overlay: &LabelOverlay) -> Result<()> {
+        match &mut self.program {
+            Some(program) => program.upload_labels(generation, overlay),
+            None => Ok(()),
+        }
+    }
+
+    fn labels_generation(&self) -> u64 {
+        match &self.program {
+            Some(program) => program.1.textures.labels_generation,
+            None => 0,
+        }
+    }
+
+    fn upload_fusion(&mut self, generation: u64, fusion: Fusion) -> Result<()> {
+        match &mut self.program {
+            Some(program) => program.upload_fusion(generation, fusion),
+            None => Ok(()),
+        }
+    }
+
+    fn fusion_generation(&self) -> u64 {
+        match &self.program {
+            Some(program) => program.1.textures.fusion_generation,
+            None => 0,
+        }
+    }
+
+    fn upload_meshes(&mut self, generation: u64, meshes: &[SceneMesh]) -> Result<()> {
+        match &mut self.program {
+            Some(program) => program.upload_meshes(generation, meshes),
+            None => Ok(()),
+        }
+    }
+
+    fn meshes_generation(&self) -> u64 {
+        match &self.program {
+            Some(program) => program.meshes_generation(),
+            None => 0,
+        }
+    }
+
+    fn resize(&mut self, width: u32, height: u32) -> Result<bool> {
+        let Some(program) = &mut self.program else {
+            return Ok(false);
+        };
+        let (width, height) = (width as i32, height as i32);
+        let size_changed = program
+            .2
+            .as_ref()
+            .map(|accumulator| !accumulator.has_size(width, height))
+            .unwrap_or(true);
+        if size_changed {
+            program.resize_accumulator(width, height);
+        }
+        Ok(size_changed && program.2.is_some())
+    }
+
+    /// Frames drawn straight to the canvas can't be refined
+    fn is_converged(&self) -> bool {
+        match &self.program {
+            Some(ProgramReady(_, _, Some(accumulator), ..)) => accumulator.is_converged(),
+            _ => true,
+        }
+    }
+
+    fn set_uniforms(&mut self, uniforms: &FrameUniforms) -> Result<()> {
+        self.uniforms = Some(uniforms.clone());
+        Ok(())
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        let program = self
+            .program
+            .as_mut()
+            .ok_or(Error::Missing)
+            .context("No volume uploaded to draw")?;
+        let uniforms = self
+            .uniforms
+            .as_ref()
+            .ok_or(Error::Missing)
+            .context("No uniforms set to draw with")?;
+        let (width, height) = (uniforms.width as i32, uniforms.height as i32);
+        program.prepare_frame(&uniforms.draw_data, width, height)?;
+        program.fit_canvas(width, height)?;
+        let canvas_dims = CanvasDims {
+            width: uniforms.width as f32,
+            height: uniforms.height as f32,
+        };
+        program.render(
+            &uniforms.draw_data,
+            uniforms.draw_data.proj_view.as_ref(),
+            &uniforms.render_settings,
+            uniforms.plan,
+            &canvas_dims,
+            &RenderTarget::CANVAS,
+        );
+        Ok(())
+    }
 }
 
 impl GlState<ProgramCompiledWithTextures<Volumetric3DLocations, Volumetric3DTextures>> {
-    pub(crate) fn set_volume_metadata(self) -> ProgramReady {
+    pub(crate) fn set_volume_metadata(
+        self,
+        volume: &Volume,
+        max_gradient_magnitude: f32,
+    ) -> ProgramReady {
         let GlState(gl, mut program_compiled_with_textures) = self;
-        let vol_dims: [i32; 3] = [VOLUME_X, VOLUME_Y, VOLUME_Z];
+        let vol_dims: [i32; 3] = volume.dims().map(|d| d as i32);
         let vol_scale: [f32; 3] = [1.0, 1.0, 1.0];
-        program_compiled_with_textures.set_volume_metadata(&gl, &vol_dims, &vol_scale);
-        ProgramReady(gl, program_compiled_with_textures)
+        // On the fly gradients are taken from the normalised volume texture
+        let gradient_scale = match max_gradient_magnitude > 0.0 {
+            true => 255.0 / max_gradient_magnitude,
+            false => 0.0,
+        };
+        program_compiled_with_textures.set_volume_metadata(
+            &gl,
+            &vol_dims,
+            &vol_scale,
+            gradient_scale,
+        );
+        ProgramReady(
+            gl,
+            program_compiled_with_textures,
+            None,
+            Overlays::default(),
+            None,
+            None,
+        )
     }
 }
 
@@ -227,10 +1239,13 @@ impl GlState<ProgramCompiled<Volumetric3DLocations>> {
 
     pub(crate) fn build_textures(
         self,
-        colormap_data: &[u8],
-        volume_density_data: &[u8],
+        transfer_function_generation: u64,
+        transfer_function: &TransferFunction,
+        volume: &Volume,
+        max_gradient_magnitude: f32,
     ) -> Result<GlState<ProgramCompiledWithTextures<Volumetric3DLocations, Volumetric3DTextures>>>
     {
+        let colormap_data = transfer_function.lookup_table();
         let GlState(gl, program_compiled) = self;
         let ProgramCompiled { program, locations } = program_compiled;
         let colormap = gl
@@ -264,70 +1279,63 @@ impl GlState<ProgramCompiled<Volumetric3DLocations>> {
             1,
             WebGl::RGBA, // See https://www.khronos.org/registry/webgl/specs/latest/2.0/#3.7.6 for info on formats
             WebGl::UNSIGNED_BYTE,
-            Some(colormap_data),
+            Some(&colormap_data),
         )
         .map_err(|_| Error::Message("Js".into()))
         .context("Failed to create tex_sub_image_2s")?;
-        let volumetric = gl
-            .create_texture()
-            .ok_or(Error::Missing)
-            .context("Couldn't create volume texture")?;
-        gl.active_texture(WebGl::TEXTURE0);
-        gl.bind_texture(WebGl::TEXTURE_3D, Some(&volumetric));
-        gl.tex_storage_3d(
-            WebGl::TEXTURE_3D,
-            1,
-            WebGl::R8,
-            VOLUME_X,
-            VOLUME_Y,
-            VOLUME_Z,
-        );
-        gl.tex_parameteri(
-            WebGl::TEXTURE_3D,
-            WebGl::TEXTURE_MIN_FILTER,
-            WebGl::LINEAR as i32,
-        );
-        gl.tex_parameteri(
-            WebGl::TEXTURE_3D,
-            WebGl::TEXTURE_WRAP_R,
-            WebGl::CLAMP_TO_EDGE as i32,
-        );
-        gl.tex_parameteri(
-            WebGl::TEXTURE_3D,
-            WebGl::TEXTURE_WRAP_S,
-            WebGl::CLAMP_TO_EDGE as i32,
-        );
-        gl.tex_parameteri(
-            WebGl::TEXTURE_3D,
-            WebGl::TEXTURE_WRAP_T,
-            WebGl::CLAMP_TO_EDGE as i32,
-        );
-
-        web_sys::console::log_1(&format!("starting 3d {}", volume_density_data.len()).into());
-        gl.tex_sub_image_3d_with_opt_u8_array(
-            WebGl::TEXTURE_3D,
-            0,
-            0,
-            0,
-            0,
-            VOLUME_X,
-            VOLUME_Y,
-            VOLUME_Z,
-            WebGl::RED,
-            WebGl::UNSIGNED_BYTE,
-            Some(
-                &volume_density_data
-                    .iter()
-                    .map(|x| x / 5)
-                    .collect::<Vec<_>>()[..],
-            ),
-        )
-        .map_err(|_| Error::Message("".into()))
-        .context("failed tex sub image 3d")?;
+        web_sys::console::log_1(&format!("starting 3d {}", volume.data().len()).into());
+        let volumetric = gl.create_texture_3d(
+            WebGl::TEXTURE0,
+            &TextureFormat {
+                internal_format: WebGl::R8,
+                format: WebGl::RED,
+                filter: WebGl::LINEAR,
+            },
+            volume.dims(),
+            volume.data(),
+        )?;
+        let gradients = gl.create_texture_3d(
+            WebGl::TEXTURE2,
+            &TextureFormat {
+                internal_format: WebGl::RGB8,
+                format: WebGl::RGB,
+                filter: WebGl::LINEAR,
+            },
+            volume.dims(),
+            &volume.gradient_texture(max_gradient_magnitude),
+        )?;
+        let macrocells = MacrocellGrid::new(volume, MACROCELL_SIZE);
+        let transfer_occupancy = macrocells.occupancy(transfer_function);
+        let occupancy = gl.create_texture_3d(
+            WebGl::TEXTURE3,
+            &TextureFormat {
+                internal_format: WebGl::R8,
+                format: WebGl::RED,
+                filter: WebGl::NEAREST,
+            },
+            macrocells.dims(),
+            &transfer_occupancy,
+        )?;
         web_sys::console::log_1(&"done with 3d".into());
         let textures = Volumetric3DTextures {
             colormap,
-            volumetric,
+            _volumetric: volumetric,
+            _gradients: gradients,
+            occupancy,
+            macrocells,
+            transfer_function_generation,
+            window: transfer_function.window,
+            color_scale: transfer_function.color_scale(),
+            transfer_occupancy,
+            labels: None,
+            label_cells: None,
+            label_occupancy: None,
+            label_blend: 0.0,
+            labels_generation: 0,
+            fusion_textures: None,
+            fusion: Fusion::default(),
+            fusion_generation: 0,
+            volume_model: volume.model(),
         };
         Ok(GlState(
             gl,
@@ -341,63 +1349,55 @@ impl GlState<ProgramCompiled<Volumetric3DLocations>> {
 }
 
 impl GlState<VertexInitialised> {
-    fn compile_shader<'b, T: shaders::Shader<'b>>(&self, shader: &T) -> Result<WebGlShader> {
-        let GlState(gl, _) = &self;
-        let to_compile_shader = gl
-            .create_shader(shader.code())
-            .ok_or(Error::Missing)
-            .context("Error creating shader")?;
-        gl.shader_source(&to_compile_shader, shader.source());
-        gl.compile_shader(&to_compile_shader);
-        let compiled_shader = to_compile_shader;
-        let status = gl
-            .get_shader_parameter(&compiled_shader, WebGl::COMPILE_STATUS)
-            .as_bool()
-            .ok_or(Error::Missing)
-            .context("Compile failed. Unable to get params")?;
-        match status {
-            false => {
-                let error_message = gl
-                    .get_shader_info_log(&compiled_shader)
-                    .unwrap_or_else(|| String::from("No compiler log"));
-                Err(Error::Message(error_message).into())
-            }
-            true => Ok(compiled_shader),
-        }
-    }
-
     pub(crate) fn assemble_volumetric_3d_program(
         self,
         vertex_shader: &shaders::VertexShader,
         fragment_shader: &shaders::FragmentShader,
     ) -> Result<GlState<ProgramCompiled<Volumetric3DLocations>>> {
-        let compiled_vertex_shader = self.compile_shader(vertex_shader)?;
-        let compiled_fragment_shader = self.compile_shader(fragment_shader)?;
         let GlState(gl, _) = self;
-
-        let program = gl
-            .create_program()
-            .ok_or(Error::Missing)
-            .context("Unable to create program")?;
-        gl.attach_shader(&program, &compiled_vertex_shader);
-        gl.attach_shader(&program, &compiled_fragment_shader);
-        gl.link_program(&program);
-
-        let program_status = gl
-            .get_program_parameter(&program, WebGl::LINK_STATUS)
-            .as_bool()
-            .ok_or(Error::Missing)
-            .context("Failed to get program status")?;
-        if !program_status {
-            return Err(Error::Message("Failed to attach shaders to program".to_string()).into());
-        };
+        let program = gl.link_program_from(vertex_shader, fragment_shader)?;
         let proj_view = gl.get_unif_loc(&program, "proj_view")?;
         let camera_pos = gl.get_unif_loc(&program, "eye_pos")?;
+        let orthographic = gl.get_unif_loc(&program, "orthographic")?;
+        let view_dir = gl.get_unif_loc(&program, "view_dir")?;
         let colormap = gl.get_unif_loc(&program, "colormap")?;
         let dt_scale = gl.get_unif_loc(&program, "dt_scale")?;
+        let ert_threshold = gl.get_unif_loc(&program, "ert_threshold")?;
+        let frame_seed = gl.get_unif_loc(&program, "frame_seed")?;
+        let canvas_width = gl.get_unif_loc(&program, "canvas_width")?;
+        let output_srgb = gl.get_unif_loc(&program, "output_srgb")?;
         let volume = gl.get_unif_loc(&program, "volume")?;
         let vol_dims = gl.get_unif_loc(&program, "volume_dims")?;
         let vol_scale = gl.get_unif_loc(&program, "volume_scale")?;
+        let render_mode = gl.get_unif_loc(&program, "render_mode")?;
+        let iso_value = gl.get_unif_loc(&program, "iso_value")?;
+        let headlight = gl.get_unif_loc(&program, "headlight")?;
+        let light_dir = gl.get_unif_loc(&program, "light_dir")?;
+        let dvr_shading = gl.get_unif_loc(&program, "dvr_shading")?;
+        let precomputed_gradients = gl.get_unif_loc(&program, "precomputed_gradients")?;
+        let gradients = gl.get_unif_loc(&program, "gradients")?;
+        let gradient_scale = gl.get_unif_loc(&program, "gradient_scale")?;
+        let occupancy = gl.get_unif_loc(&program, "occupancy")?;
+        let macrocell_size = gl.get_unif_loc(&program, "macrocell_size")?;
+        let skip_empty_space = gl.get_unif_loc(&program, "skip_empty_space")?;
+        let crop_min = gl.get_unif_loc(&program, "crop_min")?;
+        let crop_max = gl.get_unif_loc(&program, "crop_max")?;
+        let clip_planes = gl.get_unif_loc(&program, "clip_planes")?;
+        let num_clip_planes = gl.get_unif_loc(&program, "num_clip_planes")?;
+        let has_mesh = gl.get_unif_loc(&program, "has_mesh")?;
+        let mesh_color = gl.get_unif_loc(&program, "mesh_color")?;
+        let mesh_depth = gl.get_unif_loc(&program, "mesh_depth")?;
+        let inv_proj_view = gl.get_unif_loc(&program, "inv_proj_view")?;
+        let has_labels = gl.get_unif_loc(&program, "has_labels")?;
+        let labels = gl.get_unif_loc(&program, "labels")?;
+        let label_colors = gl.get_unif_loc(&program, "label_colors")?;
+        let label_blend = gl.get_unif_loc(&program, "label_blend")?;
+        let has_secondary = gl.get_unif_loc(&program, "has_secondary")?;
+        let secondary = gl.get_unif_loc(&program, "secondary")?;
+        let secondary_colormap = gl.get_unif_loc(&program, "secondary_colormap")?;
+        let secondary_inv_model = gl.get_unif_loc(&program, "secondary_inv_model")?;
+        let fusion_mode = gl.get_unif_loc(&program, "fusion_mode")?;
+        let fusion_weight = gl.get_unif_loc(&program, "fusion_weight")?;
 
         gl.use_program(Some(&program));
 
@@ -405,10 +1405,45 @@ impl GlState<VertexInitialised> {
             proj_view,
             colormap,
             camera_pos,
+            orthographic,
+            view_dir,
             volume,
             vol_dims,
             vol_scale,
             dt_scale,
+            ert_threshold,
+            frame_seed,
+            canvas_width,
+            output_srgb,
+            render_mode,
+            iso_value,
+            headlight,
+            light_dir,
+            dvr_shading,
+            precomputed_gradients,
+            gradients,
+            gradient_scale,
+            occupancy,
+            macrocell_size,
+            skip_empty_space,
+            crop_min,
+            crop_max,
+            clip_planes,
+            num_clip_planes,
+            has_mesh,
+            mesh_color,
+            mesh_depth,
+            inv_proj_view,
+            has_labels,
+            labels,
+            label_colors,
+            label_blend,
+            has_secondary,
+            secondary,
+            secondary_colormap,
+            secondary_inv_model,
+            fusion_mode,
+            fusion_weight,
         };
 
         let state = ProgramCompiled { program, locations };
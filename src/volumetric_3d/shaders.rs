@@ -6,17 +6,28 @@ layout(location=0) in vec3 pos;
 uniform mat4 proj_view;
 uniform vec3 eye_pos;
 uniform vec3 volume_scale;
+uniform bool orthographic;
+uniform vec3 view_dir;
 
 out vec3 vray_dir;
-flat out vec3 transformed_eye;
+// The eye for a perspective view. Orthographic rays are parallel, so each
+// starts on the plane through the eye facing view_dir.
+out vec3 ray_origin;
 
 void main(void) {
 	// TODO: For non-uniform size volumes we need to transform them differently as well
 	// to center them properly
 	vec3 volume_translation = vec3(0.5) - volume_scale * 0.5;
 	gl_Position = proj_view * vec4(pos * volume_scale + volume_translation, 1);
-	transformed_eye = (eye_pos - volume_translation) / volume_scale;
-	vray_dir = pos - transformed_eye;
+	vec3 transformed_eye = (eye_pos - volume_translation) / volume_scale;
+	if (orthographic) {
+		vec3 dir = normalize(view_dir / volume_scale);
+		vray_dir = dir;
+		ray_origin = pos - dot(pos - transformed_eye, dir) * dir;
+	} else {
+		vray_dir = pos - transformed_eye;
+		ray_origin = transformed_eye;
+	}
 }"#,
 );
 
@@ -25,18 +36,73 @@ pub const FRAG_SHADER: FragmentShader = FragmentShader(
 #line 24
 precision highp int;
 precision highp float;
+#define MAX_CLIP_PLANES 4
 uniform highp sampler3D volume;
 uniform highp sampler2D colormap;
 uniform ivec3 volume_dims;
 uniform float dt_scale;
+// Rays stop once their accumulated opacity reaches this
+uniform float ert_threshold;
+// Index of the frame in the current accumulation, used to vary the jitter
+uniform int frame_seed;
+uniform int canvas_width;
+// Convert to sRGB here when drawing straight to the canvas, otherwise the
+// accumulated linear colour is converted when it is presented
+uniform bool output_srgb;
+// 0: direct volume rendering, 1: first-hit isosurface
+uniform int render_mode;
+uniform float iso_value;
+uniform bool headlight;
+// Direction the light travels in, in volume space
+uniform vec3 light_dir;
+uniform bool dvr_shading;
+uniform bool precomputed_gradients;
+uniform highp sampler3D gradients;
+// Maps on the fly gradients to the same range as the gradient texture
+uniform float gradient_scale;
+// 255 for macrocells with any opacity under the transfer function, 0 otherwise
+uniform highp sampler3D occupancy;
+// Edge lengths of a macrocell in texture coordinates
+uniform vec3 macrocell_size;
+uniform bool skip_empty_space;
+// Only the part of the volume inside the crop box and on the kept side of
+// every clipping plane, where dot(plane.xyz, p) + plane.w <= 0, is drawn
+uniform vec3 crop_min;
+uniform vec3 crop_max;
+uniform vec4 clip_planes[MAX_CLIP_PLANES];
+uniform int num_clip_planes;
+// Colour and depth of the opaque meshes embedded in the volume, drawn at
+// the canvas' resolution
+uniform bool has_mesh;
+uniform highp sampler2D mesh_color;
+uniform highp sampler2D mesh_depth;
+// Maps clip space back to volume space to place the mesh depth along the ray
+uniform mat4 inv_proj_view;
+// Segment label of each voxel and the colour and opacity of each label, whose
+// opacity is scaled by label_blend
+uniform bool has_labels;
+uniform highp usampler3D labels;
+uniform highp sampler2D label_colors;
+uniform float label_blend;
+// Second volume fused with the first, with its own transfer function. Its
+// texture coordinates are secondary_inv_model * p for p in the first's.
+uniform bool has_secondary;
+uniform highp sampler3D secondary;
+uniform highp sampler2D secondary_colormap;
+uniform mat4 secondary_inv_model;
+// 0: alpha blend, 1: additive, 2: mask the first volume by the second
+uniform int fusion_mode;
+uniform float fusion_weight;
+
+// Normalised gradient magnitude above which a sample is fully shaded
+const float GRADIENT_SATURATION = 0.1;
+const float NO_MESH = 1e30;
 
 in vec3 vray_dir;
-flat in vec3 transformed_eye;
+in vec3 ray_origin;
 out vec4 color;
 
-vec2 intersect_box(vec3 orig, vec3 dir) {
-	const vec3 box_min = vec3(0);
-	const vec3 box_max = vec3(1);
+vec2 intersect_box(vec3 orig, vec3 dir, vec3 box_min, vec3 box_max) {
 	vec3 inv_dir = 1.0 / dir;
 	vec3 tmin_tmp = (box_min - orig) * inv_dir;
 	vec3 tmax_tmp = (box_max - orig) * inv_dir;
@@ -47,6 +113,28 @@ vec2 intersect_box(vec3 orig, vec3 dir) {
 	return vec2(t0, t1);
 }
 
+// Shrinks the ray interval to the kept side of each clipping plane
+vec2 clip_interval(vec3 orig, vec3 dir, vec2 t_hit) {
+	for (int i = 0; i < num_clip_planes; ++i) {
+		vec4 plane = clip_planes[i];
+		float start = dot(plane.xyz, orig) + plane.w;
+		float rate = dot(plane.xyz, dir);
+		if (rate == 0.0) {
+			if (start > 0.0) {
+				return vec2(1.0, 0.0);
+			}
+			continue;
+		}
+		float t_cross = -start / rate;
+		if (rate > 0.0) {
+			t_hit.y = min(t_hit.y, t_cross);
+		} else {
+			t_hit.x = max(t_hit.x, t_cross);
+		}
+	}
+	return t_hit;
+}
+
 // Pseudo-random number gen from
 // http://www.reedbeta.com/blog/quick-and-easy-gpu-random-numbers-in-d3d11/
 // with some tweaks for the range of values
@@ -66,32 +154,422 @@ float linear_to_srgb(float x) {
 	return 1.055f * pow(x, 1.f / 2.4f) - 0.055f;
 }
 
-void main(void) {
-	vec3 ray_dir = normalize(vray_dir);
-	vec2 t_hit = intersect_box(transformed_eye, ray_dir);
-	if (t_hit.x > t_hit.y) {
-		discard;
+// Central differences per voxel, pointing towards increasing density
+vec3 gradient(vec3 p) {
+	vec3 h = 1.0 / vec3(volume_dims);
+	return vec3(
+		texture(volume, p + vec3(h.x, 0, 0)).r - texture(volume, p - vec3(h.x, 0, 0)).r,
+		texture(volume, p + vec3(0, h.y, 0)).r - texture(volume, p - vec3(0, h.y, 0)).r,
+		texture(volume, p + vec3(0, 0, h.z)).r - texture(volume, p - vec3(0, 0, h.z)).r
+	) / 2.0;
+}
+
+// Gradient scaled so the largest gradient in the volume has length one
+vec3 normalised_gradient(vec3 p) {
+	if (precomputed_gradients) {
+		return texture(gradients, p).rgb * 2.0 - 1.0;
 	}
-	t_hit.x = max(t_hit.x, 0.0);
-	vec3 dt_vec = 1.0 / (vec3(volume_dims) * abs(ray_dir));
-	float dt = dt_scale * min(dt_vec.x, min(dt_vec.y, dt_vec.z));
-	float offset = wang_hash(int(gl_FragCoord.x + 640.0 * gl_FragCoord.y));
-	vec3 p = transformed_eye + (t_hit.x + offset * dt) * ray_dir;
+	return gradient(p) * gradient_scale;
+}
+
+// Colour of the label at p, with its blended opacity
+vec4 label_color(vec3 p) {
+	if (!has_labels) {
+		return vec4(0.0);
+	}
+	vec4 label = texelFetch(label_colors, ivec2(texture(labels, p).r, 0), 0);
+	return vec4(label.rgb, label.a * label_blend);
+}
+
+// Colour and opacity of the second volume at p, transparent outside it
+vec4 secondary_color(vec3 p) {
+	vec3 q = (secondary_inv_model * vec4(p, 1)).xyz;
+	if (any(lessThan(q, vec3(0))) || any(greaterThan(q, vec3(1)))) {
+		return vec4(0.0);
+	}
+	return texture(secondary_colormap, vec2(texture(secondary, q).r, 0.5));
+}
+
+// Combines a sample of the first volume with the second volume at p, matching
+// fusion::combine_samples
+vec4 fuse(vec4 first, vec3 p) {
+	vec4 second = secondary_color(p);
+	if (fusion_mode == 2) {
+		return vec4(first.rgb, first.a * (second.a > 0.0 ? 1.0 : 1.0 - fusion_weight));
+	}
+	float second_alpha = second.a * fusion_weight;
+	vec3 color;
+	float alpha;
+	if (fusion_mode == 0) {
+		alpha = second_alpha + first.a * (1.0 - second_alpha);
+		color = second.rgb * second_alpha + first.rgb * first.a * (1.0 - second_alpha);
+	} else {
+		alpha = min(first.a + second_alpha, 1.0);
+		color = first.rgb * first.a + second.rgb * second_alpha;
+	}
+	return alpha > 0.0 ? vec4(min(color / alpha, vec3(1)), alpha) : vec4(0.0);
+}
+
+vec3 blinn_phong(vec3 base_color, vec3 normal, vec3 ray_dir) {
+	vec3 to_eye = -ray_dir;
+	vec3 to_light = headlight ? to_eye : -normalize(light_dir);
+	// Shade both sides of the surface the same way
+	if (dot(normal, to_eye) < 0.0) {
+		normal = -normal;
+	}
+	vec3 half_vec = normalize(to_light + to_eye);
+	float diffuse = max(dot(normal, to_light), 0.0);
+	float specular = pow(max(dot(normal, half_vec), 0.0), 32.0);
+	return base_color * (0.2 + 0.7 * diffuse) + vec3(0.3 * specular);
+}
+
+vec4 march_isosurface(vec3 ray_dir, vec2 t_hit, float dt, float offset) {
+	float t = t_hit.x + offset * dt;
+	float prev_t = t;
+	float prev_val = texture(volume, ray_origin + t * ray_dir).r;
+	for (; t < t_hit.y; t += dt) {
+		float val = texture(volume, ray_origin + t * ray_dir).r;
+		if ((prev_val < iso_value) != (val < iso_value)) {
+			// Refine the crossing by bisecting the last step
+			float t_lo = prev_t;
+			float t_hi = t;
+			for (int i = 0; i < 6; ++i) {
+				float t_mid = 0.5 * (t_lo + t_hi);
+				float mid_val = texture(volume, ray_origin + t_mid * ray_dir).r;
+				if ((mid_val < iso_value) == (prev_val < iso_value)) {
+					t_lo = t_mid;
+				} else {
+					t_hi = t_mid;
+				}
+			}
+			vec3 hit = ray_origin + 0.5 * (t_lo + t_hi) * ray_dir;
+			vec3 grad = gradient(hit);
+			vec3 normal = length(grad) > 0.0 ? -normalize(grad) : -ray_dir;
+			vec3 base_color = texture(colormap, vec2(iso_value, 0.5)).rgb;
+			vec4 label = label_color(hit);
+			base_color = mix(base_color, label.rgb, label.a);
+			return vec4(blinn_phong(base_color, normal, ray_dir), 1.0);
+		}
+		prev_t = t;
+		prev_val = val;
+	}
+	return vec4(0.0);
+}
+
+// Distance along the ray from p to where it leaves the macrocell `cell`
+float macrocell_exit(vec3 p, vec3 ray_dir, ivec3 cell) {
+	vec3 cell_min = vec3(cell) * macrocell_size;
+	vec3 cell_max = cell_min + macrocell_size;
+	vec3 inv_dir = 1.0 / ray_dir;
+	vec3 t_far = max((cell_min - p) * inv_dir, (cell_max - p) * inv_dir);
+	return min(t_far.x, min(t_far.y, t_far.z));
+}
+
+vec4 march_volume(vec3 ray_dir, vec2 t_hit, float dt, float offset) {
+	vec4 color = vec4(0.0);
+	vec3 p = ray_origin + (t_hit.x + offset * dt) * ray_dir;
+	ivec3 last_cell = textureSize(occupancy, 0) - 1;
 	for (float t = t_hit.x; t < t_hit.y; t += dt) {
+		if (skip_empty_space) {
+			ivec3 cell = clamp(ivec3(p / macrocell_size), ivec3(0), last_cell);
+			if (texelFetch(occupancy, cell, 0).r == 0.0) {
+				// Jump whole steps so the remaining samples stay on the jittered grid
+				float skip = max(ceil(macrocell_exit(p, ray_dir, cell) / dt), 1.0) * dt;
+				t += skip - dt;
+				p += ray_dir * skip;
+				continue;
+			}
+		}
 		float val = texture(volume, p).r;
-		vec4 val_color = vec4(texture(colormap, vec2(val, 0.5)).rgb, val);
+		vec4 val_color = texture(colormap, vec2(val, 0.5));
+		if (has_secondary) {
+			val_color = fuse(val_color, p);
+		}
+		// Labelled voxels take on the label's colour and are at least as
+		// opaque as the label
+		vec4 label = label_color(p);
+		val_color.rgb = mix(val_color.rgb, label.rgb, label.a);
+		val_color.a = max(val_color.a, label.a);
+		if (dvr_shading) {
+			vec3 grad = normalised_gradient(p);
+			float magnitude = length(grad);
+			if (magnitude > 0.0) {
+				vec3 lit = blinn_phong(val_color.rgb, -grad / magnitude, ray_dir);
+				val_color.rgb = mix(val_color.rgb, lit, min(magnitude / GRADIENT_SATURATION, 1.0));
+			}
+		}
 		// Opacity correction
 		val_color.a = 1.0 - pow(1.0 - val_color.a, dt_scale);
 		color.rgb += (1.0 - color.a) * val_color.a * val_color.rgb;
 		color.a += (1.0 - color.a) * val_color.a;
-		if (color.a >= 0.95) {
+		if (color.a >= ert_threshold) {
 			break;
 		}
 		p += ray_dir * dt;
 	}
-    color.r = linear_to_srgb(color.r);
-    color.g = linear_to_srgb(color.g);
-    color.b = linear_to_srgb(color.b);
+	return color;
+}
+
+// Distance along the ray to the nearest mesh surface, or NO_MESH
+float mesh_distance(vec3 ray_dir) {
+	if (!has_mesh) {
+		return NO_MESH;
+	}
+	ivec2 texel = ivec2(gl_FragCoord.xy);
+	float depth = texelFetch(mesh_depth, texel, 0).r;
+	if (depth == 1.0) {
+		return NO_MESH;
+	}
+	vec2 ndc = (vec2(texel) + 0.5) / vec2(textureSize(mesh_depth, 0)) * 2.0 - 1.0;
+	vec4 surface = inv_proj_view * vec4(ndc, depth * 2.0 - 1.0, 1);
+	return dot(surface.xyz / surface.w - ray_origin, ray_dir);
+}
+
+void main(void) {
+	vec3 ray_dir = normalize(vray_dir);
+	vec2 t_hit = intersect_box(ray_origin, ray_dir, crop_min, crop_max);
+	t_hit = clip_interval(ray_origin, ray_dir, t_hit);
+	// Rays stop at the mesh, which then shows through what's in front of it
+	float t_mesh = mesh_distance(ray_dir);
+	vec4 surface = vec4(0);
+	if (t_mesh < NO_MESH) {
+		surface = texelFetch(mesh_color, ivec2(gl_FragCoord.xy), 0);
+		t_hit.y = min(t_hit.y, t_mesh);
+	}
+	t_hit.x = max(t_hit.x, 0.0);
+	if (t_hit.x > t_hit.y) {
+		if (surface.a == 0.0) {
+			discard;
+		}
+		t_hit.y = t_hit.x;
+	}
+	vec3 dt_vec = 1.0 / (vec3(volume_dims) * abs(ray_dir));
+	float dt = dt_scale * min(dt_vec.x, min(dt_vec.y, dt_vec.z));
+	int pixel = int(gl_FragCoord.x) + canvas_width * int(gl_FragCoord.y);
+	float offset = wang_hash(pixel ^ (frame_seed * 0x01000193));
+	if (render_mode == 1) {
+		color = march_isosurface(ray_dir, t_hit, dt, offset);
+	} else {
+		color = march_volume(ray_dir, t_hit, dt, offset);
+	}
+	color.rgb += (1.0 - color.a) * surface.rgb;
+	color.a += (1.0 - color.a) * surface.a;
+	if (output_srgb) {
+		color.r = linear_to_srgb(color.r);
+		color.g = linear_to_srgb(color.g);
+		color.b = linear_to_srgb(color.b);
+	}
+}"#,
+);
+
+/// Opaque meshes, lit from the eye on both sides as STL winding is unreliable
+pub const MESH_VERT_SHADER: VertexShader = VertexShader(
+    r#"#version 300 es
+layout(location=0) in vec3 pos;
+layout(location=1) in vec3 normal;
+uniform mat4 proj_view;
+uniform mat4 model;
+
+out vec3 volume_pos;
+out vec3 volume_normal;
+
+void main(void) {
+	vec4 position = model * vec4(pos, 1);
+	volume_pos = position.xyz;
+	volume_normal = transpose(inverse(mat3(model))) * normal;
+	gl_Position = proj_view * position;
+}"#,
+);
+
+pub const MESH_FRAG_SHADER: FragmentShader = FragmentShader(
+    r#"#version 300 es
+precision highp float;
+uniform vec3 eye_pos;
+uniform vec3 mesh_color;
+
+in vec3 volume_pos;
+in vec3 volume_normal;
+out vec4 color;
+
+void main(void) {
+	vec3 to_eye = normalize(eye_pos - volume_pos);
+	float diffuse = abs(dot(normalize(volume_normal), to_eye));
+	color = vec4(mesh_color * (0.2 + 0.8 * diffuse), 1);
+}"#,
+);
+
+/// Full screen triangle for presenting the accumulated image
+pub const PRESENT_VERT_SHADER: VertexShader = VertexShader(
+    r#"#version 300 es
+out vec2 uv;
+
+void main(void) {
+	vec2 corner = vec2(float((gl_VertexID << 1) & 2), float(gl_VertexID & 2));
+	uv = corner;
+	gl_Position = vec4(corner * 2.0 - 1.0, 0, 1);
+}"#,
+);
+
+pub const PRESENT_FRAG_SHADER: FragmentShader = FragmentShader(
+    r#"#version 300 es
+precision highp float;
+uniform highp sampler2D accumulation;
+
+in vec2 uv;
+out vec4 color;
+
+float linear_to_srgb(float x) {
+	if (x <= 0.0031308f) {
+		return 12.92f * x;
+	}
+	return 1.055f * pow(x, 1.f / 2.4f) - 0.055f;
+}
+
+void main(void) {
+	color = texture(accumulation, uv);
+	color.r = linear_to_srgb(color.r);
+	color.g = linear_to_srgb(color.g);
+	color.b = linear_to_srgb(color.b);
+}"#,
+);
+
+/// Points marking the centres of the crop box faces
+pub const HANDLE_VERT_SHADER: VertexShader = VertexShader(
+    r#"#version 300 es
+uniform mat4 proj_view;
+uniform vec3 handles[6];
+uniform int active_handle;
+
+flat out int is_active;
+
+void main(void) {
+	gl_Position = proj_view * vec4(handles[gl_VertexID], 1);
+	gl_PointSize = 14.0;
+	is_active = int(gl_VertexID == active_handle);
+}"#,
+);
+
+pub const HANDLE_FRAG_SHADER: FragmentShader = FragmentShader(
+    r#"#version 300 es
+precision highp float;
+
+flat in int is_active;
+out vec4 color;
+
+void main(void) {
+	vec2 from_center = gl_PointCoord - vec2(0.5);
+	if (dot(from_center, from_center) > 0.25) {
+		discard;
+	}
+	color = is_active == 1 ? vec4(1.0, 0.6, 0.0, 1.0) : vec4(0.1, 0.4, 1.0, 1.0);
+}"#,
+);
+
+/// One of the three orthogonal slices, outlined and faintly filled in the 3D view
+pub const SLICE_PLANE_VERT_SHADER: VertexShader = VertexShader(
+    r#"#version 300 es
+uniform mat4 proj_view;
+uniform vec3 cursor;
+uniform ivec2 plane_axes;
+
+void main(void) {
+	// Corners of the unit square in loop order
+	vec2 corner = vec2(gl_VertexID == 1 || gl_VertexID == 2, gl_VertexID >= 2);
+	vec3 position = cursor;
+	position[plane_axes.x] = corner.x;
+	position[plane_axes.y] = corner.y;
+	gl_Position = proj_view * vec4(position, 1);
+}"#,
+);
+
+pub const SLICE_PLANE_FRAG_SHADER: FragmentShader = FragmentShader(
+    r#"#version 300 es
+precision highp float;
+
+uniform vec4 plane_color;
+out vec4 color;
+
+void main(void) {
+	color = plane_color;
+}"#,
+);
+
+/// Fullscreen triangle for the 2D slice views, with `uv` running from (0, 0)
+/// at the bottom left to (1, 1) at the top right
+pub const SLICE_VERT_SHADER: VertexShader = VertexShader(
+    r#"#version 300 es
+out vec2 uv;
+
+void main(void) {
+	vec2 corner = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+	uv = corner;
+	gl_Position = vec4(corner * 2.0 - 1.0, 0, 1);
+}"#,
+);
+
+pub const SLICE_FRAG_SHADER: FragmentShader = FragmentShader(
+    r#"#version 300 es
+precision highp float;
+
+uniform highp sampler3D volume;
+uniform highp sampler2D colormap;
+// Opacity window of the transfer function, in normalised data units
+uniform vec2 window;
+// Brightens the colormap so its brightest entry is white
+uniform float color_scale;
+// The slice is origin + uv.x * axis_u + uv.y * axis_v in volume coordinates
+uniform vec3 slice_origin;
+uniform vec3 slice_u;
+uniform vec3 slice_v;
+// Samples across the slab, spaced slab_step apart and centred on the slice,
+// are combined by maximum (0) or average (1)
+uniform vec3 slab_step;
+uniform int slab_samples;
+uniform int slab_mode;
+uniform vec2 view_size;
+// Crosshair position in uv and the colours of its vertical and horizontal line
+uniform vec2 crosshair;
+uniform vec3 crosshair_colors[2];
+// Label overlay as in the 3D view, taken from the slice itself
+uniform bool has_labels;
+uniform highp usampler3D labels;
+uniform highp sampler2D label_colors;
+uniform float label_blend;
+
+in vec2 uv;
+out vec4 color;
+
+void main(void) {
+	vec3 center = slice_origin + uv.x * slice_u + uv.y * slice_v;
+	float value = 0.0;
+	for (int i = 0; i < slab_samples; ++i) {
+		vec3 p = center + (float(i) - float(slab_samples - 1) * 0.5) * slab_step;
+		if (any(lessThan(p, vec3(0))) || any(greaterThan(p, vec3(1)))) {
+			continue;
+		}
+		float sample_value = texture(volume, p).r;
+		value = slab_mode == 0 ? max(value, sample_value) : value + sample_value;
+	}
+	if (slab_mode == 1) {
+		value /= float(max(slab_samples, 1));
+	}
+	float t = window.y > window.x
+		? clamp((value - window.x) / (window.y - window.x), 0.0, 1.0)
+		: step(window.x, value);
+	// Sample the centre of the colormap's texels
+	float lookup = (t * 255.0 + 0.5) / 256.0;
+	vec3 c = min(texture(colormap, vec2(lookup, 0.5)).rgb * color_scale, vec3(1));
+	if (has_labels && all(greaterThanEqual(center, vec3(0))) && all(lessThanEqual(center, vec3(1)))) {
+		vec4 label = texelFetch(label_colors, ivec2(texture(labels, center).r, 0), 0);
+		c = mix(c, label.rgb, label.a * label_blend);
+	}
+	vec2 from_crosshair = abs(uv - crosshair) * view_size;
+	if (from_crosshair.x < 0.75) {
+		c = crosshair_colors[0];
+	} else if (from_crosshair.y < 0.75) {
+		c = crosshair_colors[1];
+	}
+	color = vec4(c, 1);
 }"#,
 );
 
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use web_sys::WebGl2RenderingContext as WebGl;
+use web_sys::*;
+
+use super::gl_utils::GlUtils;
+use super::shaders::{SLICE_PLANE_FRAG_SHADER, SLICE_PLANE_VERT_SHADER};
+use crate::slices::{SliceAxis, SliceCursor};
+
+/// Opacity of the plane's fill, its outline is opaque
+const PLANE_FILL_ALPHA: f32 = 0.12;
+
+/// Draws the planes of the three slice views through the volume
+pub(crate) struct SlicePlaneOverlay {
+    program: WebGlProgram,
+    proj_view: WebGlUniformLocation,
+    cursor: WebGlUniformLocation,
+    plane_axes: WebGlUniformLocation,
+    plane_color: WebGlUniformLocation,
+}
+
+impl SlicePlaneOverlay {
+    pub(crate) fn new(gl: &WebGl) -> Result<Self> {
+        let program = gl.link_program_from(&SLICE_PLANE_VERT_SHADER, &SLICE_PLANE_FRAG_SHADER)?;
+        Ok(Self {
+            proj_view: gl.get_unif_loc(&program, "proj_view")?,
+            cursor: gl.get_unif_loc(&program, "cursor")?,
+            plane_axes: gl.get_unif_loc(&program, "plane_axes")?,
+            plane_color: gl.get_unif_loc(&program, "plane_color")?,
+            program,
+        })
+    }
+
+    /// Draws onto whatever framebuffer is bound
+    pub(crate) fn draw(&self, gl: &WebGl, proj_view: &[f32; 16], cursor: &SliceCursor) {
+        gl.use_program(Some(&self.program));
+        gl.uniform_matrix4fv_with_f32_array(Some(&self.proj_view), false, proj_view);
+        gl.uniform3fv_with_f32_array(Some(&self.cursor), &cursor.position);
+        gl.blend_func(WebGl::SRC_ALPHA, WebGl::ONE_MINUS_SRC_ALPHA);
+        gl.disable(WebGl::CULL_FACE);
+        for axis in SliceAxis::ALL {
+            let [u, v] = axis.plane_axes();
+            let [r, g, b] = axis.color();
+            gl.uniform2i(Some(&self.plane_axes), u as i32, v as i32);
+            gl.uniform4f(Some(&self.plane_color), r, g, b, PLANE_FILL_ALPHA);
+            gl.draw_arrays(WebGl::TRIANGLE_FAN, 0, 4);
+            gl.uniform4f(Some(&self.plane_color), r, g, b, 1.0);
+            gl.draw_arrays(WebGl::LINE_LOOP, 0, 4);
+        }
+        gl.enable(WebGl::CULL_FACE);
+        gl.blend_func(WebGl::ONE, WebGl::ONE_MINUS_SRC_ALPHA);
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::WebGl2RenderingContext as WebGl;
+use web_sys::*;
+
+use super::capture_target::CaptureTarget;
+use super::gl_utils::GlUtils;
+use super::shaders::{SLICE_FRAG_SHADER, SLICE_VERT_SHADER};
+use super::{Error, Volumetric3DTextures, LABELS_TEXTURE_UNIT, LABEL_COLORS_TEXTURE_UNIT};
+use crate::app_state::{get_slice_data, take_slice_update, AppState};
+use crate::image::RgbaImage;
+use crate::reslice::SlabMode;
+use crate::slices::{SliceAxis, SliceData, SliceOrientation};
+use crate::SharedMut;
+
+/// Colour of the crosshair in the oblique view, which marks the cursor
+const OBLIQUE_CROSSHAIR_COLOR: [f32; 3] = [1.0, 1.0, 0.6];
+
+/// Canvas element id of the view showing slices in `orientation`
+pub fn slice_canvas_id(orientation: SliceOrientation) -> String {
+    format!("slice-{}-canvas", orientation.id())
+}
+
+/// The axial, coronal, sagittal and oblique views. They're drawn by the 3D
+/// view's context from the volume, colormap and label textures it already
+/// has bound, into an offscreen framebuffer that's copied to each view's
+/// canvas, so nothing is uploaded twice.
+pub(crate) struct SliceViews {
+    program: WebGlProgram,
+    window: WebGlUniformLocation,
+    color_scale: WebGlUniformLocation,
+    slice_origin: WebGlUniformLocation,
+    slice_u: WebGlUniformLocation,
+    slice_v: WebGlUniformLocation,
+    slab_step: WebGlUniformLocation,
+    slab_samples: WebGlUniformLocation,
+    slab_mode: WebGlUniformLocation,
+    view_size: WebGlUniformLocation,
+    crosshair: WebGlUniformLocation,
+    crosshair_colors: WebGlUniformLocation,
+    has_labels: WebGlUniformLocation,
+    label_blend: WebGlUniformLocation,
+    views: Vec<SliceCanvas>,
+    /// Framebuffer the views are drawn into, with its size
+    target: Option<(CaptureTarget, i32, i32)>,
+    /// Generations of the transfer function and labels in the textures when
+    /// the views were last drawn
+    drawn_generations: Option<(u64, u64)>,
+}
+
+/// A slice view's canvas, which only ever has pixels copied into it
+struct SliceCanvas {
+    orientation: SliceOrientation,
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+}
+
+impl SliceViews {
+    /// Sets up a view for each slice canvas found in the document
+    pub(crate) fn new(gl: &WebGl) -> Result<Self> {
+        let document = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or(Error::Missing)
+            .context("No document to find slice canvases in")?;
+        let views = SliceOrientation::ALL
+            .iter()
+            .filter_map(|orientation| {
+                let canvas = document.get_element_by_id(&slice_canvas_id(*orientation))?;
+                Some((*orientation, canvas))
+            })
+            .map(|(orientation, canvas)| {
+                let canvas = canvas
+                    .dyn_into::<HtmlCanvasElement>()
+                    .map_err(|_| Error::Message("Slice view element is not a canvas".into()))?;
+                let context = canvas
+                    .get_context("2d")
+                    .map_err(|_| Error::Missing)
+                    .context("Failed to get 2d context for slice view")?
+                    .ok_or(Error::Missing)
+                    .context("No 2d context for slice view")?
+                    .dyn_into::<CanvasRenderingContext2d>()
+                    .map_err(|_| Error::Message("Slice view context is not 2d".into()))?;
+                Ok(SliceCanvas {
+                    orientation,
+                    canvas,
+                    context,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let program = gl.link_program_from(&SLICE_VERT_SHADER, &SLICE_FRAG_SHADER)?;
+        gl.use_program(Some(&program));
+        gl.uniform1i(Some(&gl.get_unif_loc(&program, "volume")?), 0);
+        gl.uniform1i(Some(&gl.get_unif_loc(&program, "colormap")?), 1);
+        gl.uniform1i(
+            Some(&gl.get_unif_loc(&program, "labels")?),
+            (LABELS_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+        );
+        gl.uniform1i(
+            Some(&gl.get_unif_loc(&program, "label_colors")?),
+            (LABEL_COLORS_TEXTURE_UNIT - WebGl::TEXTURE0) as i32,
+        );
+        Ok(Self {
+            window: gl.get_unif_loc(&program, "window")?,
+            color_scale: gl.get_unif_loc(&program, "color_scale")?,
+            slice_origin: gl.get_unif_loc(&program, "slice_origin")?,
+            slice_u: gl.get_unif_loc(&program, "slice_u")?,
+            slice_v: gl.get_unif_loc(&program, "slice_v")?,
+            slab_step: gl.get_unif_loc(&program, "slab_step")?,
+            slab_samples: gl.get_unif_loc(&program, "slab_samples")?,
+            slab_mode: gl.get_unif_loc(&program, "slab_mode")?,
+            view_size: gl.get_unif_loc(&program, "view_size")?,
+            crosshair: gl.get_unif_loc(&program, "crosshair")?,
+            crosshair_colors: gl.get_unif_loc(&program, "crosshair_colors")?,
+            has_labels: gl.get_unif_loc(&program, "has_labels")?,
+            label_blend: gl.get_unif_loc(&program, "label_blend")?,
+            program,
+            views,
+            target: None,
+            drawn_generations: None,
+        })
+    }
+
+    /// Redraws the views if the slices moved or the transfer function or
+    /// labels in `textures` changed since they were last drawn
+    pub(crate) fn render_from_state(
+        &mut self,
+        gl: &WebGl,
+        app_state: &SharedMut<AppState>,
+        textures: &Volumetric3DTextures,
+    ) -> Result<()> {
+        let generations = (
+            textures.transfer_function_generation,
+            textures.labels_generation,
+        );
+        let slice_data = match take_slice_update(app_state)? {
+            Some(slice_data) => slice_data,
+            None if self.drawn_generations != Some(generations) => get_slice_data(app_state)?,
+            None => return Ok(()),
+        };
+        let target = self.fit_target(gl)?;
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, Some(&target));
+        gl.disable(WebGl::BLEND);
+        gl.disable(WebGl::CULL_FACE);
+        gl.use_program(Some(&self.program));
+        let (low, high) = textures.window;
+        gl.uniform2f(Some(&self.window), low / 255.0, high / 255.0);
+        gl.uniform1f(Some(&self.color_scale), textures.color_scale);
+        gl.uniform1i(Some(&self.has_labels), textures.labels.is_some() as i32);
+        gl.uniform1f(Some(&self.label_blend), textures.label_blend);
+        let result = self
+            .views
+            .iter()
+            .try_for_each(|view| self.render_view(gl, view, &slice_data));
+        gl.enable(WebGl::BLEND);
+        gl.enable(WebGl::CULL_FACE);
+        gl.bind_framebuffer(WebGl::FRAMEBUFFER, None);
+        result?;
+        self.drawn_generations = Some(generations);
+        Ok(())
+    }
+
+    /// The framebuffer, grown to fit the largest view
+    fn fit_target(&mut self, gl: &WebGl) -> Result<WebGlFramebuffer> {
+        let (width, height) = self.views.iter().fold((1, 1), |(width, height), view| {
+            (
+                width.max(view.canvas.width() as i32),
+                height.max(view.canvas.height() as i32),
+            )
+        });
+        if let Some((target, target_width, target_height)) = &self.target {
+            if *target_width >= width && *target_height >= height {
+                return Ok(target.framebuffer().clone());
+            }
+        }
+        if let Some((target, _, _)) = self.target.take() {
+            target.delete(gl);
+        }
+        let target = CaptureTarget::new(gl, width, height)?;
+        let framebuffer = target.framebuffer().clone();
+        self.target = Some((target, width, height));
+        Ok(framebuffer)
+    }
+
+    fn render_view(&self, gl: &WebGl, view: &SliceCanvas, slice_data: &SliceData) -> Result<()> {
+        let geometry = slice_data.geometry(view.orientation);
+        let (crosshair, crosshair_colors) = match view.orientation {
+            SliceOrientation::Orthogonal(axis) => (
+                axis.view_uv(slice_data.cursor.position),
+                axis.plane_axes()
+                    .map(|axis| SliceAxis::for_normal_axis(axis).color()),
+            ),
+            // The oblique plane is centred on the cursor
+            SliceOrientation::Oblique => ([0.5, 0.5], [OBLIQUE_CROSSHAIR_COLOR; 2]),
+        };
+        let slab_mode = match geometry.slab_mode {
+            SlabMode::Mip => 0,
+            SlabMode::Average => 1,
+        };
+        let (width, height) = (view.canvas.width() as i32, view.canvas.height() as i32);
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        gl.viewport(0, 0, width, height);
+        gl.uniform3fv_with_f32_array(Some(&self.slice_origin), &geometry.origin);
+        gl.uniform3fv_with_f32_array(Some(&self.slice_u), &geometry.u);
+        gl.uniform3fv_with_f32_array(Some(&self.slice_v), &geometry.v);
+        gl.uniform3fv_with_f32_array(Some(&self.slab_step), &geometry.slab_step);
+        gl.uniform1i(Some(&self.slab_samples), geometry.slab_samples as i32);
+        gl.uniform1i(Some(&self.slab_mode), slab_mode);
+        gl.uniform2f(Some(&self.view_size), width as f32, height as f32);
+        gl.uniform2fv_with_f32_array(Some(&self.crosshair), &crosshair);
+        gl.uniform3fv_with_f32_array(
+            Some(&self.crosshair_colors),
+            crosshair_colors.as_flattened(),
+        );
+        gl.draw_arrays(WebGl::TRIANGLES, 0, 3);
+
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        gl.read_pixels_with_opt_u8_array(
+            0,
+            0,
+            width,
+            height,
+            WebGl::RGBA,
+            WebGl::UNSIGNED_BYTE,
+            Some(&mut pixels),
+        )
+        .map_err(|_| Error::Message("Js".into()))
+        .context("Failed to read back slice view")?;
+        let image = RgbaImage::from_bottom_up(width as usize, height as usize, pixels);
+        let image_data = ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(&image.data),
+            width as u32,
+            height as u32,
+        )
+        .map_err(|_| Error::Message("Js".into()))
+        .context("Failed to create slice view image")?;
+        view.context
+            .put_image_data(&image_data, 0.0, 0.0)
+            .map_err(|_| Error::Message("Js".into()))
+            .context("Failed to copy slice view to its canvas")?;
+        Ok(())
+    }
+}
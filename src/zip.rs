@@ -0,0 +1,146 @@
+//! Writes uncompressed ZIP archives, enough to bundle already compressed
+//! files such as PNGs for a single download. There's no ZIP64 support, so
+//! archives are limited to 65535 files and 4 GiB.
+
+use crate::Error;
+
+/// Most files an archive can hold
+pub const MAX_ENTRIES: usize = u16::MAX as usize;
+
+/// Files to be stored in an archive, in order
+#[derive(Default)]
+pub struct ZipWriter {
+    data: Vec<u8>,
+    central_directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails if the archive can't hold another file of this size
+    pub fn add_file(&mut self, name: &str, contents: &[u8]) -> Result<(), Error> {
+        if self.entries as usize >= MAX_ENTRIES {
+            return Err(Error::ArchiveTooLarge(format!(
+                "more than {MAX_ENTRIES} files"
+            )));
+        }
+        let name = name.as_bytes();
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| Error::ArchiveTooLarge(format!("file name of {} bytes", name.len())))?;
+        let offset = to_u32(self.data.len())?;
+        let size = to_u32(contents.len())?;
+        // The file has to end where the next one's offset can still be written
+        to_u32(self.data.len() + 30 + name.len() + contents.len())?;
+        let crc = crc32fast::hash(contents);
+
+        // Local file header
+        self.data.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        self.data.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        write_fields(&mut self.data, crc, size, name_len);
+        self.data.extend_from_slice(name);
+        self.data.extend_from_slice(contents);
+
+        // Central directory record
+        let record = &mut self.central_directory;
+        record.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        record.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        record.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        write_fields(record, crc, size, name_len);
+        record.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        record.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        record.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        record.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        record.extend_from_slice(&offset.to_le_bytes());
+        record.extend_from_slice(name);
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Fails if the central directory would end past 4 GiB
+    pub fn finish(mut self) -> Result<Vec<u8>, Error> {
+        let directory_offset = to_u32(self.data.len())?;
+        let directory_size = to_u32(self.central_directory.len())?;
+        to_u32(self.data.len() + self.central_directory.len())?;
+        self.data.extend_from_slice(&self.central_directory);
+        self.data.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // this disk
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // directory disk
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&directory_size.to_le_bytes());
+        self.data.extend_from_slice(&directory_offset.to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        Ok(self.data)
+    }
+}
+
+/// Sizes and offsets are 32 bit without ZIP64
+fn to_u32(value: usize) -> Result<u32, Error> {
+    u32::try_from(value).map_err(|_| Error::ArchiveTooLarge(format!("{value} bytes")))
+}
+
+/// Fields shared by local headers and central directory records, from the
+/// flags to the extra field length
+fn write_fields(out: &mut Vec<u8>, crc: u32, size: u32, name_len: u16) {
+    out.extend_from_slice(&0u16.to_le_bytes()); // flags
+    out.extend_from_slice(&0u16.to_le_bytes()); // stored, no compression
+    out.extend_from_slice(&0u16.to_le_bytes()); // modification time
+    out.extend_from_slice(&0x21u16.to_le_bytes()); // modification date, 1980-01-01
+    out.extend_from_slice(&crc.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes()); // compressed size
+    out.extend_from_slice(&size.to_le_bytes()); // uncompressed size
+    out.extend_from_slice(&name_len.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ZipWriter, MAX_ENTRIES};
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_archive_layout() {
+        let mut zip = ZipWriter::new();
+        zip.add_file("a.txt", b"hello").unwrap();
+        zip.add_file("b.txt", b"world!").unwrap();
+        let data = zip.finish().unwrap();
+
+        // The end record points at the central directory, which points at
+        // each local header
+        let end = data.len() - 22;
+        assert_eq!(u32_at(&data, end), 0x06054b50);
+        assert_eq!(u16_at(&data, end + 10), 2);
+        let mut record = u32_at(&data, end + 16) as usize;
+        for (name, contents) in [("a.txt", &b"hello"[..]), ("b.txt", &b"world!"[..])] {
+            assert_eq!(u32_at(&data, record), 0x02014b50);
+            assert_eq!(u32_at(&data, record + 16), crc32fast::hash(contents));
+            let name_len = u16_at(&data, record + 28) as usize;
+            assert_eq!(&data[record + 46..record + 46 + name_len], name.as_bytes());
+            let local = u32_at(&data, record + 42) as usize;
+            assert_eq!(u32_at(&data, local), 0x04034b50);
+            let start = local + 30 + name_len;
+            assert_eq!(&data[start..start + contents.len()], contents);
+            record += 46 + name_len;
+        }
+    }
+
+    #[test]
+    fn test_too_many_files() {
+        let mut zip = ZipWriter::new();
+        for index in 0..MAX_ENTRIES {
+            zip.add_file(&index.to_string(), b"").unwrap();
+        }
+        assert!(zip.add_file("one too many", b"").is_err());
+        assert!(zip.finish().is_ok());
+    }
+}
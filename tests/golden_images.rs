@@ -0,0 +1,263 @@
+//! Renders synthetic volumes with the CPU ray marcher, which mirrors the
+//! fragment shader, and compares them against the reference images in
+//! `tests/golden`. Failing cases write the actual image and a diff to
+//! `target/golden-diff`. Run with `UPDATE_GOLDEN=1` to accept the current
+//! output as the new references.
+
+use std::path::{Path, PathBuf};
+
+use cgmath::{Deg, Quaternion, Rotation3, Vector3};
+use volumetric_renderer::app_state::{RenderMode, RenderSettings};
+use volumetric_renderer::camera::CameraPose;
+use volumetric_renderer::capture::CaptureCamera;
+use volumetric_renderer::cpu_render::CpuRenderer;
+use volumetric_renderer::image::RgbaImage;
+use volumetric_renderer::projection::ProjectionMode;
+use volumetric_renderer::transfer_function::TransferFunction;
+use volumetric_renderer::volume::Volume;
+
+const SIZE: usize = 32;
+const IMAGE_SIZE: u32 = 64;
+const SAMPLES: u32 = 4;
+/// Largest channel difference, out of 255, a pixel may have before it
+/// counts as changed
+const PIXEL_TOLERANCE: u8 = 12;
+/// Share of pixels allowed to change, for differences in floating point
+/// maths between platforms along silhouettes
+const MAX_CHANGED: f32 = 0.005;
+/// Largest mean channel difference over the whole image, catching slight
+/// shifts in colour that stay under `PIXEL_TOLERANCE`
+const MAX_MEAN_DIFFERENCE: f32 = 1.0;
+
+fn volume(value: impl Fn([f32; 3]) -> f32) -> Volume {
+    let data = (0..SIZE * SIZE * SIZE)
+        .map(|i| {
+            let voxel = [i % SIZE, i / SIZE % SIZE, i / (SIZE * SIZE)];
+            let centre = voxel.map(|v| (v as f32 + 0.5) / SIZE as f32);
+            value(centre).clamp(0.0, 255.0).round() as u8
+        })
+        .collect();
+    Volume::new([SIZE; 3], data).unwrap()
+}
+
+fn sphere() -> Volume {
+    volume(|[x, y, z]| {
+        let distance = ((x - 0.5).powi(2) + (y - 0.5).powi(2) + (z - 0.5).powi(2)).sqrt();
+        255.0 * (1.0 - distance / 0.45)
+    })
+}
+
+fn gradient_cube() -> Volume {
+    volume(|[x, _, _]| 255.0 * x)
+}
+
+fn checkerboard() -> Volume {
+    volume(|point| {
+        let parity: usize = point.iter().map(|c| (c * 4.0) as usize).sum();
+        match parity % 2 {
+            0 => 40.0,
+            _ => 220.0,
+        }
+    })
+}
+
+fn camera(rotation: Quaternion<f32>, projection: ProjectionMode) -> CaptureCamera {
+    CaptureCamera {
+        pose: CameraPose {
+            center: Vector3::new(0.5, 0.5, 0.5),
+            rotation,
+            distance: 2.2,
+        },
+        projection,
+        fov: 45.0,
+        near: 0.1,
+        far: 10.0,
+    }
+}
+
+fn oblique() -> Quaternion<f32> {
+    Quaternion::from_angle_x(Deg(25.0)) * Quaternion::from_angle_y(Deg(-35.0))
+}
+
+fn render(
+    volume: &Volume,
+    transfer_function: &TransferFunction,
+    settings: &RenderSettings,
+    camera: &CaptureCamera,
+) -> RgbaImage {
+    let renderer = CpuRenderer::new(volume, transfer_function, settings);
+    renderer.render(
+        &camera.draw_data(IMAGE_SIZE, IMAGE_SIZE),
+        IMAGE_SIZE as usize,
+        IMAGE_SIZE as usize,
+        SAMPLES,
+        [1.0; 4],
+    )
+}
+
+fn read_png(path: &Path) -> Option<RgbaImage> {
+    let decoder = png::Decoder::new(std::fs::File::open(path).ok()?);
+    let mut reader = decoder.read_info().ok()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).ok()?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return None;
+    }
+    data.truncate(info.buffer_size());
+    Some(RgbaImage {
+        width: info.width as usize,
+        height: info.height as usize,
+        data,
+    })
+}
+
+fn write_png(path: &Path, image: &RgbaImage) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, image.to_png().unwrap()).unwrap();
+}
+
+/// How far apart two images of the same size are
+struct Difference {
+    /// Share of pixels with a channel more than `PIXEL_TOLERANCE` apart
+    changed: f32,
+    mean: f32,
+    /// Changed pixels in red over a faded copy of `expected`
+    image: RgbaImage,
+}
+
+fn difference(expected: &RgbaImage, actual: &RgbaImage) -> Difference {
+    let mut changed = 0;
+    let mut total = 0;
+    let mut data = Vec::with_capacity(expected.data.len());
+    for (expected, actual) in expected.data.chunks(4).zip(actual.data.chunks(4)) {
+        let deltas: Vec<u8> = expected
+            .iter()
+            .zip(actual)
+            .map(|(e, a)| e.abs_diff(*a))
+            .collect();
+        total += deltas.iter().map(|d| *d as u32).sum::<u32>();
+        let delta = *deltas.iter().max().unwrap();
+        match delta > PIXEL_TOLERANCE {
+            true => {
+                changed += 1;
+                data.extend([255, 0, 0, 255]);
+            }
+            false => data.extend(expected[..3].iter().map(|c| 192 + c / 4).chain([255])),
+        }
+    }
+    let pixels = (expected.width * expected.height).max(1) as f32;
+    Difference {
+        changed: changed as f32 / pixels,
+        mean: total as f32 / (pixels * 4.0),
+        image: RgbaImage {
+            width: expected.width,
+            height: expected.height,
+            data,
+        },
+    }
+}
+
+fn check_golden(name: &str, actual: &RgbaImage) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let reference = root.join("tests/golden").join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        write_png(&reference, actual);
+        return;
+    }
+    let expected = read_png(&reference).unwrap_or_else(|| {
+        panic!(
+            "Missing reference {}, run with UPDATE_GOLDEN=1 to create it",
+            reference.display()
+        )
+    });
+    let diff_dir = root.join("target/golden-diff");
+    let same_size = (expected.width, expected.height) == (actual.width, actual.height);
+    let difference = same_size.then(|| difference(&expected, actual));
+    let passed = matches!(&difference, Some(difference)
+        if difference.changed <= MAX_CHANGED && difference.mean <= MAX_MEAN_DIFFERENCE);
+    if passed {
+        return;
+    }
+    write_png(&diff_dir.join(format!("{name}_actual.png")), actual);
+    match difference {
+        Some(difference) => {
+            write_png(
+                &diff_dir.join(format!("{name}_diff.png")),
+                &difference.image,
+            );
+            panic!(
+                "{name} differs from its reference: {:.2}% of pixels changed, mean difference {:.2}. \
+                 See {}",
+                difference.changed * 100.0,
+                difference.mean,
+                diff_dir.display()
+            );
+        }
+        None => panic!(
+            "{name} is {}x{} but its reference is {}x{}",
+            actual.width, actual.height, expected.width, expected.height
+        ),
+    }
+}
+
+#[test]
+fn golden_sphere_isosurface() {
+    let settings = RenderSettings {
+        mode: RenderMode::Isosurface,
+        iso_value: 100.0,
+        ..Default::default()
+    };
+    let camera = camera(oblique(), ProjectionMode::Perspective);
+    let image = render(&sphere(), &TransferFunction::hot(), &settings, &camera);
+    check_golden("sphere_isosurface", &image);
+}
+
+#[test]
+fn golden_gradient_cube_dvr() {
+    let transfer_function = TransferFunction {
+        window: (0.0, 255.0),
+        max_opacity: 0.3,
+        ..TransferFunction::hot()
+    };
+    let camera = camera(oblique(), ProjectionMode::Perspective);
+    let image = render(
+        &gradient_cube(),
+        &transfer_function,
+        &RenderSettings::default(),
+        &camera,
+    );
+    check_golden("gradient_cube_dvr", &image);
+}
+
+#[test]
+fn golden_checkerboard_shaded() {
+    let settings = RenderSettings {
+        dvr_shading: true,
+        ..Default::default()
+    };
+    let transfer_function = TransferFunction {
+        window: (100.0, 220.0),
+        max_opacity: 0.5,
+        ..TransferFunction::default()
+    };
+    let rotation = Quaternion::from_angle_x(Deg(30.0)) * Quaternion::from_angle_y(Deg(45.0));
+    let camera = camera(rotation, ProjectionMode::Orthographic);
+    let image = render(&checkerboard(), &transfer_function, &settings, &camera);
+    check_golden("checkerboard_shaded", &image);
+}
+
+#[test]
+fn difference_flags_changed_pixels() {
+    let expected = RgbaImage {
+        width: 2,
+        height: 1,
+        data: vec![10, 10, 10, 255, 200, 200, 200, 255],
+    };
+    let actual = RgbaImage {
+        data: vec![15, 10, 10, 255, 100, 200, 200, 255],
+        ..expected.clone()
+    };
+    let difference = difference(&expected, &actual);
+    assert_eq!(difference.changed, 0.5);
+    assert_eq!(&difference.image.data[4..], &[255, 0, 0, 255]);
+}